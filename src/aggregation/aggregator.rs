@@ -1,20 +1,46 @@
+use crate::aggregation::arbitrage::{self, Opportunity};
+use crate::aggregation::execution::{self, Amount, Execution};
 use crate::aggregation::quote_merge::MergeQuotes;
+use crate::aggregation::routing::{self, ParentOrder, RoutePlan};
+use crate::common::clock::{Clock, SystemClock};
 use crate::common::model::OrderBookUpdate;
-use crate::common::model::{AggregatedBookQuote, ExchangeQuote};
-use crate::orderbook::{Level, Summary};
-use std::collections::HashMap;
-use tracing::error;
+use crate::common::model::{AggregatedBookQuote, ExchangeQuote, Instrument, Price, Qty, Side};
+use crate::orderbook::book::L2Book;
+use crate::orderbook::venue_status::State;
+use crate::orderbook::{ConsolidatedLevel, Level, Summary, VenueStatus};
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
 
 pub struct OrderBookAggregator<T: MergeQuotes> {
+    exchanges_books: Vec<L2Book>,
     exchanges_bids: Vec<Vec<ExchangeQuote>>,
     exchanges_asks: Vec<Vec<ExchangeQuote>>,
     bid_book_top: Vec<AggregatedBookQuote>,
     ask_book_top: Vec<AggregatedBookQuote>,
+    consolidated_bids: Vec<ConsolidatedLevel>,
+    consolidated_asks: Vec<ConsolidatedLevel>,
 
-    exchanges_number: usize,
+    consolidated: bool,
     top_book_depth: usize,
+    instrument: Instrument,
     quotes_merger: T,
     exchanges_id_mapping: HashMap<usize, String>,
+    exchanges_currency: HashMap<usize, String>,
+
+    clock: Arc<dyn Clock>,
+    /// Number of processed updates.
+    sequence: u64,
+    exchange_time: Option<u64>,
+    receive_time: u64,
+
+    /// Venue quotes are dropped from the top when no update comes within the timeout.
+    stale_timeout: Option<Duration>,
+    /// Clock time of the last update of every exchange.
+    last_update_times: Vec<Option<u64>>,
+    stale: Vec<bool>,
 }
 
 impl<T: MergeQuotes> OrderBookAggregator<T> {
@@ -24,28 +50,72 @@ impl<T: MergeQuotes> OrderBookAggregator<T> {
         top_book_depth: usize,
         exchanges_id_mapping: HashMap<usize, String>,
     ) -> Self {
+        let mut exchanges_books = Vec::with_capacity(exchanges_number);
         let mut exchanges_bids = Vec::with_capacity(exchanges_number);
         let mut exchanges_asks = Vec::with_capacity(exchanges_number);
         for _ in 0..exchanges_number {
+            exchanges_books.push(L2Book::new());
             exchanges_bids.push(Vec::with_capacity(top_book_depth));
             exchanges_asks.push(Vec::with_capacity(top_book_depth));
         }
 
-        let old_bid_book_top = Vec::with_capacity(top_book_depth.into());
-        let old_ask_book_top = Vec::with_capacity(top_book_depth.into());
+        let old_bid_book_top = Vec::with_capacity(top_book_depth);
+        let old_ask_book_top = Vec::with_capacity(top_book_depth);
 
         Self {
+            exchanges_books,
             exchanges_bids,
             exchanges_asks,
             bid_book_top: old_bid_book_top,
             ask_book_top: old_ask_book_top,
-            exchanges_number,
+            consolidated_bids: vec![],
+            consolidated_asks: vec![],
+            consolidated: false,
             top_book_depth,
+            instrument: Instrument::default(),
             quotes_merger,
             exchanges_id_mapping,
+            exchanges_currency: HashMap::new(),
+            clock: Arc::new(SystemClock),
+            sequence: 0,
+            exchange_time: None,
+            receive_time: 0,
+            stale_timeout: None,
+            last_update_times: vec![None; exchanges_number],
+            stale: vec![false; exchanges_number],
         }
     }
 
+    pub fn with_instrument(mut self, instrument: Instrument) -> Self {
+        self.instrument = instrument;
+        self
+    }
+
+    /// Native quote currency of every exchange, `Level` is tagged with it.
+    pub fn with_currencies(mut self, exchanges_currency: HashMap<usize, String>) -> Self {
+        self.exchanges_currency = exchanges_currency;
+        self
+    }
+
+    /// Clock of `Summary` aggregation time.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Exchanges without updates within `stale_timeout` are excluded from the top until the next
+    /// update, see [`Self::check_stale`].
+    pub fn with_stale_timeout(mut self, stale_timeout: Duration) -> Self {
+        self.stale_timeout = Some(stale_timeout);
+        self
+    }
+
+    /// Levels of all exchanges at the same price are also summed into `Summary` consolidated levels.
+    pub fn with_consolidated(mut self, consolidated: bool) -> Self {
+        self.consolidated = consolidated;
+        self
+    }
+
     pub fn process(&mut self, order_book_update: OrderBookUpdate) -> Option<Summary> {
         let exchange_id = match order_book_update.exchange_id {
             Some(val) => val,
@@ -55,10 +125,74 @@ impl<T: MergeQuotes> OrderBookAggregator<T> {
             }
         };
 
-        let mut top_changed = false;
+        self.sequence += 1;
+        self.exchange_time = order_book_update.exchange_time;
+        self.receive_time = order_book_update.receive_time;
+        // resets sent on every reconnect attempt carry no exchange data
+        let has_data =
+            !order_book_update.bid_changes.is_empty() || !order_book_update.ask_changes.is_empty();
+        let mut status_changed = false;
+        if has_data {
+            self.last_update_times[exchange_id] = Some(self.clock.now_micros());
+            status_changed = std::mem::replace(&mut self.stale[exchange_id], false);
+            if status_changed {
+                info!("exchange is live. exchange_id={}", exchange_id);
+            }
+        }
 
-        self.exchanges_asks[exchange_id] = order_book_update.ask_changes;
-        self.exchanges_bids[exchange_id] = order_book_update.bid_changes;
+        let book = &mut self.exchanges_books[exchange_id];
+        book.apply(&order_book_update);
+        if self.stale[exchange_id] {
+            // quotes of a stale exchange stay excluded
+            return None;
+        }
+        let instrument = self.instrument;
+        self.exchanges_bids[exchange_id] = book
+            .bids()
+            .filter_map(|quote| instrument.normalize(&quote))
+            .take(self.top_book_depth)
+            .collect();
+        self.exchanges_asks[exchange_id] = book
+            .asks()
+            .filter_map(|quote| instrument.normalize(&quote))
+            .take(self.top_book_depth)
+            .collect();
+
+        self.merge(status_changed)
+    }
+
+    /// Marks exchanges without updates within the stale timeout as stale and drops their quotes.
+    /// Returns new summary when any exchange became stale.
+    pub fn check_stale(&mut self) -> Option<Summary> {
+        let stale_timeout = self.stale_timeout?.as_micros() as u64;
+        let now = self.clock.now_micros();
+        let mut status_changed = false;
+        for exchange_id in 0..self.stale.len() {
+            let last_update_time = match self.last_update_times[exchange_id] {
+                Some(val) => val,
+                None => continue,
+            };
+            if self.stale[exchange_id] || now.saturating_sub(last_update_time) < stale_timeout {
+                continue;
+            }
+            error!(
+                "exchange is stale. exchange_id={} last update={}",
+                exchange_id, last_update_time
+            );
+            self.stale[exchange_id] = true;
+            // book is kept and quotes come back with the next update
+            self.exchanges_bids[exchange_id].clear();
+            self.exchanges_asks[exchange_id].clear();
+            status_changed = true;
+        }
+        if !status_changed {
+            return None;
+        }
+        self.merge(true)
+    }
+
+    fn merge(&mut self, status_changed: bool) -> Option<Summary> {
+        let mut top_changed = status_changed;
 
         if let Some(val) =
             self.quotes_merger
@@ -76,47 +210,219 @@ impl<T: MergeQuotes> OrderBookAggregator<T> {
             self.ask_book_top = val;
         };
 
+        if self.consolidated {
+            let consolidated_bids = self.consolidate(&self.exchanges_bids, true);
+            if consolidated_bids != self.consolidated_bids {
+                top_changed = true;
+                self.consolidated_bids = consolidated_bids;
+            }
+            let consolidated_asks = self.consolidate(&self.exchanges_asks, false);
+            if consolidated_asks != self.consolidated_asks {
+                top_changed = true;
+                self.consolidated_asks = consolidated_asks;
+            }
+        }
+
         if top_changed {
-            return self.get_summary();
+            return Some(self.get_summary());
         };
 
         None
     }
 
-    fn get_summary(&self) -> Option<Summary> {
-        if self.bid_book_top.is_empty() || self.ask_book_top.is_empty() {
-            return None;
+    /// Crossed and locked books between different exchanges, stale exchanges are excluded.
+    pub fn arbitrage_opportunities(&self) -> Vec<Opportunity> {
+        let aggregated_quotes = |exchanges_quotes: &[Vec<ExchangeQuote>], reverse_ordering| {
+            exchanges_quotes
+                .iter()
+                .enumerate()
+                .map(|(exchange_id, quotes)| {
+                    quotes
+                        .iter()
+                        .map(|quote| {
+                            self.quotes_merger.aggregated_quote(
+                                exchange_id,
+                                quote,
+                                reverse_ordering,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let exchanges_asks = aggregated_quotes(&self.exchanges_asks, false);
+        let exchanges_bids = aggregated_quotes(&self.exchanges_bids, true);
+
+        let mut opportunities = vec![];
+        for (buy_exchange, asks) in exchanges_asks.iter().enumerate() {
+            for (sell_exchange, bids) in exchanges_bids.iter().enumerate() {
+                if buy_exchange == sell_exchange {
+                    continue;
+                }
+                if let Some(opportunity) = arbitrage::opportunity(asks, bids) {
+                    opportunities.push(opportunity);
+                }
+            }
         }
+        opportunities
+    }
 
-        let mut bids = Vec::with_capacity(self.top_book_depth);
-        let mut asks = Vec::with_capacity(self.top_book_depth);
-
-        for bid in &self.bid_book_top {
-            bids.push(Level {
-                exchange: self
-                    .exchanges_id_mapping
-                    .get(&bid.exchange)
-                    .unwrap()
-                    .clone(),
-                price: bid.price,
-                amount: bid.qty,
-            })
+    /// Cost of a market order against the aggregated top of `exchanges`, every exchange when
+    /// `None`. See [`execution::execution`].
+    pub fn execution(&self, side: Side, amount: Amount, exchanges: Option<&[usize]>) -> Execution {
+        let top = |quotes: &[AggregatedBookQuote]| -> Vec<AggregatedBookQuote> {
+            quotes
+                .iter()
+                .filter(|quote| match exchanges {
+                    Some(val) => val.contains(&quote.exchange),
+                    None => true,
+                })
+                .cloned()
+                .collect()
+        };
+        execution::execution(
+            side,
+            amount,
+            self.instrument.qty_scale,
+            &top(&self.bid_book_top),
+            &top(&self.ask_book_top),
+        )
+    }
+
+    /// Split of `parent` across the top `top_book_depth` levels of exchange books,
+    /// stale exchanges are excluded. See [`routing::route`].
+    pub fn route(&self, parent: &ParentOrder) -> RoutePlan {
+        let exchanges_quotes = match parent.side {
+            Side::Buy => &self.exchanges_asks,
+            Side::Sell => &self.exchanges_bids,
+        };
+        routing::route(parent, exchanges_quotes)
+    }
+
+    /// Stale exchanges are excluded from the top until the next update, see [`Self::check_stale`].
+    pub fn is_stale(&self, exchange_id: usize) -> bool {
+        self.stale.get(exchange_id).copied().unwrap_or_default()
+    }
+
+    /// Full book of every exchange, stale exchanges included.
+    pub fn exchanges_books(&self) -> &[L2Book] {
+        &self.exchanges_books
+    }
+
+    pub fn exchanges_id_mapping(&self) -> &HashMap<usize, String> {
+        &self.exchanges_id_mapping
+    }
+
+    /// Top `top_book_depth` distinct prices are always within top `top_book_depth` levels
+    /// of every exchange, so exchange tops are enough to build the consolidated book.
+    fn consolidate(
+        &self,
+        exchanges_quotes: &[Vec<ExchangeQuote>],
+        reverse_ordering: bool,
+    ) -> Vec<ConsolidatedLevel> {
+        let mut price_levels: BTreeMap<Price, Vec<AggregatedBookQuote>> = BTreeMap::new();
+        for (exchange_id, quotes) in exchanges_quotes.iter().enumerate() {
+            for quote in quotes {
+                let agg_quote =
+                    self.quotes_merger
+                        .aggregated_quote(exchange_id, quote, reverse_ordering);
+                price_levels.entry(quote.price).or_default().push(agg_quote);
+            }
         }
 
-        for ask in &self.ask_book_top {
-            asks.push(Level {
-                exchange: self
-                    .exchanges_id_mapping
-                    .get(&ask.exchange)
-                    .unwrap()
-                    .clone(),
-                price: ask.price,
-                amount: ask.qty,
-            })
+        let to_consolidated_level = |(price, venues): (&Price, &Vec<AggregatedBookQuote>)| {
+            let amount: Qty = venues.iter().map(|quote| quote.qty).sum();
+            ConsolidatedLevel {
+                price: price.to_f64().unwrap_or_default(),
+                amount: amount.to_f64().unwrap_or_default(),
+                venues: venues.iter().map(|quote| self.level(quote)).collect(),
+            }
+        };
+        if reverse_ordering {
+            price_levels
+                .iter()
+                .rev()
+                .take(self.top_book_depth)
+                .map(to_consolidated_level)
+                .collect()
+        } else {
+            price_levels
+                .iter()
+                .take(self.top_book_depth)
+                .map(to_consolidated_level)
+                .collect()
         }
+    }
 
-        let spread = self.ask_book_top[0].price - self.bid_book_top[0].price;
+    fn level(&self, quote: &AggregatedBookQuote) -> Level {
+        Level {
+            exchange: self
+                .exchanges_id_mapping
+                .get(&quote.exchange)
+                .unwrap()
+                .clone(),
+            price: quote.price.to_f64().unwrap_or_default(),
+            amount: quote.qty.to_f64().unwrap_or_default(),
+            effective_price: quote.effective_price.to_f64().unwrap_or_default(),
+            currency: self
+                .exchanges_currency
+                .get(&quote.exchange)
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
 
-        Some(Summary { spread, bids, asks })
+    /// Levels of a side are empty while no live exchange has quotes on it, the summary still
+    /// reports venue states.
+    fn get_summary(&self) -> Summary {
+        let bids = self
+            .bid_book_top
+            .iter()
+            .map(|bid| self.level(bid))
+            .collect();
+        let asks = self
+            .ask_book_top
+            .iter()
+            .map(|ask| self.level(ask))
+            .collect();
+
+        let spread = match (self.bid_book_top.first(), self.ask_book_top.first()) {
+            (Some(bid), Some(ask)) => (ask.price - bid.price).to_f64().unwrap_or_default(),
+            _ => 0.0,
+        };
+
+        Summary {
+            spread,
+            bids,
+            asks,
+            consolidated_bids: self.consolidated_bids.clone(),
+            consolidated_asks: self.consolidated_asks.clone(),
+            exchange_time: self.exchange_time.unwrap_or_default(),
+            receive_time: self.receive_time,
+            aggregation_time: self.clock.now_micros(),
+            sequence: self.sequence,
+            venues: self.venues(),
+        }
+    }
+
+    fn venues(&self) -> Vec<VenueStatus> {
+        let mut exchange_ids: Vec<_> = self.exchanges_id_mapping.keys().copied().collect();
+        exchange_ids.sort_unstable();
+        exchange_ids
+            .into_iter()
+            .map(|exchange_id| {
+                let last_update_time = self.last_update_times[exchange_id];
+                let state = if last_update_time.is_none() || self.stale[exchange_id] {
+                    State::Stale
+                } else {
+                    State::Live
+                };
+                VenueStatus {
+                    exchange: self.exchanges_id_mapping[&exchange_id].clone(),
+                    state: state.into(),
+                    last_update_time: last_update_time.unwrap_or_default(),
+                }
+            })
+            .collect()
     }
 }
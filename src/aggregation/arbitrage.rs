@@ -0,0 +1,231 @@
+use crate::common::clock::{Clock, SystemClock};
+use crate::common::model::{AggregatedBookQuote, Price, Qty};
+use crate::orderbook::arbitrage_opportunity::State;
+use crate::orderbook::{ArbitrageOpportunity, ArbitragePairStats, ArbitrageReport};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Crossed or locked book between asks of `buy_exchange` and bids of `sell_exchange`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Opportunity {
+    pub buy_exchange: usize,
+    pub sell_exchange: usize,
+    /// Best ask of the buy exchange.
+    pub buy_price: Price,
+    /// Best bid of the sell exchange.
+    pub sell_price: Price,
+    /// Qty executable at a non-negative gross profit.
+    pub qty: Qty,
+    pub gross_profit: Decimal,
+    /// Profit with taker fees of both exchanges.
+    pub net_profit: Decimal,
+}
+
+impl Opportunity {
+    /// Best bid equals best ask.
+    pub fn is_locked(&self) -> bool {
+        self.sell_price == self.buy_price
+    }
+}
+
+/// Walks asks of one exchange and bids of another one from the best price while bid is not below ask.
+/// Returns `None` when the books are not crossed or locked.
+pub fn opportunity(
+    asks: &[AggregatedBookQuote],
+    bids: &[AggregatedBookQuote],
+) -> Option<Opportunity> {
+    let (best_ask, best_bid) = (asks.first()?, bids.first()?);
+    if best_bid.price < best_ask.price {
+        return None;
+    }
+    let mut opportunity = Opportunity {
+        buy_exchange: best_ask.exchange,
+        sell_exchange: best_bid.exchange,
+        buy_price: best_ask.price,
+        sell_price: best_bid.price,
+        qty: Qty::ZERO,
+        gross_profit: Decimal::ZERO,
+        net_profit: Decimal::ZERO,
+    };
+
+    let mut asks = asks.iter().cloned();
+    let mut bids = bids.iter().cloned();
+    let mut ask = asks.next();
+    let mut bid = bids.next();
+    while let (Some(ask_quote), Some(bid_quote)) = (ask.as_mut(), bid.as_mut()) {
+        if bid_quote.price < ask_quote.price {
+            break;
+        }
+        let qty = ask_quote.qty.min(bid_quote.qty);
+        opportunity.qty += qty;
+        opportunity.gross_profit += (bid_quote.price - ask_quote.price) * qty;
+        opportunity.net_profit += (bid_quote.effective_price - ask_quote.effective_price) * qty;
+
+        ask_quote.qty -= qty;
+        bid_quote.qty -= qty;
+        if ask_quote.qty.is_zero() {
+            ask = asks.next();
+        }
+        if bid_quote.qty.is_zero() {
+            bid = bids.next();
+        }
+    }
+    Some(opportunity)
+}
+
+struct OpenOpportunity {
+    started_at: u64,
+    opportunity: Opportunity,
+}
+
+#[derive(Default)]
+struct PairStats {
+    count: u64,
+    profitable_count: u64,
+    total_duration: u64,
+    max_duration: u64,
+    max_qty: Qty,
+    max_gross_profit: Decimal,
+    /// Net profit may be negative, `None` before the first opportunity.
+    max_net_profit: Option<Decimal>,
+}
+
+/// Tracks opportunities of the aggregator from opening to closing and collects per exchange pair stats.
+pub struct ArbitrageTracker {
+    exchanges_id_mapping: HashMap<usize, String>,
+    clock: Arc<dyn Clock>,
+    open: BTreeMap<(usize, usize), OpenOpportunity>,
+    stats: BTreeMap<(usize, usize), PairStats>,
+}
+
+impl ArbitrageTracker {
+    pub fn new(exchanges_id_mapping: HashMap<usize, String>) -> Self {
+        Self {
+            exchanges_id_mapping,
+            clock: Arc::new(SystemClock),
+            open: BTreeMap::new(),
+            stats: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Compares current opportunities with the open ones. Returns events of opened, changed and
+    /// closed opportunities, closed ones carry the final duration.
+    pub fn update(&mut self, opportunities: Vec<Opportunity>) -> Vec<ArbitrageOpportunity> {
+        let now = self.clock.now_micros();
+        let current: BTreeMap<_, _> = opportunities
+            .into_iter()
+            .map(|opportunity| {
+                (
+                    (opportunity.buy_exchange, opportunity.sell_exchange),
+                    opportunity,
+                )
+            })
+            .collect();
+
+        let mut events = vec![];
+        let closed: Vec<_> = self
+            .open
+            .keys()
+            .filter(|key| !current.contains_key(key))
+            .copied()
+            .collect();
+        for key in closed {
+            let open = self.open.remove(&key).unwrap();
+            let duration = now.saturating_sub(open.started_at);
+            let stats = self.stats.entry(key).or_default();
+            stats.total_duration += duration;
+            stats.max_duration = stats.max_duration.max(duration);
+            events.push(self.event(State::Close, &open, now));
+        }
+
+        for (key, opportunity) in current {
+            let stats = self.stats.entry(key).or_default();
+            stats.max_qty = stats.max_qty.max(opportunity.qty);
+            stats.max_gross_profit = stats.max_gross_profit.max(opportunity.gross_profit);
+            stats.max_net_profit = Some(match stats.max_net_profit {
+                Some(val) => val.max(opportunity.net_profit),
+                None => opportunity.net_profit,
+            });
+
+            let state = match self.open.get_mut(&key) {
+                Some(open) if open.opportunity == opportunity => continue,
+                Some(open) => {
+                    open.opportunity = opportunity;
+                    State::Update
+                }
+                None => {
+                    stats.count += 1;
+                    if opportunity.net_profit > Decimal::ZERO {
+                        stats.profitable_count += 1;
+                    }
+                    self.open.insert(
+                        key,
+                        OpenOpportunity {
+                            started_at: now,
+                            opportunity,
+                        },
+                    );
+                    State::Open
+                }
+            };
+            events.push(self.event(state, &self.open[&key], now));
+        }
+        events
+    }
+
+    fn exchange_name(&self, exchange_id: usize) -> String {
+        self.exchanges_id_mapping
+            .get(&exchange_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn event(&self, state: State, open: &OpenOpportunity, now: u64) -> ArbitrageOpportunity {
+        let opportunity = &open.opportunity;
+        ArbitrageOpportunity {
+            state: state.into(),
+            buy_exchange: self.exchange_name(opportunity.buy_exchange),
+            sell_exchange: self.exchange_name(opportunity.sell_exchange),
+            locked: opportunity.is_locked(),
+            buy_price: opportunity.buy_price.to_f64().unwrap_or_default(),
+            sell_price: opportunity.sell_price.to_f64().unwrap_or_default(),
+            qty: opportunity.qty.to_f64().unwrap_or_default(),
+            gross_profit: opportunity.gross_profit.to_f64().unwrap_or_default(),
+            net_profit: opportunity.net_profit.to_f64().unwrap_or_default(),
+            started_at: open.started_at,
+            duration: now.saturating_sub(open.started_at),
+        }
+    }
+
+    /// Stats of every exchange pair that had an opportunity, durations include closed ones only.
+    pub fn report(&self) -> ArbitrageReport {
+        let pairs = self
+            .stats
+            .iter()
+            .map(
+                |((buy_exchange, sell_exchange), stats)| ArbitragePairStats {
+                    buy_exchange: self.exchange_name(*buy_exchange),
+                    sell_exchange: self.exchange_name(*sell_exchange),
+                    count: stats.count,
+                    profitable_count: stats.profitable_count,
+                    total_duration: stats.total_duration,
+                    max_duration: stats.max_duration,
+                    max_qty: stats.max_qty.to_f64().unwrap_or_default(),
+                    max_gross_profit: stats.max_gross_profit.to_f64().unwrap_or_default(),
+                    max_net_profit: stats
+                        .max_net_profit
+                        .and_then(|val| val.to_f64())
+                        .unwrap_or_default(),
+                },
+            )
+            .collect();
+        ArbitrageReport { pairs }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::aggregation::aggregator::OrderBookAggregator;
+use crate::aggregation::quote_merge::MergeQuotes;
+use crate::common::clock::SimulatedClock;
+use crate::common::model::OrderBookUpdate;
+use crate::orderbook::Summary;
+use std::sync::Arc;
+
+/// Aggregator driven by recorded updates, its clock follows the receive time of every update so
+/// the same updates always produce the same `Summary` sequence.
+pub struct Backtest<T: MergeQuotes> {
+    order_book_aggregator: OrderBookAggregator<T>,
+    clock: Arc<SimulatedClock>,
+}
+
+impl<T: MergeQuotes> Backtest<T> {
+    pub fn new(order_book_aggregator: OrderBookAggregator<T>) -> Self {
+        let clock = SimulatedClock::at(0);
+        Self {
+            order_book_aggregator: order_book_aggregator.with_clock(clock.clone()),
+            clock,
+        }
+    }
+
+    pub fn process(&mut self, order_book_update: OrderBookUpdate) -> Option<Summary> {
+        self.clock.set(order_book_update.receive_time);
+        self.order_book_aggregator.process(order_book_update)
+    }
+}
+
+/// One line of a summary sequence file: `sequence` of the summary, the number of the update that
+/// produced it, followed by the summary.
+pub fn summary_line(summary: &Summary) -> String {
+    format!("{} {:?}", summary.sequence, summary)
+}
+
+fn line_sequence(line: &str) -> Option<u64> {
+    line.split_once(' ')?.0.parse().ok()
+}
+
+/// First differing line of two summary sequences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// Zero based line number.
+    pub line: usize,
+    /// First update with a different result, the lower sequence of the two lines.
+    pub event: Option<u64>,
+    /// `None` when the sequence ended before the line.
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+pub fn first_difference(
+    left: impl IntoIterator<Item = String>,
+    right: impl IntoIterator<Item = String>,
+) -> Option<Difference> {
+    let mut left = left.into_iter();
+    let mut right = right.into_iter();
+    let mut line = 0;
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) => return None,
+            (left, right) if left != right => {
+                let event = [&left, &right]
+                    .into_iter()
+                    .filter_map(|val| val.as_deref().and_then(line_sequence))
+                    .min();
+                return Some(Difference {
+                    line,
+                    event,
+                    left,
+                    right,
+                });
+            }
+            _ => line += 1,
+        }
+    }
+}
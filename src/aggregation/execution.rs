@@ -0,0 +1,219 @@
+use crate::common::model::{AggregatedBookQuote, Price, Qty, Side};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::BTreeMap;
+
+/// Size of a market order: base qty or quote notional.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Amount {
+    Qty(Qty),
+    Notional(Decimal),
+}
+
+/// Part of the order filled on one exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExchangeFill {
+    pub exchange: usize,
+    pub qty: Qty,
+    pub notional: Decimal,
+}
+
+impl ExchangeFill {
+    pub fn vwap(&self) -> Price {
+        self.notional / self.qty
+    }
+}
+
+/// Cost of a market order walking the aggregated top of book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    pub qty: Qty,
+    pub notional: Decimal,
+    /// `None` when nothing is filled.
+    pub vwap: Option<Price>,
+    /// Price of the last level taken.
+    pub worst_price: Option<Price>,
+    /// `None` when one side of the book is empty.
+    pub mid_price: Option<Price>,
+    /// Positive when the vwap is worse than the mid price.
+    pub slippage_bps: Option<Decimal>,
+    /// Ordered by exchange id.
+    pub fills: Vec<ExchangeFill>,
+    /// The top ran out before the amount was filled.
+    pub depth_exhausted: bool,
+}
+
+/// Takes levels of the opposite side from the best one until `amount` is filled.
+/// Levels are taken in the merged order of the top, so with fees configured the best
+/// fee-inclusive price goes first. Qty bought with a notional is rounded down to `qty_scale`.
+pub fn execution(
+    side: Side,
+    amount: Amount,
+    qty_scale: u32,
+    bids: &[AggregatedBookQuote],
+    asks: &[AggregatedBookQuote],
+) -> Execution {
+    let levels = match side {
+        Side::Buy => asks,
+        Side::Sell => bids,
+    };
+
+    let mut qty = Qty::ZERO;
+    let mut notional = Decimal::ZERO;
+    let mut worst_price = None;
+    let mut fills: BTreeMap<usize, ExchangeFill> = BTreeMap::new();
+    let notional_qty = |remaining: Decimal, price: Price| {
+        (remaining / price).round_dp_with_strategy(qty_scale, RoundingStrategy::ToZero)
+    };
+    let mut depth_exhausted = true;
+    for level in levels {
+        let level_qty = match amount {
+            Amount::Qty(target) => (target - qty).min(level.qty),
+            // the remaining notional below one qty step is not taken from the next level
+            Amount::Notional(target) => notional_qty(target - notional, level.price).min(level.qty),
+        };
+        if level_qty <= Qty::ZERO {
+            depth_exhausted = false;
+            break;
+        }
+        let level_notional = level_qty * level.price;
+        qty += level_qty;
+        notional += level_notional;
+        worst_price = Some(level.price);
+        let fill = fills.entry(level.exchange).or_insert(ExchangeFill {
+            exchange: level.exchange,
+            qty: Qty::ZERO,
+            notional: Decimal::ZERO,
+        });
+        fill.qty += level_qty;
+        fill.notional += level_notional;
+    }
+    // the last level may fill the amount exactly
+    if depth_exhausted {
+        depth_exhausted = match (amount, worst_price) {
+            (Amount::Qty(target), _) => qty < target,
+            (Amount::Notional(target), Some(price)) => {
+                !notional_qty(target - notional, price).is_zero()
+            }
+            (Amount::Notional(_), None) => true,
+        };
+    }
+
+    let vwap = (!qty.is_zero()).then(|| notional / qty);
+    let mid_price = match (bids.first(), asks.first()) {
+        (Some(bid), Some(ask)) => Some((bid.price + ask.price) / Decimal::TWO),
+        _ => None,
+    };
+    let slippage_bps = match (vwap, mid_price) {
+        (Some(vwap), Some(mid_price)) if !mid_price.is_zero() => {
+            let slippage = match side {
+                Side::Buy => vwap - mid_price,
+                Side::Sell => mid_price - vwap,
+            };
+            Some(slippage / mid_price * Decimal::from(10_000))
+        }
+        _ => None,
+    };
+
+    Execution {
+        qty,
+        notional,
+        vwap,
+        worst_price,
+        mid_price,
+        slippage_bps,
+        fills: fills.into_values().collect(),
+        depth_exhausted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{execution, Amount, ExchangeFill};
+    use crate::common::model::{AggregatedBookQuote, Side};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn quote(exchange: usize, price: Decimal, qty: Decimal) -> AggregatedBookQuote {
+        AggregatedBookQuote {
+            exchange,
+            price,
+            effective_price: price,
+            qty,
+        }
+    }
+
+    #[test]
+    fn walk_top_of_book() {
+        let bids = vec![quote(1, dec!(99), dec!(1)), quote(0, dec!(98), dec!(2))];
+        let asks = vec![
+            quote(0, dec!(101), dec!(1)),
+            quote(1, dec!(102), dec!(2)),
+            quote(0, dec!(103), dec!(1)),
+        ];
+
+        let buy = execution(Side::Buy, Amount::Qty(dec!(2)), 8, &bids, &asks);
+        assert_eq!(buy.qty, dec!(2));
+        assert_eq!(buy.notional, dec!(203));
+        assert_eq!(buy.vwap, Some(dec!(101.5)));
+        assert_eq!(buy.worst_price, Some(dec!(102)));
+        assert_eq!(buy.mid_price, Some(dec!(100)));
+        assert_eq!(buy.slippage_bps, Some(dec!(150)));
+        assert_eq!(
+            buy.fills,
+            vec![
+                ExchangeFill {
+                    exchange: 0,
+                    qty: dec!(1),
+                    notional: dec!(101),
+                },
+                ExchangeFill {
+                    exchange: 1,
+                    qty: dec!(1),
+                    notional: dec!(102),
+                },
+            ]
+        );
+        assert!(!buy.depth_exhausted);
+
+        // 99 + 2 * 98 = 295, 5 more are not in the top
+        let sell = execution(Side::Sell, Amount::Notional(dec!(300)), 8, &bids, &asks);
+        assert_eq!(sell.qty, dec!(3));
+        assert_eq!(sell.notional, dec!(295));
+        assert_eq!(sell.worst_price, Some(dec!(98)));
+        assert!(sell.slippage_bps.unwrap() > dec!(166));
+        assert!(sell.depth_exhausted);
+
+        let exact = execution(Side::Sell, Amount::Notional(dec!(295)), 8, &bids, &asks);
+        assert_eq!(exact.qty, dec!(3));
+        assert!(!exact.depth_exhausted);
+
+        let empty = execution(Side::Buy, Amount::Qty(dec!(1)), 8, &bids, &[]);
+        assert_eq!(empty.vwap, None);
+        assert_eq!(empty.slippage_bps, None);
+        assert!(empty.depth_exhausted);
+    }
+
+    #[test]
+    fn notional_rounded_to_qty_scale() {
+        let asks = vec![quote(0, dec!(3), dec!(50)), quote(1, dec!(4), dec!(10))];
+
+        // 100 / 3 doesn't divide evenly, the remaining dust is not taken at 4
+        let buy = execution(Side::Buy, Amount::Notional(dec!(100)), 8, &[], &asks);
+        assert_eq!(buy.qty, dec!(33.33333333));
+        assert_eq!(buy.notional, dec!(99.99999999));
+        assert_eq!(buy.worst_price, Some(dec!(3)));
+        assert_eq!(buy.fills.len(), 1);
+        assert!(!buy.depth_exhausted);
+
+        let buy = execution(Side::Buy, Amount::Notional(dec!(100)), 2, &[], &asks[..1]);
+        assert_eq!(buy.qty, dec!(33.33));
+        assert_eq!(buy.notional, dec!(99.99));
+        assert!(!buy.depth_exhausted);
+
+        // 150 + 40, the remaining 10 buys more than one qty step
+        let buy = execution(Side::Buy, Amount::Notional(dec!(200)), 8, &[], &asks);
+        assert_eq!(buy.qty, dec!(60));
+        assert_eq!(buy.worst_price, Some(dec!(4)));
+        assert!(buy.depth_exhausted);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::common::model::Price;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// Maker and taker fee rates of an exchange, e.g. `0.001` is 10 bps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub maker: Decimal,
+    pub taker: Decimal,
+}
+
+impl FeeSchedule {
+    pub fn new(maker: Decimal, taker: Decimal) -> Self {
+        Self { maker, taker }
+    }
+
+    /// Price paid when taking the quote: bids are marked down, asks are marked up.
+    pub fn effective_price(&self, price: Price, is_bid: bool) -> Price {
+        if is_bid {
+            price * (Decimal::ONE - self.taker)
+        } else {
+            price * (Decimal::ONE + self.taker)
+        }
+    }
+}
+
+/// Parses `maker:taker` rates, e.g. `0.0002:0.0005`.
+impl FromStr for FeeSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (maker, taker) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected maker:taker fee rates, got {}", s))?;
+        let parse =
+            |rate: &str| Decimal::from_str(rate).map_err(|err| format!("{}: {}", rate, err));
+        Ok(Self::new(parse(maker)?, parse(taker)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeeSchedule;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn parse_and_effective_price() {
+        let fees: FeeSchedule = "0.0002:0.001".parse().unwrap();
+        assert_eq!(fees, FeeSchedule::new(dec!(0.0002), dec!(0.001)));
+        assert_eq!(fees.effective_price(dec!(30000), true), dec!(29970));
+        assert_eq!(fees.effective_price(dec!(30000), false), dec!(30030));
+
+        assert!("0.001".parse::<FeeSchedule>().is_err());
+        assert!("0.001:x".parse::<FeeSchedule>().is_err());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::common::model::{ExchangeQuote, OrderBookUpdate, Price};
+use crate::orderbook::book::L2Book;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use tracing::{error, info};
+
+/// Quote currency of `BASE/QUOTE` symbol.
+pub fn quote_currency(symbol: &str) -> String {
+    symbol.rsplit('/').next().unwrap_or_default().to_uppercase()
+}
+
+/// Book of a conversion pair, e.g. `USDT/USD` gives USD price of one USDT.
+struct FxFeed {
+    currency: String,
+    inverse: bool,
+    book: L2Book,
+}
+
+/// Converts books quoted in other currencies into the aggregated symbol currency.
+///
+/// Native books of such exchanges are kept here and every change is forwarded as a converted
+/// snapshot, so a rate change doesn't break price keys of books applied in `OrderBookAggregator`.
+pub struct FxConversionStage {
+    currency: String,
+    exchanges_currency: Vec<String>,
+    rates: HashMap<String, Price>,
+    fx_feeds: HashMap<usize, FxFeed>,
+    native_books: HashMap<usize, L2Book>,
+    top_book_depth: usize,
+}
+
+impl FxConversionStage {
+    /// `exchanges_currency` is the native quote currency indexed by exchange id.
+    pub fn new(currency: &str, exchanges_currency: Vec<String>, top_book_depth: usize) -> Self {
+        Self {
+            currency: currency.to_uppercase(),
+            exchanges_currency,
+            rates: HashMap::new(),
+            fx_feeds: HashMap::new(),
+            native_books: HashMap::new(),
+            top_book_depth,
+        }
+    }
+
+    /// Configured price of one `currency` unit in the aggregated currency.
+    pub fn with_rate(mut self, currency: &str, rate: Price) -> Self {
+        self.rates.insert(currency.to_uppercase(), rate);
+        self
+    }
+
+    /// Live rate from mid price of `pair` book published with `exchange_id`.
+    /// Either `CURRENCY/TARGET` or `TARGET/CURRENCY` pair is accepted, other pairs are an error.
+    pub fn with_feed(mut self, exchange_id: usize, pair: &str) -> Result<Self, String> {
+        let (base, quote) = pair.split_once('/').unwrap_or((pair, ""));
+        let (currency, inverse) = if quote.eq_ignore_ascii_case(&self.currency) {
+            (base.to_uppercase(), false)
+        } else if base.eq_ignore_ascii_case(&self.currency) {
+            (quote.to_uppercase(), true)
+        } else {
+            return Err(format!(
+                "fx pair {} doesn't convert into {}",
+                pair, &self.currency
+            ));
+        };
+        self.fx_feeds.insert(
+            exchange_id,
+            FxFeed {
+                currency,
+                inverse,
+                book: L2Book::new(),
+            },
+        );
+        Ok(self)
+    }
+
+    pub fn rate(&self, currency: &str) -> Option<Price> {
+        if currency == self.currency {
+            return Some(Decimal::ONE);
+        }
+        self.rates.get(currency).copied()
+    }
+
+    pub fn process(&mut self, order_book_update: OrderBookUpdate) -> Vec<OrderBookUpdate> {
+        let exchange_id = match order_book_update.exchange_id {
+            Some(val) => val,
+            None => return vec![order_book_update],
+        };
+
+        if self.fx_feeds.contains_key(&exchange_id) {
+            return self.on_fx_update(exchange_id, &order_book_update);
+        }
+
+        let currency = match self.exchanges_currency.get(exchange_id) {
+            Some(val) if *val != self.currency => val.clone(),
+            _ => return vec![order_book_update],
+        };
+        let book = self.native_books.entry(exchange_id).or_default();
+        book.apply(&order_book_update);
+
+        match self.converted_snapshot(exchange_id, &currency, &order_book_update) {
+            Some(val) => vec![val],
+            None => vec![],
+        }
+    }
+
+    fn on_fx_update(
+        &mut self,
+        exchange_id: usize,
+        order_book_update: &OrderBookUpdate,
+    ) -> Vec<OrderBookUpdate> {
+        let fx_feed = self.fx_feeds.get_mut(&exchange_id).unwrap();
+        fx_feed.book.apply(order_book_update);
+        let mid = match (fx_feed.book.best_bid(), fx_feed.book.best_ask()) {
+            (Some(bid), Some(ask)) => (bid.price + ask.price) / Decimal::TWO,
+            _ => return vec![],
+        };
+        if mid.is_zero() {
+            error!("zero fx mid price. currency={}", &fx_feed.currency);
+            return vec![];
+        }
+        let rate = if fx_feed.inverse {
+            Decimal::ONE / mid
+        } else {
+            mid
+        };
+        let currency = fx_feed.currency.clone();
+        if self.rates.insert(currency.clone(), rate) == Some(rate) {
+            return vec![];
+        }
+        info!("fx rate updated. currency={} rate={}", &currency, rate);
+
+        let mut exchange_ids: Vec<_> = self.native_books.keys().copied().collect();
+        exchange_ids.sort();
+        exchange_ids
+            .into_iter()
+            .filter(|exchange_id| self.exchanges_currency[*exchange_id] == currency)
+            .filter_map(|exchange_id| {
+                self.converted_snapshot(exchange_id, &currency, order_book_update)
+            })
+            .collect()
+    }
+
+    /// Snapshot is stamped with times of the `source` update that changed the book or the rate.
+    fn converted_snapshot(
+        &self,
+        exchange_id: usize,
+        currency: &str,
+        source: &OrderBookUpdate,
+    ) -> Option<OrderBookUpdate> {
+        let rate = self.rate(currency)?;
+        let book = self.native_books.get(&exchange_id)?;
+        let convert = |quote: ExchangeQuote| ExchangeQuote {
+            price: quote.price * rate,
+            qty: quote.qty,
+        };
+        Some(OrderBookUpdate {
+            exchange_id: Some(exchange_id),
+            bid_changes: book.bids().take(self.top_book_depth).map(convert).collect(),
+            ask_changes: book.asks().take(self.top_book_depth).map(convert).collect(),
+            is_snapshot: true,
+            exchange_time: source.exchange_time,
+            receive_time: source.receive_time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote_currency, FxConversionStage};
+    use crate::common::model::{ExchangeQuote, OrderBookUpdate, Price, Qty};
+    use rust_decimal_macros::dec;
+
+    fn book_update(
+        exchange_id: usize,
+        bids: &[(Price, Qty)],
+        asks: &[(Price, Qty)],
+        is_snapshot: bool,
+    ) -> OrderBookUpdate {
+        let quotes = |levels: &[(Price, Qty)]| {
+            levels
+                .iter()
+                .map(|(price, qty)| ExchangeQuote {
+                    price: *price,
+                    qty: *qty,
+                })
+                .collect()
+        };
+        OrderBookUpdate {
+            exchange_id: Some(exchange_id),
+            bid_changes: quotes(bids),
+            ask_changes: quotes(asks),
+            is_snapshot,
+            exchange_time: None,
+            receive_time: 0,
+        }
+    }
+
+    fn prices(quotes: &[ExchangeQuote]) -> Vec<Price> {
+        quotes.iter().map(|quote| quote.price).collect()
+    }
+
+    #[test]
+    fn symbol_quote_currency() {
+        assert_eq!(quote_currency("BTC/USDT"), "USDT");
+        assert_eq!(quote_currency("btc/usd"), "USD");
+    }
+
+    #[test]
+    fn same_currency_passes_through() {
+        let mut stage = FxConversionStage::new("USD", vec!["USD".to_string()], 10);
+        let updates = stage.process(book_update(0, &[(dec!(100), dec!(0))], &[], false));
+        assert_eq!(updates.len(), 1);
+        assert!(!updates[0].is_snapshot);
+    }
+
+    #[test]
+    fn configured_rate() {
+        let mut stage =
+            FxConversionStage::new("USD", vec!["USD".to_string(), "EUR".to_string()], 10)
+                .with_rate("eur", dec!(1.1));
+        stage.process(book_update(
+            1,
+            &[(dec!(100), dec!(1))],
+            &[(dec!(101), dec!(1))],
+            true,
+        ));
+        let updates = stage.process(book_update(1, &[(dec!(99), dec!(2))], &[], false));
+        assert_eq!(updates.len(), 1);
+        assert!(updates[0].is_snapshot);
+        assert_eq!(
+            prices(&updates[0].bid_changes),
+            vec![dec!(110.0), dec!(108.9)]
+        );
+        assert_eq!(prices(&updates[0].ask_changes), vec![dec!(111.1)]);
+    }
+
+    #[test]
+    fn live_rate_from_fx_book() {
+        let exchanges_currency = vec!["USD".to_string(), "USDT".to_string()];
+        let mut stage = FxConversionStage::new("USD", exchanges_currency, 10)
+            .with_feed(2, "USDT/USD")
+            .unwrap();
+
+        // no rate yet, native book is kept
+        let updates = stage.process(book_update(1, &[(dec!(30000), dec!(1))], &[], true));
+        assert!(updates.is_empty());
+
+        let updates = stage.process(book_update(
+            2,
+            &[(dec!(0.999), dec!(1000))],
+            &[(dec!(1.001), dec!(1000))],
+            true,
+        ));
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].exchange_id, Some(1));
+        assert_eq!(prices(&updates[0].bid_changes), vec![dec!(30000)]);
+
+        let updates = stage.process(book_update(
+            2,
+            &[(dec!(0.999), dec!(0)), (dec!(0.998), dec!(1000))],
+            &[(dec!(1.001), dec!(0)), (dec!(0.999), dec!(1000))],
+            false,
+        ));
+        assert_eq!(prices(&updates[0].bid_changes), vec![dec!(29955)]);
+        assert_eq!(stage.rate("USDT"), Some(dec!(0.9985)));
+
+        // inverse pair
+        let mut stage = FxConversionStage::new("USDT", vec!["USD".to_string()], 10)
+            .with_feed(1, "USDT/USD")
+            .unwrap();
+        stage.process(book_update(
+            1,
+            &[(dec!(0.5), dec!(1))],
+            &[(dec!(0.5), dec!(1))],
+            true,
+        ));
+        assert_eq!(stage.rate("USD"), Some(dec!(2)));
+    }
+
+    #[test]
+    fn feed_of_other_currencies_is_rejected() {
+        let stage = FxConversionStage::new("USD", vec!["USD".to_string()], 10);
+        assert!(stage.with_feed(1, "EUR/USDT").is_err());
+        let stage = FxConversionStage::new("USD", vec!["USD".to_string()], 10);
+        assert!(stage.with_feed(1, "USDT").is_err());
+    }
+}
@@ -1,108 +1,131 @@
 pub mod aggregator;
+pub mod arbitrage;
+pub mod backtest;
+pub mod execution;
+pub mod fees;
+pub mod fx;
+pub mod paper;
 pub mod quote_merge;
+pub mod routing;
+pub mod synthetic;
 
 #[cfg(test)]
 mod tests {
+    use crate::aggregation::aggregator::OrderBookAggregator;
+    use crate::aggregation::arbitrage::ArbitrageTracker;
+    use crate::aggregation::backtest::{first_difference, summary_line, Backtest};
+    use crate::aggregation::execution::Amount;
+    use crate::aggregation::fees::FeeSchedule;
+    use crate::aggregation::paper::{Liquidity, PaperEngine, PaperOrder};
     use crate::aggregation::quote_merge::{IterativeMergeQuotes, MergeQuotes, VecSortMergeQuotes};
-    use crate::common::model::ExchangeQuote;
+    use crate::aggregation::routing::{ParentOrder, RoutePlan, VenueRules};
+    use crate::common::clock::SimulatedClock;
+    use crate::common::model::{ExchangeQuote, OrderBookUpdate};
+    use crate::common::model::{Price, Qty, Side};
+    use crate::orderbook::arbitrage_opportunity::State as OpportunityState;
+    use crate::orderbook::book::L2Book;
+    use crate::orderbook::venue_status::State;
+    use rust_decimal_macros::dec;
     use std::cmp::Ordering;
+    use std::collections::{BTreeSet, HashMap};
+    use std::time::Duration;
 
     fn exchanges_quotes_asks_fixture() -> Vec<Vec<ExchangeQuote>> {
         let changes1 = vec![
             ExchangeQuote {
-                price: 1.0,
-                qty: 2.0,
+                price: dec!(1.0),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 1.2,
-                qty: 2.2,
+                price: dec!(1.2),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.25,
-                qty: 2.2,
+                price: dec!(1.25),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.35,
-                qty: 2.2,
+                price: dec!(1.35),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.45,
-                qty: 2.2,
+                price: dec!(1.45),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.65,
-                qty: 2.2,
+                price: dec!(1.65),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.68,
-                qty: 2.2,
+                price: dec!(1.68),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.69,
-                qty: 2.2,
+                price: dec!(1.69),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.75,
-                qty: 2.25,
+                price: dec!(1.75),
+                qty: dec!(2.25),
             },
             ExchangeQuote {
-                price: 1.83,
-                qty: 0.25,
+                price: dec!(1.83),
+                qty: dec!(0.25),
             },
             ExchangeQuote {
-                price: 1.93,
-                qty: 0.25,
+                price: dec!(1.93),
+                qty: dec!(0.25),
             },
         ];
 
         let changes2 = vec![
             ExchangeQuote {
-                price: 1.1,
-                qty: 2.0,
+                price: dec!(1.1),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 1.2,
-                qty: 2.4,
+                price: dec!(1.2),
+                qty: dec!(2.4),
             },
             ExchangeQuote {
-                price: 1.25,
-                qty: 1.9,
+                price: dec!(1.25),
+                qty: dec!(1.9),
             },
             ExchangeQuote {
-                price: 1.36,
-                qty: 2.2,
+                price: dec!(1.36),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.46,
-                qty: 2.2,
+                price: dec!(1.46),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.55,
-                qty: 5.0,
+                price: dec!(1.55),
+                qty: dec!(5.0),
             },
             ExchangeQuote {
-                price: 1.65,
-                qty: 2.2,
+                price: dec!(1.65),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.68,
-                qty: 2.2,
+                price: dec!(1.68),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.69,
-                qty: 2.2,
+                price: dec!(1.69),
+                qty: dec!(2.2),
             },
             ExchangeQuote {
-                price: 1.74,
-                qty: 2.25,
+                price: dec!(1.74),
+                qty: dec!(2.25),
             },
             ExchangeQuote {
-                price: 1.84,
-                qty: 0.25,
+                price: dec!(1.84),
+                qty: dec!(0.25),
             },
             ExchangeQuote {
-                price: 1.93,
-                qty: 0.25,
+                price: dec!(1.93),
+                qty: dec!(0.25),
             },
         ];
 
@@ -112,103 +135,103 @@ mod tests {
     fn exchanges_quotes_bids_fixture() -> Vec<Vec<ExchangeQuote>> {
         let changes1 = vec![
             ExchangeQuote {
-                price: 1.0,
-                qty: 2.0,
+                price: dec!(1.0),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.98,
-                qty: 2.0,
+                price: dec!(0.98),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.975,
-                qty: 2.0,
+                price: dec!(0.975),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.97,
-                qty: 2.0,
+                price: dec!(0.97),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.94,
-                qty: 2.0,
+                price: dec!(0.94),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.92,
-                qty: 3.0,
+                price: dec!(0.92),
+                qty: dec!(3.0),
             },
             ExchangeQuote {
-                price: 0.915,
-                qty: 5.0,
+                price: dec!(0.915),
+                qty: dec!(5.0),
             },
             ExchangeQuote {
-                price: 0.91,
-                qty: 2.0,
+                price: dec!(0.91),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.9,
-                qty: 2.0,
+                price: dec!(0.9),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.8,
-                qty: 12.0,
+                price: dec!(0.8),
+                qty: dec!(12.0),
             },
             ExchangeQuote {
-                price: 0.7,
-                qty: 12.0,
+                price: dec!(0.7),
+                qty: dec!(12.0),
             },
             ExchangeQuote {
-                price: 0.4,
-                qty: 42.0,
+                price: dec!(0.4),
+                qty: dec!(42.0),
             },
         ];
 
         let changes2 = vec![
             ExchangeQuote {
-                price: 1.1,
-                qty: 2.0,
+                price: dec!(1.1),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.98,
-                qty: 5.0,
+                price: dec!(0.98),
+                qty: dec!(5.0),
             },
             ExchangeQuote {
-                price: 0.97,
-                qty: 3.0,
+                price: dec!(0.97),
+                qty: dec!(3.0),
             },
             ExchangeQuote {
-                price: 0.96,
-                qty: 2.0,
+                price: dec!(0.96),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.94,
-                qty: 2.0,
+                price: dec!(0.94),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.925,
-                qty: 5.0,
+                price: dec!(0.925),
+                qty: dec!(5.0),
             },
             ExchangeQuote {
-                price: 0.912,
-                qty: 5.0,
+                price: dec!(0.912),
+                qty: dec!(5.0),
             },
             ExchangeQuote {
-                price: 0.911,
-                qty: 2.0,
+                price: dec!(0.911),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.9,
-                qty: 2.0,
+                price: dec!(0.9),
+                qty: dec!(2.0),
             },
             ExchangeQuote {
-                price: 0.85,
-                qty: 12.0,
+                price: dec!(0.85),
+                qty: dec!(12.0),
             },
             ExchangeQuote {
-                price: 0.71,
-                qty: 12.0,
+                price: dec!(0.71),
+                qty: dec!(12.0),
             },
             ExchangeQuote {
-                price: 0.46,
-                qty: 42.0,
+                price: dec!(0.46),
+                qty: dec!(42.0),
             },
         ];
 
@@ -330,4 +353,815 @@ mod tests {
         let top_book_after_same_quotes = merger.merge_quotes(&order_books, &top_book, true);
         assert!(top_book_after_same_quotes.is_none());
     }
+
+    fn book_update(
+        exchange_id: usize,
+        bids: &[(Price, Qty)],
+        asks: &[(Price, Qty)],
+        is_snapshot: bool,
+    ) -> OrderBookUpdate {
+        let quotes = |levels: &[(Price, Qty)]| {
+            levels
+                .iter()
+                .map(|(price, qty)| ExchangeQuote {
+                    price: *price,
+                    qty: *qty,
+                })
+                .collect()
+        };
+        OrderBookUpdate {
+            exchange_id: Some(exchange_id),
+            bid_changes: quotes(bids),
+            ask_changes: quotes(asks),
+            is_snapshot,
+            exchange_time: None,
+            receive_time: 0,
+        }
+    }
+
+    #[test]
+    fn aggregator_applies_deltas() {
+        let top_book_depth = 3;
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let merger = VecSortMergeQuotes::new(top_book_depth, 2);
+        let mut aggregator =
+            OrderBookAggregator::new(merger, 2, top_book_depth, exchanges_id_mapping);
+
+        aggregator.process(book_update(
+            0,
+            &[(dec!(10.0), dec!(1.0)), (dec!(9.0), dec!(1.0))],
+            &[(dec!(11.0), dec!(1.0))],
+            true,
+        ));
+        let summary = aggregator
+            .process(book_update(
+                1,
+                &[(dec!(9.5), dec!(2.0))],
+                &[(dec!(10.5), dec!(2.0)), (dec!(12.0), dec!(2.0))],
+                true,
+            ))
+            .unwrap();
+        assert_eq!(summary.spread, 0.5);
+
+        // best binance bid is replaced by a lower one, other levels stay
+        let summary = aggregator
+            .process(book_update(
+                0,
+                &[(dec!(10.0), dec!(0.0)), (dec!(9.8), dec!(3.0))],
+                &[],
+                false,
+            ))
+            .unwrap();
+        let bids: Vec<_> = summary
+            .bids
+            .iter()
+            .map(|level| (level.exchange.as_str(), level.price, level.amount))
+            .collect();
+        assert_eq!(
+            bids,
+            vec![
+                ("binance", 9.8, 3.0),
+                ("bitstamp", 9.5, 2.0),
+                ("binance", 9.0, 1.0)
+            ]
+        );
+        assert_eq!(summary.asks[0].price, 10.5);
+        assert_eq!(summary.asks[1].price, 11.0);
+
+        // snapshot replaces the whole exchange book
+        let summary = aggregator
+            .process(book_update(
+                0,
+                &[(dec!(8.0), dec!(1.0))],
+                &[(dec!(13.0), dec!(1.0))],
+                true,
+            ))
+            .unwrap();
+        assert_eq!(summary.bids[0].exchange, "bitstamp");
+        assert_eq!(summary.bids[1].price, 8.0);
+        assert_eq!(summary.asks.len(), 3);
+        assert_eq!(summary.asks[2].price, 13.0);
+    }
+
+    #[test]
+    fn aggregator_summary_times() {
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let merger = VecSortMergeQuotes::new(2, 2);
+        let mut aggregator = OrderBookAggregator::new(merger, 2, 2, exchanges_id_mapping)
+            .with_clock(SimulatedClock::at(1672515782300000));
+
+        let mut update = book_update(
+            0,
+            &[(dec!(10.0), dec!(1.0))],
+            &[(dec!(11.0), dec!(1.0))],
+            true,
+        );
+        update.exchange_time = Some(1672515782136000);
+        update.receive_time = 1672515782200000;
+        let summary = aggregator.process(update).unwrap();
+        assert_eq!(summary.exchange_time, 1672515782136000);
+        assert_eq!(summary.receive_time, 1672515782200000);
+        assert_eq!(summary.aggregation_time, 1672515782300000);
+        assert_eq!(summary.sequence, 1);
+
+        // every processed update is counted, times are taken from the last one
+        aggregator.process(book_update(1, &[(dec!(1.0), dec!(1.0))], &[], true));
+        let summary = aggregator
+            .process(book_update(1, &[(dec!(10.5), dec!(1.0))], &[], true))
+            .unwrap();
+        assert_eq!(summary.exchange_time, 0);
+        assert_eq!(summary.sequence, 3);
+    }
+
+    fn merge_both<F>(check: F)
+    where
+        F: Fn(&mut dyn MergeQuotes),
+    {
+        check(&mut IterativeMergeQuotes::new(5, 2));
+        check(&mut VecSortMergeQuotes::new(5, 2));
+    }
+
+    #[test]
+    fn merge_with_empty_exchange() {
+        // merge ends once quotes of all exchanges are exhausted, an exchange without quotes
+        // doesn't keep the iterative merge looking for the next level
+        merge_both(|merger| {
+            let quote = ExchangeQuote {
+                price: dec!(1.0),
+                qty: dec!(1.0),
+            };
+            let exchanges_quotes = vec![vec![], vec![quote.clone(), quote]];
+            let top = merger.merge_quotes(&exchanges_quotes, &[], false).unwrap();
+            assert_eq!(top.len(), 2);
+            assert!(top.iter().all(|quote| quote.exchange == 1));
+        });
+    }
+
+    #[test]
+    fn merge_detects_dropped_levels() {
+        merge_both(|merger| {
+            let quotes = vec![
+                vec![ExchangeQuote {
+                    price: dec!(1.0),
+                    qty: dec!(1.0),
+                }],
+                vec![ExchangeQuote {
+                    price: dec!(1.1),
+                    qty: dec!(1.0),
+                }],
+            ];
+            let top = merger.merge_quotes(&quotes, &[], false).unwrap();
+            assert_eq!(top.len(), 2);
+            assert!(merger.merge_quotes(&quotes, &top, false).is_none());
+
+            // shorter top with the same first levels is a change
+            let quotes = vec![quotes[0].clone(), vec![]];
+            let new_top = merger.merge_quotes(&quotes, &top, false).unwrap();
+            assert_eq!(new_top, top[..1].to_vec());
+        });
+    }
+
+    #[test]
+    fn stale_exchange_excluded() {
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let merger = IterativeMergeQuotes::new(2, 2);
+        let clock = SimulatedClock::at(0);
+        let mut aggregator = OrderBookAggregator::new(merger, 2, 2, exchanges_id_mapping)
+            .with_clock(clock.clone())
+            .with_stale_timeout(Duration::from_secs(1));
+
+        aggregator.process(book_update(
+            0,
+            &[(dec!(10.0), dec!(1.0))],
+            &[(dec!(11.0), dec!(1.0))],
+            true,
+        ));
+        clock.set(500_000);
+        aggregator.process(book_update(
+            1,
+            &[(dec!(9.0), dec!(1.0))],
+            &[(dec!(12.0), dec!(1.0))],
+            true,
+        ));
+        assert!(aggregator.check_stale().is_none());
+
+        // binance stalls
+        clock.set(1_200_000);
+        let summary = aggregator.check_stale().unwrap();
+        assert_eq!(summary.bids.len(), 1);
+        assert_eq!(summary.bids[0].exchange, "bitstamp");
+        assert_eq!(summary.asks[0].price, 12.0);
+        let states: Vec<_> = summary.venues.iter().map(|venue| venue.state()).collect();
+        assert_eq!(states, vec![State::Stale, State::Live]);
+        assert_eq!(summary.venues[1].last_update_time, 500_000);
+        assert!(aggregator.check_stale().is_none());
+
+        // binance is back with a delta on top of the kept book
+        let summary = aggregator
+            .process(book_update(0, &[(dec!(10.5), dec!(1.0))], &[], false))
+            .unwrap();
+        assert_eq!(summary.bids[0].price, 10.5);
+        assert_eq!(summary.asks[0].price, 11.0);
+        assert_eq!(summary.venues[0].state(), State::Live);
+    }
+
+    fn all_exchanges_stale<T: MergeQuotes>(merger: T) {
+        let clock = SimulatedClock::at(0);
+        let mut aggregator =
+            OrderBookAggregator::new(merger, 1, 2, HashMap::from([(0, "binance".to_string())]))
+                .with_clock(clock.clone())
+                .with_stale_timeout(Duration::from_secs(1));
+        aggregator.process(book_update(
+            0,
+            &[(dec!(10.0), dec!(1.0))],
+            &[(dec!(11.0), dec!(1.0))],
+            true,
+        ));
+
+        clock.set(1_200_000);
+        // the venue state change is published with empty levels
+        let summary = aggregator.check_stale().unwrap();
+        assert!(summary.bids.is_empty() && summary.asks.is_empty());
+        assert_eq!(summary.spread, 0.0);
+        assert_eq!(summary.venues[0].state(), State::Stale);
+        // frozen quotes of the stale exchange don't stay in the top
+        for side in [Side::Buy, Side::Sell] {
+            let execution = aggregator.execution(side, Amount::Qty(dec!(1)), None);
+            assert!(execution.fills.is_empty());
+        }
+    }
+
+    #[test]
+    fn all_exchanges_stale_clear_top() {
+        all_exchanges_stale(IterativeMergeQuotes::new(2, 1));
+        all_exchanges_stale(VecSortMergeQuotes::new(2, 1));
+    }
+
+    #[test]
+    fn reset_updates_keep_exchange_stale() {
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let merger = IterativeMergeQuotes::new(2, 2);
+        let clock = SimulatedClock::at(0);
+        let mut aggregator = OrderBookAggregator::new(merger, 2, 2, exchanges_id_mapping)
+            .with_clock(clock.clone())
+            .with_stale_timeout(Duration::from_secs(1));
+
+        aggregator.process(book_update(
+            0,
+            &[(dec!(10.0), dec!(1.0))],
+            &[(dec!(11.0), dec!(1.0))],
+            true,
+        ));
+        // bitstamp fails to connect and only sends resets
+        for time in [200_000, 700_000, 1_100_000] {
+            clock.set(time);
+            aggregator.process(book_update(1, &[], &[], true));
+        }
+        // binance is disconnected after its first update and keeps reconnecting
+        aggregator.process(book_update(0, &[], &[], true));
+
+        let summary = aggregator
+            .process(book_update(
+                0,
+                &[(dec!(10.0), dec!(2.0))],
+                &[(dec!(11.0), dec!(1.0))],
+                true,
+            ))
+            .unwrap();
+        let states: Vec<_> = summary.venues.iter().map(|venue| venue.state()).collect();
+        assert_eq!(states, vec![State::Live, State::Stale]);
+        assert_eq!(summary.venues[1].last_update_time, 0);
+
+        clock.set(1_500_000);
+        aggregator.process(book_update(0, &[], &[], true));
+        // the reset doesn't count as an update
+        clock.set(2_200_000);
+        aggregator.check_stale();
+        let summary = aggregator
+            .process(book_update(
+                1,
+                &[(dec!(9.0), dec!(1.0))],
+                &[(dec!(12.0), dec!(1.0))],
+                true,
+            ))
+            .unwrap();
+        let states: Vec<_> = summary.venues.iter().map(|venue| venue.state()).collect();
+        assert_eq!(states, vec![State::Stale, State::Live]);
+    }
+
+    #[test]
+    fn arbitrage_between_exchanges() {
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let fees = vec![
+            FeeSchedule::new(dec!(0), dec!(0.001)),
+            FeeSchedule::new(dec!(0), dec!(0.001)),
+        ];
+        let merger = IterativeMergeQuotes::new(3, 2).with_fees(fees);
+        let clock = SimulatedClock::at(1_000_000);
+        let mut aggregator = OrderBookAggregator::new(merger, 2, 3, exchanges_id_mapping.clone());
+        let mut tracker = ArbitrageTracker::new(exchanges_id_mapping).with_clock(clock.clone());
+
+        aggregator.process(book_update(
+            0,
+            &[(dec!(99), dec!(1))],
+            &[(dec!(100), dec!(1)), (dec!(100.5), dec!(2))],
+            true,
+        ));
+        aggregator.process(book_update(
+            1,
+            &[(dec!(101), dec!(1.5)), (dec!(100), dec!(1))],
+            &[(dec!(102), dec!(1))],
+            true,
+        ));
+
+        // buy 1 at 100 and 0.5 at 100.5 on binance, sell 1.5 at 101 on bitstamp,
+        // 1 more at 100.5 against the bitstamp bid at 100 is not executable
+        let opportunities = aggregator.arbitrage_opportunities();
+        assert_eq!(opportunities.len(), 1);
+        let opportunity = &opportunities[0];
+        assert_eq!(
+            (opportunity.buy_exchange, opportunity.sell_exchange),
+            (0, 1)
+        );
+        assert_eq!(opportunity.qty, dec!(1.5));
+        assert_eq!(opportunity.gross_profit, dec!(1.25));
+        // taker fees: 101 * 1.5 * 0.001 + (100 + 100.5 * 0.5) * 0.001
+        assert_eq!(opportunity.net_profit, dec!(0.94825));
+        assert!(!opportunity.is_locked());
+
+        let events = tracker.update(opportunities);
+        assert_eq!(events[0].state(), OpportunityState::Open);
+        assert_eq!(events[0].buy_exchange, "binance");
+
+        // bitstamp bid drops to the binance ask, the book is locked
+        aggregator.process(book_update(1, &[(dec!(101), dec!(0))], &[], false));
+        clock.set(1_500_000);
+        let events = tracker.update(aggregator.arbitrage_opportunities());
+        assert_eq!(events[0].state(), OpportunityState::Update);
+        assert!(events[0].locked);
+        assert_eq!(events[0].qty, 1.0);
+
+        aggregator.process(book_update(1, &[(dec!(100), dec!(0))], &[], false));
+        clock.set(3_000_000);
+        let events = tracker.update(aggregator.arbitrage_opportunities());
+        assert_eq!(events[0].state(), OpportunityState::Close);
+        assert_eq!(events[0].duration, 2_000_000);
+
+        let report = tracker.report();
+        assert_eq!(report.pairs.len(), 1);
+        assert_eq!(report.pairs[0].count, 1);
+        assert_eq!(report.pairs[0].profitable_count, 1);
+        assert_eq!(report.pairs[0].max_duration, 2_000_000);
+        assert_eq!(report.pairs[0].max_qty, 1.5);
+    }
+
+    #[test]
+    fn aggregator_consolidated_levels() {
+        let top_book_depth = 2;
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let merger = VecSortMergeQuotes::new(top_book_depth, 2);
+        let mut aggregator =
+            OrderBookAggregator::new(merger, 2, top_book_depth, exchanges_id_mapping)
+                .with_consolidated(true);
+
+        aggregator.process(book_update(
+            0,
+            &[(dec!(30000.00), dec!(1)), (dec!(29999), dec!(1))],
+            &[(dec!(30001), dec!(1))],
+            true,
+        ));
+        let summary = aggregator
+            .process(book_update(
+                1,
+                &[(dec!(30000), dec!(0.5)), (dec!(29998), dec!(4))],
+                &[(dec!(30002), dec!(1))],
+                true,
+            ))
+            .unwrap();
+
+        // two rows at 30000 are merged, the second distinct price comes from binance
+        assert_eq!(summary.bids.len(), 2);
+        assert_eq!(summary.consolidated_bids.len(), 2);
+        let best = &summary.consolidated_bids[0];
+        assert_eq!(best.price, 30000.0);
+        assert_eq!(best.amount, 1.5);
+        let venues: Vec<_> = best
+            .venues
+            .iter()
+            .map(|level| (level.exchange.as_str(), level.amount))
+            .collect();
+        assert_eq!(venues, vec![("binance", 1.0), ("bitstamp", 0.5)]);
+        assert_eq!(summary.consolidated_bids[1].price, 29999.0);
+        assert_eq!(summary.consolidated_asks[0].price, 30001.0);
+        assert_eq!(summary.consolidated_asks[1].venues[0].exchange, "bitstamp");
+
+        // change below merged top rows still updates consolidated book
+        let summary = aggregator
+            .process(book_update(1, &[(dec!(29999), dec!(2))], &[], false))
+            .unwrap();
+        assert_eq!(summary.consolidated_bids[1].amount, 3.0);
+    }
+
+    fn routing_aggregator() -> OrderBookAggregator<IterativeMergeQuotes> {
+        let exchanges_id_mapping = HashMap::from([
+            (0, "binance".to_string()),
+            (1, "bitstamp".to_string()),
+            (2, "coinbase".to_string()),
+        ]);
+        let mut aggregator =
+            OrderBookAggregator::new(IterativeMergeQuotes::new(5, 3), 3, 5, exchanges_id_mapping);
+        aggregator.process(book_update(
+            0,
+            &[(dec!(99), dec!(1)), (dec!(98), dec!(2))],
+            &[
+                (dec!(100), dec!(1)),
+                (dec!(101), dec!(2)),
+                (dec!(103), dec!(5)),
+            ],
+            true,
+        ));
+        aggregator.process(book_update(
+            1,
+            &[(dec!(99.5), dec!(0.5)), (dec!(97), dec!(1))],
+            &[(dec!(100.5), dec!(1.5)), (dec!(102), dec!(3))],
+            true,
+        ));
+        aggregator.process(book_update(
+            2,
+            &[(dec!(96), dec!(10))],
+            &[(dec!(100.2), dec!(0.3)), (dec!(104), dec!(10))],
+            true,
+        ));
+        aggregator
+    }
+
+    fn child_orders(plan: &RoutePlan) -> Vec<(usize, Qty, Price)> {
+        plan.child_orders
+            .iter()
+            .map(|order| (order.exchange, order.qty, order.limit_price))
+            .collect()
+    }
+
+    #[test]
+    fn route_best_price() {
+        let aggregator = routing_aggregator();
+
+        let plan = aggregator.route(&ParentOrder::new(Side::Buy, dec!(3)));
+        assert_eq!(
+            child_orders(&plan),
+            vec![
+                (0, dec!(1.2), dec!(101)),
+                (1, dec!(1.5), dec!(100.5)),
+                (2, dec!(0.3), dec!(100.2)),
+            ]
+        );
+        // 100 + 0.2 * 101 + 1.5 * 100.5 + 0.3 * 100.2
+        assert_eq!(plan.notional, dec!(301.01));
+        assert_eq!(plan.qty, dec!(3));
+        assert_eq!(plan.unfilled, dec!(0));
+    }
+
+    #[test]
+    fn route_with_fees_and_sizes() {
+        let aggregator = routing_aggregator();
+        let parent = ParentOrder::new(Side::Buy, dec!(3))
+            .with_venue(
+                0,
+                VenueRules::new(FeeSchedule::new(dec!(0), dec!(0.003)), dec!(0), dec!(0)),
+            )
+            .with_venue(1, VenueRules::new(FeeSchedule::default(), dec!(0), dec!(1)))
+            .with_venue(
+                2,
+                VenueRules::new(FeeSchedule::default(), dec!(0.5), dec!(0)),
+            );
+
+        // coinbase 0.3 is below the min size, bitstamp takes whole lots and the second lot
+        // at 101.25 on average is cheaper than binance 101 with fees
+        let plan = aggregator.route(&parent);
+        assert_eq!(
+            child_orders(&plan),
+            vec![(0, dec!(1), dec!(100)), (1, dec!(2), dec!(102))]
+        );
+        assert_eq!(plan.child_orders[1].notional, dec!(201.75));
+        assert_eq!(plan.fees, dec!(0.3));
+        assert_eq!(plan.unfilled, dec!(0));
+    }
+
+    #[test]
+    fn route_within_limit_price() {
+        let aggregator = routing_aggregator();
+
+        let parent = ParentOrder::new(Side::Sell, dec!(5)).with_limit_price(dec!(98));
+        let plan = aggregator.route(&parent);
+        assert_eq!(
+            child_orders(&plan),
+            vec![(0, dec!(3), dec!(98)), (1, dec!(0.5), dec!(99.5))]
+        );
+        assert_eq!(plan.unfilled, dec!(1.5));
+
+        let plan = aggregator.route(&parent.with_exchanges(BTreeSet::from([0])));
+        assert_eq!(child_orders(&plan), vec![(0, dec!(3), dec!(98))]);
+        assert_eq!(plan.unfilled, dec!(2));
+        assert_eq!(plan.vwap(), Some(dec!(295) / dec!(3)));
+    }
+
+    #[test]
+    fn fee_adjusted_merge() {
+        let top_book_depth = 2;
+        let fees = vec![
+            FeeSchedule::new(dec!(0), dec!(0.001)),
+            FeeSchedule::new(dec!(0), dec!(0.0001)),
+        ];
+        let exchanges_asks = vec![
+            vec![ExchangeQuote {
+                price: dec!(100.00),
+                qty: dec!(1),
+            }],
+            vec![ExchangeQuote {
+                price: dec!(100.05),
+                qty: dec!(1),
+            }],
+        ];
+        let mut mergers: Vec<Box<dyn MergeQuotes>> = vec![
+            Box::new(VecSortMergeQuotes::new(top_book_depth, 2).with_fees(fees.clone())),
+            Box::new(IterativeMergeQuotes::new(top_book_depth, 2).with_fees(fees.clone())),
+        ];
+        for merger in mergers.iter_mut() {
+            // 100.00 * 1.001 = 100.1 is worse than 100.05 * 1.0001 = 100.060005
+            let top = merger.merge_quotes(&exchanges_asks, &[], false).unwrap();
+            let ranked: Vec<_> = top
+                .iter()
+                .map(|quote| (quote.exchange, quote.price, quote.effective_price))
+                .collect();
+            assert_eq!(
+                ranked,
+                vec![
+                    (1, dec!(100.05), dec!(100.060005)),
+                    (0, dec!(100.00), dec!(100.1))
+                ]
+            );
+        }
+
+        // bids are marked down
+        let merger = VecSortMergeQuotes::new(top_book_depth, 2).with_fees(fees);
+        let quote = merger.aggregated_quote(0, &exchanges_asks[0][0], true);
+        assert_eq!(quote.effective_price, dec!(99.9));
+
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let mut aggregator =
+            OrderBookAggregator::new(merger, 2, top_book_depth, exchanges_id_mapping);
+        aggregator.process(book_update(
+            0,
+            &[(dec!(99), dec!(1))],
+            &[(dec!(100), dec!(1))],
+            true,
+        ));
+        let summary = aggregator
+            .process(book_update(
+                1,
+                &[(dec!(98), dec!(1))],
+                &[(dec!(100.05), dec!(1))],
+                true,
+            ))
+            .unwrap();
+        assert_eq!(summary.asks[0].exchange, "bitstamp");
+        assert_eq!(summary.asks[0].price, 100.05);
+        assert_eq!(summary.asks[1].price, 100.0);
+        assert_eq!(summary.asks[1].effective_price, 100.1);
+    }
+
+    fn backtest_lines<T: MergeQuotes>(merger: T, top_book_depth: usize) -> Vec<String> {
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let mut backtest = Backtest::new(OrderBookAggregator::new(
+            merger,
+            2,
+            top_book_depth,
+            exchanges_id_mapping,
+        ));
+        let updates = [
+            book_update(0, &[(dec!(99), dec!(1))], &[(dec!(101), dec!(1))], true),
+            book_update(1, &[(dec!(99), dec!(2))], &[(dec!(100), dec!(1))], true),
+            book_update(0, &[(dec!(99.5), dec!(1))], &[(dec!(101), dec!(0))], false),
+            book_update(1, &[(dec!(98), dec!(3))], &[(dec!(102), dec!(1))], false),
+        ];
+        updates
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, mut update)| {
+                update.receive_time = 1672515782136422 + index as u64;
+                backtest.process(update)
+            })
+            .map(|summary| summary_line(&summary))
+            .collect()
+    }
+
+    #[test]
+    fn backtest_mergers_agree() {
+        let iterative = backtest_lines(IterativeMergeQuotes::new(3, 2), 3);
+        let vec_sort = backtest_lines(VecSortMergeQuotes::new(3, 2), 3);
+        assert_eq!(iterative.len(), 4);
+        // aggregation time is set from the receive time
+        assert!(iterative[1].contains("aggregation_time: 1672515782136423"));
+        assert_eq!(first_difference(iterative.clone(), vec_sort), None);
+        assert_eq!(
+            first_difference(
+                iterative.clone(),
+                backtest_lines(IterativeMergeQuotes::new(3, 2), 3)
+            ),
+            None
+        );
+
+        let difference = first_difference(
+            iterative.clone(),
+            backtest_lines(IterativeMergeQuotes::new(1, 2), 1),
+        )
+        .unwrap();
+        // books have one level until the second update
+        assert_eq!(difference.line, 1);
+        assert_eq!(difference.event, Some(2));
+        assert_eq!(difference.left.as_ref(), iterative.get(1));
+
+        let difference = first_difference(iterative.clone(), iterative[..3].to_vec()).unwrap();
+        assert_eq!(difference.line, 3);
+        assert_eq!(difference.event, Some(4));
+        assert_eq!(difference.right, None);
+    }
+
+    fn paper_book(bids: &[(Price, Qty)], asks: &[(Price, Qty)]) -> L2Book {
+        let mut book = L2Book::new();
+        for (price, qty) in bids {
+            book.update_bid(*price, *qty);
+        }
+        for (price, qty) in asks {
+            book.update_ask(*price, *qty);
+        }
+        book
+    }
+
+    fn paper_engine() -> PaperEngine {
+        PaperEngine::new().with_fees(HashMap::from([(
+            1,
+            FeeSchedule::new(dec!(0.0001), dec!(0.001)),
+        )]))
+    }
+
+    #[test]
+    fn market_order_takes_book() {
+        let mut engine = paper_engine();
+        let books = vec![
+            paper_book(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]),
+            paper_book(
+                &[(dec!(99), dec!(1))],
+                &[(dec!(100), dec!(1)), (dec!(101), dec!(1))],
+            ),
+        ];
+
+        let ack = engine
+            .submit(PaperOrder::market(1, Side::Buy, dec!(2.5)), &books)
+            .unwrap();
+        let fills: Vec<_> = ack
+            .fills
+            .iter()
+            .map(|fill| (fill.price, fill.qty, fill.fee, fill.liquidity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (dec!(100), dec!(1), dec!(0.1), Liquidity::Taker),
+                (dec!(101), dec!(1), dec!(0.101), Liquidity::Taker),
+            ]
+        );
+        assert_eq!(ack.cancelled, dec!(0.5));
+        assert!(ack.resting.is_none());
+
+        let position = &engine.positions()[&1];
+        assert_eq!(position.qty, dec!(2));
+        assert_eq!(position.avg_price, dec!(100.5));
+        assert_eq!(position.fees, dec!(0.201));
+        assert!(engine.positions().get(&0).is_none());
+
+        assert!(engine
+            .submit(PaperOrder::market(2, Side::Buy, dec!(1)), &books)
+            .is_err());
+        assert!(engine
+            .submit(PaperOrder::market(0, Side::Buy, dec!(0)), &books)
+            .is_err());
+    }
+
+    #[test]
+    fn resting_limit_queue_position() {
+        let mut engine = paper_engine();
+        let mut books = vec![
+            paper_book(&[], &[]),
+            paper_book(
+                &[(dec!(99), dec!(3)), (dec!(98), dec!(5))],
+                &[(dec!(100), dec!(1))],
+            ),
+        ];
+
+        let ack = engine
+            .submit(PaperOrder::limit(1, Side::Buy, dec!(99), dec!(2)), &books)
+            .unwrap();
+        assert!(ack.fills.is_empty());
+        let resting = ack.resting.unwrap();
+        assert_eq!(resting.queue_ahead, dec!(3));
+
+        // new orders behind, then 4 traded: 3 ahead of the order and 1 of the order
+        books[1].update_bid(dec!(99), dec!(6));
+        assert!(engine.on_book_update(1, &books[1]).is_empty());
+        books[1].update_bid(dec!(99), dec!(2));
+        let fills = engine.on_book_update(1, &books[1]);
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].qty, dec!(1));
+        assert_eq!(fills[0].price, dec!(99));
+        assert_eq!(fills[0].liquidity, Liquidity::Maker);
+        assert_eq!(fills[0].fee, dec!(0.0099));
+        let order = engine.orders().next().unwrap();
+        assert_eq!(order.remaining, dec!(1));
+        assert_eq!(order.queue_ahead, dec!(0));
+
+        // other exchanges do not touch the order
+        assert!(engine.on_book_update(0, &books[0]).is_empty());
+
+        // asks trade through the order price
+        books[1].update_ask(dec!(98.5), dec!(4));
+        let fills = engine.on_book_update(1, &books[1]);
+        assert_eq!(fills[0].qty, dec!(1));
+        assert_eq!(engine.orders().count(), 0);
+        assert_eq!(engine.positions()[&1].qty, dec!(2));
+
+        let ack = engine
+            .submit(PaperOrder::limit(1, Side::Sell, dec!(101), dec!(1)), &books)
+            .unwrap();
+        assert_eq!(engine.cancel(ack.order_id).unwrap().remaining, dec!(1));
+        assert!(engine.cancel(ack.order_id).is_none());
+    }
+
+    #[test]
+    fn resting_limit_consumes_crossing_liquidity() {
+        let mut engine = paper_engine();
+        let mut books = vec![
+            paper_book(&[], &[]),
+            paper_book(&[(dec!(99), dec!(1))], &[(dec!(100), dec!(1))]),
+        ];
+
+        // takes the 100 ask, the rest rests at 101
+        let ack = engine
+            .submit(PaperOrder::limit(1, Side::Buy, dec!(101), dec!(3)), &books)
+            .unwrap();
+        assert_eq!(ack.fills.len(), 1);
+        assert_eq!(ack.resting.unwrap().remaining, dec!(2));
+
+        // the same book again, the ask is already taken
+        assert!(engine.on_book_update(1, &books[1]).is_empty());
+
+        // qty added to the taken level and a new level
+        books[1].update_ask(dec!(100), dec!(1.5));
+        books[1].update_ask(dec!(100.5), dec!(0.25));
+        let fills = engine.on_book_update(1, &books[1]);
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].qty, dec!(0.75));
+        assert_eq!(fills[0].liquidity, Liquidity::Maker);
+        assert!(engine.on_book_update(1, &books[1]).is_empty());
+        assert_eq!(engine.orders().next().unwrap().remaining, dec!(1.25));
+
+        // the level is traded by others down to 1, then refilled to 2
+        books[1].update_ask(dec!(100), dec!(1));
+        assert!(engine.on_book_update(1, &books[1]).is_empty());
+        books[1].update_ask(dec!(100), dec!(2));
+        let fills = engine.on_book_update(1, &books[1]);
+        assert_eq!(fills[0].qty, dec!(1));
+        assert_eq!(engine.orders().next().unwrap().remaining, dec!(0.25));
+    }
+
+    #[test]
+    fn position_pnl() {
+        let mut engine = PaperEngine::new();
+        let books = vec![paper_book(
+            &[(dec!(110), dec!(5))],
+            &[(dec!(100), dec!(1)), (dec!(102), dec!(1))],
+        )];
+
+        engine
+            .submit(PaperOrder::market(0, Side::Buy, dec!(2)), &books)
+            .unwrap();
+        // sells 2 long at 101 average and goes 1 short
+        engine
+            .submit(PaperOrder::limit(0, Side::Sell, dec!(105), dec!(3)), &books)
+            .unwrap();
+
+        let position = &engine.positions()[&0];
+        assert_eq!(position.realized_pnl, dec!(18));
+        assert_eq!(position.qty, dec!(-1));
+        assert_eq!(position.avg_price, dec!(110));
+        assert_eq!(position.unrealized_pnl(dec!(108)), dec!(2));
+    }
 }
@@ -0,0 +1,373 @@
+use crate::aggregation::fees::FeeSchedule;
+use crate::common::clock::{Clock, SystemClock};
+use crate::common::model::{Price, Qty, Side};
+use crate::orderbook::book::L2Book;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderType {
+    /// Takes the exchange book, the part above the book depth is cancelled.
+    Market,
+    /// Takes the book up to the price, the rest rests at the price.
+    Limit(Price),
+}
+
+/// Simulated order on one exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaperOrder {
+    pub exchange: usize,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub qty: Qty,
+}
+
+impl PaperOrder {
+    pub fn market(exchange: usize, side: Side, qty: Qty) -> Self {
+        Self {
+            exchange,
+            side,
+            order_type: OrderType::Market,
+            qty,
+        }
+    }
+
+    pub fn limit(exchange: usize, side: Side, price: Price, qty: Qty) -> Self {
+        Self {
+            exchange,
+            side,
+            order_type: OrderType::Limit(price),
+            qty,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fill {
+    pub order_id: u64,
+    pub exchange: usize,
+    pub side: Side,
+    pub price: Price,
+    pub qty: Qty,
+    pub fee: Decimal,
+    pub liquidity: Liquidity,
+    /// Clock time in microseconds since epoch.
+    pub time: u64,
+}
+
+/// Limit order waiting in the exchange book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestingOrder {
+    pub order_id: u64,
+    pub exchange: usize,
+    pub side: Side,
+    pub price: Price,
+    pub qty: Qty,
+    pub remaining: Qty,
+    /// Estimated qty of the level queued before the order.
+    pub queue_ahead: Qty,
+    /// Level qty at the last book update.
+    level_qty: Qty,
+    /// Qty of crossing opposite levels already filled by the order, by price.
+    consumed: BTreeMap<Price, Qty>,
+}
+
+/// Result of a submitted order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderAck {
+    pub order_id: u64,
+    pub fills: Vec<Fill>,
+    /// Resting part of a limit order.
+    pub resting: Option<RestingOrder>,
+    /// Part of a market order above the book depth.
+    pub cancelled: Qty,
+}
+
+/// Position of one exchange, average cost basis.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Position {
+    /// Negative for a short position.
+    pub qty: Qty,
+    pub avg_price: Price,
+    pub realized_pnl: Decimal,
+    pub fees: Decimal,
+}
+
+impl Position {
+    fn apply(&mut self, fill: &Fill) {
+        let signed_qty = match fill.side {
+            Side::Buy => fill.qty,
+            Side::Sell => -fill.qty,
+        };
+        self.fees += fill.fee;
+        if self.qty.is_zero() || self.qty.is_sign_positive() == signed_qty.is_sign_positive() {
+            let qty = self.qty.abs() + fill.qty;
+            self.avg_price = (self.avg_price * self.qty.abs() + fill.price * fill.qty) / qty;
+            self.qty += signed_qty;
+            return;
+        }
+
+        let closed = fill.qty.min(self.qty.abs());
+        let pnl = (fill.price - self.avg_price) * closed;
+        self.realized_pnl += if self.qty.is_sign_positive() {
+            pnl
+        } else {
+            -pnl
+        };
+        self.qty += signed_qty;
+        if self.qty.is_zero() {
+            self.avg_price = Price::ZERO;
+        } else if fill.qty > closed {
+            // position is reversed at the fill price
+            self.avg_price = fill.price;
+        }
+    }
+
+    /// PnL of the open position at `mark_price`.
+    pub fn unrealized_pnl(&self, mark_price: Price) -> Decimal {
+        (mark_price - self.avg_price) * self.qty
+    }
+}
+
+/// Fills simulated orders against exchange books of one symbol.
+///
+/// Orders do not change the books: every order takes the liquidity the exchange shows. A resting
+/// limit order is filled when the opposite side crosses its price or when its level shrinks by
+/// more than the qty queued ahead of it, decreases of the level are treated as trades from the
+/// front of the queue. Crossing liquidity a resting order has filled is not filled again, only
+/// new or increased qty of a crossing level is.
+pub struct PaperEngine {
+    fees: HashMap<usize, FeeSchedule>,
+    clock: Arc<dyn Clock>,
+    next_order_id: u64,
+    orders: BTreeMap<u64, RestingOrder>,
+    positions: BTreeMap<usize, Position>,
+}
+
+impl Default for PaperEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PaperEngine {
+    pub fn new() -> Self {
+        Self {
+            fees: HashMap::new(),
+            clock: Arc::new(SystemClock),
+            next_order_id: 1,
+            orders: BTreeMap::new(),
+            positions: BTreeMap::new(),
+        }
+    }
+
+    /// Maker and taker fee rates of every exchange, no fees by default.
+    pub fn with_fees(mut self, fees: HashMap<usize, FeeSchedule>) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    /// Clock of fill times.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn orders(&self) -> impl Iterator<Item = &RestingOrder> {
+        self.orders.values()
+    }
+
+    /// Positions of exchanges with fills.
+    pub fn positions(&self) -> &BTreeMap<usize, Position> {
+        &self.positions
+    }
+
+    /// Takes the opposite side of the exchange book, the rest of a limit order rests in the book.
+    pub fn submit(&mut self, order: PaperOrder, books: &[L2Book]) -> Result<OrderAck, String> {
+        let book = books
+            .get(order.exchange)
+            .ok_or_else(|| format!("unknown exchange id {}", order.exchange))?;
+        if order.qty <= Qty::ZERO {
+            return Err(format!("qty must be positive, got {}", order.qty));
+        }
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+
+        let levels: Vec<_> = match order.side {
+            Side::Buy => book.asks().collect(),
+            Side::Sell => book.bids().collect(),
+        };
+        let mut remaining = order.qty;
+        let mut fills = vec![];
+        for level in levels {
+            if remaining.is_zero() {
+                break;
+            }
+            let crossed = match (order.order_type, order.side) {
+                (OrderType::Market, _) => true,
+                (OrderType::Limit(price), Side::Buy) => level.price <= price,
+                (OrderType::Limit(price), Side::Sell) => level.price >= price,
+            };
+            if !crossed {
+                break;
+            }
+            let qty = level.qty.min(remaining);
+            remaining -= qty;
+            fills.push(self.fill(order_id, &order, level.price, qty, Liquidity::Taker));
+        }
+
+        let mut ack = OrderAck {
+            order_id,
+            fills,
+            resting: None,
+            cancelled: Qty::ZERO,
+        };
+        match order.order_type {
+            _ if remaining.is_zero() => {}
+            OrderType::Market => ack.cancelled = remaining,
+            OrderType::Limit(price) => {
+                let level_qty = match order.side {
+                    Side::Buy => book.bid_qty(price),
+                    Side::Sell => book.ask_qty(price),
+                };
+                let resting = RestingOrder {
+                    order_id,
+                    exchange: order.exchange,
+                    side: order.side,
+                    price,
+                    qty: order.qty,
+                    remaining,
+                    queue_ahead: level_qty,
+                    level_qty,
+                    // the order took every crossing level
+                    consumed: ack
+                        .fills
+                        .iter()
+                        .map(|fill| (fill.price, fill.qty))
+                        .collect(),
+                };
+                self.orders.insert(order_id, resting.clone());
+                ack.resting = Some(resting);
+            }
+        }
+        Ok(ack)
+    }
+
+    pub fn cancel(&mut self, order_id: u64) -> Option<RestingOrder> {
+        self.orders.remove(&order_id)
+    }
+
+    /// Fills resting orders of `exchange` after its book changed.
+    pub fn on_book_update(&mut self, exchange: usize, book: &L2Book) -> Vec<Fill> {
+        let mut fills = vec![];
+        let order_ids: Vec<u64> = self
+            .orders
+            .values()
+            .filter(|order| order.exchange == exchange)
+            .map(|order| order.order_id)
+            .collect();
+        for order_id in order_ids {
+            let mut order = self.orders.remove(&order_id).unwrap();
+            let fill_qty = Self::resting_fill_qty(&mut order, book);
+            if !fill_qty.is_zero() {
+                order.remaining -= fill_qty;
+                let paper_order =
+                    PaperOrder::limit(order.exchange, order.side, order.price, fill_qty);
+                fills.push(self.fill(
+                    order_id,
+                    &paper_order,
+                    order.price,
+                    fill_qty,
+                    Liquidity::Maker,
+                ));
+            }
+            if !order.remaining.is_zero() {
+                self.orders.insert(order_id, order);
+            }
+        }
+        fills
+    }
+
+    /// Qty of a resting order filled by the book change, updates the queue estimate.
+    fn resting_fill_qty(order: &mut RestingOrder, book: &L2Book) -> Qty {
+        // the opposite side trades through the order price
+        let crossing: Vec<_> = match order.side {
+            Side::Buy => book
+                .asks()
+                .take_while(|level| level.price <= order.price)
+                .collect(),
+            Side::Sell => book
+                .bids()
+                .take_while(|level| level.price >= order.price)
+                .collect(),
+        };
+        if !crossing.is_empty() {
+            order.queue_ahead = Qty::ZERO;
+            let mut fill_qty = Qty::ZERO;
+            let mut consumed = BTreeMap::new();
+            for level in crossing {
+                // a shrinking level is traded by others first
+                let taken = match order.consumed.get(&level.price) {
+                    Some(val) => level.qty.min(*val),
+                    None => Qty::ZERO,
+                };
+                let qty = (level.qty - taken).min(order.remaining - fill_qty);
+                fill_qty += qty;
+                consumed.insert(level.price, taken + qty);
+            }
+            order.consumed = consumed;
+            return fill_qty;
+        }
+
+        let level_qty = match order.side {
+            Side::Buy => book.bid_qty(order.price),
+            Side::Sell => book.ask_qty(order.price),
+        };
+        let decrease = order.level_qty - level_qty;
+        order.level_qty = level_qty;
+        if decrease <= Qty::ZERO {
+            // new orders queue behind
+            return Qty::ZERO;
+        }
+        let traded_ahead = decrease.min(order.queue_ahead);
+        order.queue_ahead -= traded_ahead;
+        (decrease - traded_ahead).min(order.remaining)
+    }
+
+    fn fill(
+        &mut self,
+        order_id: u64,
+        order: &PaperOrder,
+        price: Price,
+        qty: Qty,
+        liquidity: Liquidity,
+    ) -> Fill {
+        let fees = self.fees.get(&order.exchange).copied().unwrap_or_default();
+        let rate = match liquidity {
+            Liquidity::Maker => fees.maker,
+            Liquidity::Taker => fees.taker,
+        };
+        let fill = Fill {
+            order_id,
+            exchange: order.exchange,
+            side: order.side,
+            price,
+            qty,
+            fee: price * qty * rate,
+            liquidity,
+            time: self.clock.now_micros(),
+        };
+        self.positions
+            .entry(order.exchange)
+            .or_default()
+            .apply(&fill);
+        fill
+    }
+}
@@ -1,6 +1,6 @@
+use crate::aggregation::fees::FeeSchedule;
 use crate::common::model::{AggregatedBookQuote, ExchangeQuote};
 use std::cmp::Ordering;
-use tracing::info;
 
 pub trait MergeQuotes {
     fn merge_quotes(
@@ -9,11 +9,39 @@ pub trait MergeQuotes {
         old_top: &[AggregatedBookQuote],
         reverse_ordering: bool,
     ) -> Option<Vec<AggregatedBookQuote>>;
+
+    /// Quote with the effective price used for ranking. `reverse_ordering` is set for bids.
+    fn aggregated_quote(
+        &self,
+        exchange: usize,
+        quote: &ExchangeQuote,
+        reverse_ordering: bool,
+    ) -> AggregatedBookQuote;
+}
+
+/// Fee schedules are indexed by exchange id, exchanges without schedule are ranked by raw price.
+fn fee_adjusted_quote(
+    fees: &[FeeSchedule],
+    exchange: usize,
+    quote: &ExchangeQuote,
+    reverse_ordering: bool,
+) -> AggregatedBookQuote {
+    let effective_price = match fees.get(exchange) {
+        Some(fee_schedule) => fee_schedule.effective_price(quote.price, reverse_ordering),
+        None => quote.price,
+    };
+    AggregatedBookQuote {
+        exchange,
+        price: quote.price,
+        effective_price,
+        qty: quote.qty,
+    }
 }
 
 pub struct VecSortMergeQuotes {
     storage: Vec<AggregatedBookQuote>,
     top_book_depth: usize,
+    fees: Vec<FeeSchedule>,
 }
 
 impl VecSortMergeQuotes {
@@ -23,8 +51,15 @@ impl VecSortMergeQuotes {
         Self {
             storage,
             top_book_depth,
+            fees: vec![],
         }
     }
+
+    /// Ranks quotes by the fee-inclusive price.
+    pub fn with_fees(mut self, fees: Vec<FeeSchedule>) -> Self {
+        self.fees = fees;
+        self
+    }
 }
 
 impl MergeQuotes for VecSortMergeQuotes {
@@ -41,11 +76,8 @@ impl MergeQuotes for VecSortMergeQuotes {
                 if i >= self.top_book_depth {
                     continue 'exchange_book_loop;
                 }
-                self.storage.push(AggregatedBookQuote {
-                    exchange: exchange_id,
-                    price: quote.price,
-                    qty: quote.qty,
-                })
+                let agg_quote = self.aggregated_quote(exchange_id, quote, reverse_ordering);
+                self.storage.push(agg_quote)
             }
         }
 
@@ -76,6 +108,15 @@ impl MergeQuotes for VecSortMergeQuotes {
 
         None
     }
+
+    fn aggregated_quote(
+        &self,
+        exchange: usize,
+        quote: &ExchangeQuote,
+        reverse_ordering: bool,
+    ) -> AggregatedBookQuote {
+        fee_adjusted_quote(&self.fees, exchange, quote, reverse_ordering)
+    }
 }
 
 pub struct IterativeMergeQuotes {
@@ -84,6 +125,7 @@ pub struct IterativeMergeQuotes {
     empty_indexes: Vec<usize>,
     top_book_depth: usize,
     exchanges_number: usize,
+    fees: Vec<FeeSchedule>,
 }
 
 impl IterativeMergeQuotes {
@@ -97,8 +139,15 @@ impl IterativeMergeQuotes {
             empty_indexes,
             top_book_depth,
             exchanges_number,
+            fees: vec![],
         }
     }
+
+    /// Ranks quotes by the fee-inclusive price.
+    pub fn with_fees(mut self, fees: Vec<FeeSchedule>) -> Self {
+        self.fees = fees;
+        self
+    }
 }
 
 impl MergeQuotes for IterativeMergeQuotes {
@@ -118,7 +167,6 @@ impl MergeQuotes for IterativeMergeQuotes {
         let mut best_value;
         let mut best_value_exchange;
         let mut best_value_quote_index;
-        let mut top_book_changed = false;
 
         let ordering = if reverse_ordering {
             Ordering::Greater
@@ -133,7 +181,11 @@ impl MergeQuotes for IterativeMergeQuotes {
             }
         }
         if exchanges_quotes_empty {
-            return None;
+            // quotes of all exchanges were dropped, e.g. every exchange is stale
+            if old_top.is_empty() {
+                return None;
+            }
+            return Some(vec![]);
         }
 
         'merge_loop: loop {
@@ -156,44 +208,30 @@ impl MergeQuotes for IterativeMergeQuotes {
                     },
                     None => continue 'index_key_loop,
                 };
-                let agg_quote = AggregatedBookQuote {
-                    exchange: exchange_key,
-                    price: value.price,
-                    qty: value.qty,
-                };
+                let agg_quote = self.aggregated_quote(exchange_key, value, reverse_ordering);
                 match &best_value {
                     Some(val) => {
                         if agg_quote.cmp(val) == ordering {
                             best_value = Some(agg_quote);
                             best_value_exchange = Some(exchange_key);
-                            best_value_quote_index = Some(index.clone())
+                            best_value_quote_index = Some(*index)
                         }
                     }
                     None => {
                         best_value = Some(agg_quote);
                         best_value_exchange = Some(exchange_key);
-                        best_value_quote_index = Some(index.clone());
+                        best_value_quote_index = Some(*index);
                         continue 'index_key_loop;
                     }
                 }
             }
 
+            // exchanges without quotes never get into empty_indexes, so quotes of
+            // all exchanges are exhausted when nothing is found
             if best_value_exchange.is_none() || best_value_quote_index.is_none() {
-                info!("min_key or min_key_index is none");
-                continue 'merge_loop;
+                break 'merge_loop;
             }
 
-            let old_value = old_top.get(self.top_of_book.len());
-            match old_value {
-                None => {
-                    top_book_changed = true;
-                }
-                Some(val) => {
-                    if !val.eq(best_value.as_ref().unwrap()) {
-                        top_book_changed = true
-                    }
-                }
-            }
             self.top_of_book.push(best_value.unwrap());
             if self.top_of_book.len() >= self.top_book_depth {
                 break 'merge_loop;
@@ -212,10 +250,20 @@ impl MergeQuotes for IterativeMergeQuotes {
                 break 'merge_loop;
             }
         }
-        if top_book_changed {
+        // a changed level or levels dropped from the end of the old top
+        if self.top_of_book.as_slice() != old_top {
             Some(self.top_of_book.clone())
         } else {
             None
         }
     }
+
+    fn aggregated_quote(
+        &self,
+        exchange: usize,
+        quote: &ExchangeQuote,
+        reverse_ordering: bool,
+    ) -> AggregatedBookQuote {
+        fee_adjusted_quote(&self.fees, exchange, quote, reverse_ordering)
+    }
 }
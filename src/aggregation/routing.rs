@@ -0,0 +1,299 @@
+use crate::aggregation::fees::FeeSchedule;
+use crate::common::model::{ExchangeQuote, Price, Qty, Side};
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap};
+
+/// Order constraints of an exchange, zero `min_size` and `lot_size` mean no constraint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VenueRules {
+    /// Taker fee is charged on every child order.
+    pub fees: FeeSchedule,
+    pub min_size: Qty,
+    pub lot_size: Qty,
+}
+
+impl VenueRules {
+    pub fn new(fees: FeeSchedule, min_size: Qty, lot_size: Qty) -> Self {
+        Self {
+            fees,
+            min_size,
+            lot_size,
+        }
+    }
+}
+
+/// Order to split across exchanges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParentOrder {
+    pub side: Side,
+    pub qty: Qty,
+    /// Worst price of child orders, levels beyond it are not taken.
+    pub limit_price: Option<Price>,
+    /// Exchanges without rules have no fees and no size constraints.
+    pub venues: HashMap<usize, VenueRules>,
+    /// Exchanges to route to, all exchanges when `None`.
+    pub exchanges: Option<BTreeSet<usize>>,
+}
+
+impl ParentOrder {
+    pub fn new(side: Side, qty: Qty) -> Self {
+        Self {
+            side,
+            qty,
+            limit_price: None,
+            venues: HashMap::new(),
+            exchanges: None,
+        }
+    }
+
+    pub fn with_limit_price(mut self, limit_price: Price) -> Self {
+        self.limit_price = Some(limit_price);
+        self
+    }
+
+    pub fn with_venue(mut self, exchange: usize, rules: VenueRules) -> Self {
+        self.venues.insert(exchange, rules);
+        self
+    }
+
+    pub fn with_exchanges(mut self, exchanges: BTreeSet<usize>) -> Self {
+        self.exchanges = Some(exchanges);
+        self
+    }
+
+    fn within_limit(&self, price: Price) -> bool {
+        match (self.limit_price, self.side) {
+            (None, _) => true,
+            (Some(limit_price), Side::Buy) => price <= limit_price,
+            (Some(limit_price), Side::Sell) => price >= limit_price,
+        }
+    }
+}
+
+/// Marketable limit order of one exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChildOrder {
+    pub exchange: usize,
+    pub qty: Qty,
+    /// Worst price of the levels taken.
+    pub limit_price: Price,
+    pub notional: Decimal,
+    pub fees: Decimal,
+}
+
+impl ChildOrder {
+    pub fn vwap(&self) -> Price {
+        self.notional / self.qty
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutePlan {
+    /// Ordered by exchange id.
+    pub child_orders: Vec<ChildOrder>,
+    pub qty: Qty,
+    pub notional: Decimal,
+    pub fees: Decimal,
+    /// Qty not available within the limit price and size constraints.
+    pub unfilled: Qty,
+}
+
+impl RoutePlan {
+    pub fn vwap(&self) -> Option<Price> {
+        (!self.qty.is_zero()).then(|| self.notional / self.qty)
+    }
+}
+
+/// Part of an exchange book taken at once.
+struct Chunk {
+    /// Index of the exchange cursor.
+    cursor: usize,
+    qty: Qty,
+    notional: Decimal,
+    worst_price: Price,
+    effective_price: Price,
+}
+
+/// Position of a child order in the exchange book.
+struct VenueCursor<'a> {
+    exchange: usize,
+    quotes: &'a [ExchangeQuote],
+    rules: VenueRules,
+    level: usize,
+    /// Qty taken from `level`.
+    level_used: Qty,
+    order: Option<ChildOrder>,
+}
+
+impl<'a> VenueCursor<'a> {
+    /// Notional and worst price of the next `qty` in the book, `None` when the book within the
+    /// limit price is not deep enough.
+    fn cost(&self, qty: Qty, parent: &ParentOrder) -> Option<(Decimal, Price)> {
+        let mut remaining = qty;
+        let mut notional = Decimal::ZERO;
+        let mut used = self.level_used;
+        for quote in &self.quotes[self.level..] {
+            if !parent.within_limit(quote.price) {
+                return None;
+            }
+            let level_qty = (quote.qty - used).min(remaining);
+            used = Qty::ZERO;
+            remaining -= level_qty;
+            notional += level_qty * quote.price;
+            if remaining.is_zero() {
+                return Some((notional, quote.price));
+            }
+        }
+        None
+    }
+
+    /// Qty of the next chunk: the rest of the current level rounded down to lots, or one lot
+    /// across levels when the level has less than a lot.
+    fn next_chunk(&self, remaining: Qty, parent: &ParentOrder) -> Option<Qty> {
+        let quote = self.quotes.get(self.level)?;
+        if !parent.within_limit(quote.price) {
+            return None;
+        }
+        let available = (quote.qty - self.level_used).min(remaining);
+        let lot_size = self.rules.lot_size;
+        if lot_size <= Qty::ZERO {
+            return Some(available);
+        }
+        let lots = (available / lot_size).floor();
+        if lots >= Decimal::ONE {
+            return Some(lots * lot_size);
+        }
+        (remaining >= lot_size).then_some(lot_size)
+    }
+
+    /// Next chunk with its fee-inclusive price.
+    fn chunk(&self, cursor: usize, remaining: Qty, parent: &ParentOrder) -> Option<Chunk> {
+        let qty = self.next_chunk(remaining, parent)?;
+        let (notional, worst_price) = self.cost(qty, parent)?;
+        let effective_price = self
+            .rules
+            .fees
+            .effective_price(notional / qty, parent.side == Side::Sell);
+        Some(Chunk {
+            cursor,
+            qty,
+            notional,
+            worst_price,
+            effective_price,
+        })
+    }
+
+    fn take(&mut self, chunk: &Chunk) {
+        let mut remaining = chunk.qty;
+        while !remaining.is_zero() {
+            let level_qty = (self.quotes[self.level].qty - self.level_used).min(remaining);
+            remaining -= level_qty;
+            self.level_used += level_qty;
+            if self.level_used == self.quotes[self.level].qty {
+                self.level += 1;
+                self.level_used = Qty::ZERO;
+            }
+        }
+        let order = self.order.get_or_insert(ChildOrder {
+            exchange: self.exchange,
+            qty: Qty::ZERO,
+            limit_price: chunk.worst_price,
+            notional: Decimal::ZERO,
+            fees: Decimal::ZERO,
+        });
+        order.qty += chunk.qty;
+        order.limit_price = chunk.worst_price;
+        order.notional += chunk.notional;
+        order.fees += chunk.notional * self.rules.fees.taker;
+    }
+}
+
+/// Splits `parent` across exchange books of the opposite side (asks for a buy order), every
+/// level of `exchanges_quotes` is ordered from the best price.
+///
+/// Chunks of the books are taken in the order of the fee-inclusive price, child order sizes are
+/// whole lots. Exchanges with a child order below the venue min size are dropped and the order
+/// is split again without them.
+pub fn route(parent: &ParentOrder, exchanges_quotes: &[Vec<ExchangeQuote>]) -> RoutePlan {
+    let mut excluded = BTreeSet::new();
+    loop {
+        let plan = route_excluding(parent, exchanges_quotes, &excluded);
+        let below_min_size: Vec<usize> = plan
+            .child_orders
+            .iter()
+            .filter(|order| {
+                let rules = parent.venues.get(&order.exchange).copied();
+                order.qty < rules.unwrap_or_default().min_size
+            })
+            .map(|order| order.exchange)
+            .collect();
+        if below_min_size.is_empty() {
+            return plan;
+        }
+        excluded.extend(below_min_size);
+    }
+}
+
+fn route_excluding(
+    parent: &ParentOrder,
+    exchanges_quotes: &[Vec<ExchangeQuote>],
+    excluded: &BTreeSet<usize>,
+) -> RoutePlan {
+    let is_bid = parent.side == Side::Sell;
+    let mut cursors: Vec<VenueCursor> = exchanges_quotes
+        .iter()
+        .enumerate()
+        .filter(|(exchange, _)| {
+            !excluded.contains(exchange)
+                && parent
+                    .exchanges
+                    .as_ref()
+                    .is_none_or(|exchanges| exchanges.contains(exchange))
+        })
+        .map(|(exchange, quotes)| VenueCursor {
+            exchange,
+            quotes,
+            rules: parent.venues.get(&exchange).copied().unwrap_or_default(),
+            level: 0,
+            level_used: Qty::ZERO,
+            order: None,
+        })
+        .collect();
+
+    let mut remaining = parent.qty;
+    while remaining > Qty::ZERO {
+        let mut best: Option<Chunk> = None;
+        for (index, cursor) in cursors.iter().enumerate() {
+            let chunk = match cursor.chunk(index, remaining, parent) {
+                Some(val) => val,
+                None => continue,
+            };
+            let better = match &best {
+                None => true,
+                Some(best) if is_bid => chunk.effective_price > best.effective_price,
+                Some(best) => chunk.effective_price < best.effective_price,
+            };
+            if better {
+                best = Some(chunk);
+            }
+        }
+        let chunk = match best {
+            Some(val) => val,
+            None => break,
+        };
+        cursors[chunk.cursor].take(&chunk);
+        remaining -= chunk.qty;
+    }
+
+    let child_orders: Vec<ChildOrder> = cursors
+        .into_iter()
+        .filter_map(|cursor| cursor.order)
+        .collect();
+    RoutePlan {
+        qty: child_orders.iter().map(|order| order.qty).sum(),
+        notional: child_orders.iter().map(|order| order.notional).sum(),
+        fees: child_orders.iter().map(|order| order.fees).sum(),
+        unfilled: remaining,
+        child_orders,
+    }
+}
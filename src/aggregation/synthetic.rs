@@ -0,0 +1,233 @@
+use crate::common::model::{ExchangeQuote, OrderBookUpdate, Price, Qty};
+use crate::orderbook::book::L2Book;
+use std::collections::{BTreeMap, HashMap};
+
+/// Exchange name of the synthetic book in `Summary`.
+pub const NAME: &str = "synthetic";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leg {
+    /// `BASE/X` book of `BASE/QUOTE` cross.
+    Base,
+    /// `QUOTE/X` book of `BASE/QUOTE` cross.
+    Quote,
+}
+
+/// Implied levels of a cross from two legs quoted in the same currency.
+///
+/// Implied bids sell base on base leg bids and buy quote on quote leg asks,
+/// implied asks buy base on base leg asks and sell quote on quote leg bids.
+/// Liquidity of both legs is consumed level by level, so qty (in base) is limited by the thinner leg.
+pub fn implied_levels(
+    base_side: &[ExchangeQuote],
+    quote_side: &[ExchangeQuote],
+    depth: usize,
+) -> Vec<ExchangeQuote> {
+    let mut levels: Vec<ExchangeQuote> = Vec::with_capacity(depth);
+    let mut base_quotes = base_side.iter().filter(|q| !q.price.is_zero()).cloned();
+    let mut quote_quotes = quote_side.iter().filter(|q| !q.price.is_zero()).cloned();
+    let mut base = base_quotes.next();
+    let mut quote = quote_quotes.next();
+
+    while let (Some(base_quote), Some(quote_quote)) = (base.as_mut(), quote.as_mut()) {
+        let base_notional = base_quote.price * base_quote.qty;
+        let quote_notional = quote_quote.price * quote_quote.qty;
+        let notional = base_notional.min(quote_notional);
+        let price = base_quote.price / quote_quote.price;
+        let qty = notional / base_quote.price;
+
+        match levels.last_mut() {
+            Some(last) if last.price == price => last.qty += qty,
+            _ => {
+                if levels.len() >= depth {
+                    break;
+                }
+                levels.push(ExchangeQuote { price, qty })
+            }
+        }
+
+        if base_notional <= quote_notional {
+            base = base_quotes.next();
+        } else {
+            base_quote.qty -= qty;
+        }
+        if quote_notional <= base_notional {
+            quote = quote_quotes.next();
+        } else {
+            quote_quote.qty -= notional / quote_quote.price;
+        }
+    }
+    levels
+}
+
+/// Builds `BASE/QUOTE` book from `BASE/X` and `QUOTE/X` books of several exchanges and publishes it
+/// as one more exchange of `OrderBookAggregator`.
+pub struct SyntheticCross {
+    exchange_id: usize,
+    legs: HashMap<usize, Leg>,
+    books: HashMap<usize, L2Book>,
+    top_book_depth: usize,
+}
+
+impl SyntheticCross {
+    /// `exchange_id` of the synthetic exchange in `OrderBookAggregator`.
+    pub fn new(exchange_id: usize, top_book_depth: usize) -> Self {
+        Self {
+            exchange_id,
+            legs: HashMap::new(),
+            books: HashMap::new(),
+            top_book_depth,
+        }
+    }
+
+    /// Leg book published with `exchange_id`. Books of the same leg are summed by price.
+    pub fn with_leg(mut self, exchange_id: usize, leg: Leg) -> Self {
+        self.legs.insert(exchange_id, leg);
+        self
+    }
+
+    /// Leg updates are replaced with the synthetic book snapshot, other updates pass through.
+    pub fn process(&mut self, order_book_update: OrderBookUpdate) -> Vec<OrderBookUpdate> {
+        let exchange_id = match order_book_update.exchange_id {
+            Some(val) if self.legs.contains_key(&val) => val,
+            _ => return vec![order_book_update],
+        };
+        self.books
+            .entry(exchange_id)
+            .or_default()
+            .apply(&order_book_update);
+
+        let base_bids = self.leg_side(Leg::Base, true);
+        let base_asks = self.leg_side(Leg::Base, false);
+        let quote_bids = self.leg_side(Leg::Quote, true);
+        let quote_asks = self.leg_side(Leg::Quote, false);
+        vec![OrderBookUpdate {
+            exchange_id: Some(self.exchange_id),
+            bid_changes: implied_levels(&base_bids, &quote_asks, self.top_book_depth),
+            ask_changes: implied_levels(&base_asks, &quote_bids, self.top_book_depth),
+            is_snapshot: true,
+            exchange_time: order_book_update.exchange_time,
+            receive_time: order_book_update.receive_time,
+        }]
+    }
+
+    /// Top levels of the leg summed across exchanges, from the best price.
+    fn leg_side(&self, leg: Leg, is_bid: bool) -> Vec<ExchangeQuote> {
+        let mut levels: BTreeMap<Price, Qty> = BTreeMap::new();
+        for (exchange_id, book) in &self.books {
+            if self.legs[exchange_id] != leg {
+                continue;
+            }
+            let quotes = if is_bid {
+                book.top_bids(self.top_book_depth)
+            } else {
+                book.top_asks(self.top_book_depth)
+            };
+            for quote in quotes {
+                *levels.entry(quote.price).or_default() += quote.qty;
+            }
+        }
+        let to_quote = |(price, qty): (&Price, &Qty)| ExchangeQuote {
+            price: *price,
+            qty: *qty,
+        };
+        if is_bid {
+            levels.iter().rev().map(to_quote).collect()
+        } else {
+            levels.iter().map(to_quote).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{implied_levels, Leg, SyntheticCross};
+    use crate::common::model::{ExchangeQuote, OrderBookUpdate, Price, Qty};
+    use rust_decimal_macros::dec;
+
+    fn quotes(levels: &[(Price, Qty)]) -> Vec<ExchangeQuote> {
+        levels
+            .iter()
+            .map(|(price, qty)| ExchangeQuote {
+                price: *price,
+                qty: *qty,
+            })
+            .collect()
+    }
+
+    fn levels(quotes: &[ExchangeQuote]) -> Vec<(Price, Qty)> {
+        quotes.iter().map(|q| (q.price, q.qty)).collect()
+    }
+
+    #[test]
+    fn walk_both_legs() {
+        // ETH/USD bids and BTC/USD asks give ETH/BTC bids
+        let eth_bids = quotes(&[(dec!(2000), dec!(1)), (dec!(1990), dec!(2))]);
+        let btc_asks = quotes(&[(dec!(40000), dec!(0.03)), (dec!(50000), dec!(1))]);
+        assert_eq!(
+            levels(&implied_levels(&eth_bids, &btc_asks, 10)),
+            vec![
+                // 1200 USD of the first BTC level buys 0.6 ETH
+                (dec!(0.05), dec!(0.6)),
+                (dec!(0.04), dec!(0.4)),
+                (dec!(0.0398), dec!(2)),
+            ]
+        );
+        assert_eq!(implied_levels(&eth_bids, &btc_asks, 2).len(), 2);
+        assert!(implied_levels(&eth_bids, &[], 10).is_empty());
+    }
+
+    #[test]
+    fn synthetic_exchange_updates() {
+        let mut cross = SyntheticCross::new(2, 10)
+            .with_leg(3, Leg::Base)
+            .with_leg(4, Leg::Quote)
+            .with_leg(5, Leg::Quote);
+
+        let passed = cross.process(OrderBookUpdate {
+            exchange_id: Some(0),
+            bid_changes: vec![],
+            ask_changes: vec![],
+            is_snapshot: true,
+            exchange_time: None,
+            receive_time: 0,
+        });
+        assert_eq!(passed[0].exchange_id, Some(0));
+
+        cross.process(OrderBookUpdate {
+            exchange_id: Some(3),
+            bid_changes: quotes(&[(dec!(2000), dec!(1))]),
+            ask_changes: quotes(&[(dec!(2010), dec!(1))]),
+            is_snapshot: true,
+            exchange_time: None,
+            receive_time: 0,
+        });
+        cross.process(OrderBookUpdate {
+            exchange_id: Some(4),
+            bid_changes: quotes(&[(dec!(39000), dec!(0.01))]),
+            ask_changes: quotes(&[(dec!(40000), dec!(0.01))]),
+            is_snapshot: true,
+            exchange_time: None,
+            receive_time: 0,
+        });
+        let updates = cross.process(OrderBookUpdate {
+            exchange_id: Some(5),
+            bid_changes: quotes(&[(dec!(39000), dec!(0.02))]),
+            ask_changes: quotes(&[(dec!(40000), dec!(0.04))]),
+            is_snapshot: true,
+            exchange_time: None,
+            receive_time: 0,
+        });
+
+        assert_eq!(updates.len(), 1);
+        let synthetic = &updates[0];
+        assert_eq!(synthetic.exchange_id, Some(2));
+        assert!(synthetic.is_snapshot);
+        // quote leg books are summed: 0.05 BTC at 40000 is 2000 USD, 1 ETH
+        assert_eq!(levels(&synthetic.bid_changes), vec![(dec!(0.05), dec!(1))]);
+        // 0.03 BTC at 39000 is 1170 USD, 1170 / 2010 ETH
+        assert_eq!(synthetic.ask_changes.len(), 1);
+        assert_eq!(synthetic.ask_changes[0].price, dec!(2010) / dec!(39000));
+        assert_eq!(synthetic.ask_changes[0].qty, dec!(1170) / dec!(2010));
+    }
+}
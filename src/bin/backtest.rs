@@ -0,0 +1,248 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use lob::aggregation::aggregator::OrderBookAggregator;
+use lob::aggregation::backtest::{first_difference, summary_line, Backtest};
+use lob::aggregation::quote_merge::{IterativeMergeQuotes, MergeQuotes, VecSortMergeQuotes};
+use lob::common::model::Instrument;
+use lob::connectors::replay::{replay_files, Replay, ReplayFormat, ReplaySpeed};
+use lob::connectors::ConnectorRegistry;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc::channel;
+use tracing::{error, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Merger {
+    Iterative,
+    VecSort,
+}
+
+/// Capture and aggregation options.
+#[derive(clap::Args, Debug)]
+struct CaptureArgs {
+    /// Recorded file, or `frames-*.jsonl.gz` files of a directory.
+    #[clap(long)]
+    replay: PathBuf,
+    #[clap(long, value_enum, default_value_t = ReplayFormat::Frames)]
+    replay_format: ReplayFormat,
+    /// Symbol and exchanges of a single symbol recording, they assign exchange ids of the frames.
+    #[clap(short, long)]
+    symbol: String,
+    #[clap(short, long, value_delimiter = ',', default_value = "binance,bitstamp")]
+    exchanges: Vec<String>,
+    #[clap(short, long, default_value_t = 10)]
+    top_book_depth: usize,
+    #[clap(long, default_value_t = 8)]
+    price_scale: u32,
+    #[clap(long, default_value_t = 8)]
+    qty_scale: u32,
+    #[clap(long)]
+    consolidated: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Replays a capture through the aggregator and writes the `Summary` sequence.
+    Run {
+        #[clap(flatten)]
+        capture: CaptureArgs,
+        #[clap(long, value_enum, default_value_t = Merger::Iterative)]
+        merger: Merger,
+        #[clap(short, long)]
+        output: PathBuf,
+    },
+    /// Reports the first differing event of two `Summary` sequence files.
+    Diff { left: PathBuf, right: PathBuf },
+    /// Replays a capture with two mergers into `left.summaries` and `right.summaries` of the
+    /// output directory and reports the first differing event.
+    Compare {
+        #[clap(flatten)]
+        capture: CaptureArgs,
+        #[clap(long, value_enum, default_value_t = Merger::Iterative)]
+        left_merger: Merger,
+        #[clap(long, value_enum, default_value_t = Merger::VecSort)]
+        right_merger: Merger,
+        #[clap(short, long)]
+        output_dir: PathBuf,
+    },
+}
+
+/// Deterministic replay of recorded data through the aggregator.
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// Writes summaries of the capture to `output`, returns the number of summaries.
+async fn run<T: MergeQuotes>(
+    capture: &CaptureArgs,
+    quotes_merger: T,
+    output: &Path,
+) -> Result<u64, String> {
+    let registry = ConnectorRegistry::default();
+    let mut feeds = Vec::with_capacity(capture.exchanges.len());
+    let mut exchanges_id_mapping = HashMap::with_capacity(capture.exchanges.len());
+    for (exchange_id, name) in capture.exchanges.iter().enumerate() {
+        let connector = registry
+            .build(name, &capture.symbol, exchange_id)
+            .ok_or_else(|| {
+                format!(
+                    "unknown exchange={}. available exchanges={:?}",
+                    name,
+                    registry.names()
+                )
+            })?;
+        exchanges_id_mapping.insert(exchange_id, connector.name().to_string());
+        feeds.push((exchange_id, connector.feed()));
+    }
+    let order_book_aggregator = OrderBookAggregator::new(
+        quotes_merger,
+        capture.exchanges.len(),
+        capture.top_book_depth,
+        exchanges_id_mapping,
+    )
+    .with_instrument(Instrument::new(capture.price_scale, capture.qty_scale))
+    .with_consolidated(capture.consolidated);
+    let mut backtest = Backtest::new(order_book_aggregator);
+
+    let files = replay_files(&capture.replay)
+        .map_err(|err| format!("failed to list replay files. err={:?}", err))?;
+    let replay = Replay::new(files).with_speed(ReplaySpeed::Max);
+    let (sender, mut receiver) = channel(1024);
+    let format = capture.replay_format;
+    let replay = tokio::spawn(async move { replay.run(format, feeds, sender).await });
+
+    let file = File::create(output)
+        .map_err(|err| format!("failed to create {:?}. err={:?}", output, err))?;
+    let mut writer = BufWriter::new(file);
+    let mut summaries = 0;
+    while let Some(update) = receiver.recv().await {
+        let summary = match backtest.process(update) {
+            Some(val) => val,
+            None => continue,
+        };
+        writeln!(writer, "{}", summary_line(&summary))
+            .map_err(|err| format!("failed to write {:?}. err={:?}", output, err))?;
+        summaries += 1;
+    }
+    writer
+        .flush()
+        .map_err(|err| format!("failed to write {:?}. err={:?}", output, err))?;
+    replay.await.unwrap()?;
+    info!(
+        "backtest is complete. output={:?} summaries={}",
+        output, summaries
+    );
+    Ok(summaries)
+}
+
+async fn run_merger(capture: &CaptureArgs, merger: Merger, output: &Path) -> Result<u64, String> {
+    let exchanges_number = capture.exchanges.len();
+    match merger {
+        Merger::Iterative => {
+            let quotes_merger = IterativeMergeQuotes::new(capture.top_book_depth, exchanges_number);
+            run(capture, quotes_merger, output).await
+        }
+        Merger::VecSort => {
+            let quotes_merger = VecSortMergeQuotes::new(capture.top_book_depth, exchanges_number);
+            run(capture, quotes_merger, output).await
+        }
+    }
+}
+
+/// Prints the first difference of two summary files, returns whether the files are equal.
+/// A read error fails the comparison rather than ending a sequence early.
+fn diff(left: &Path, right: &Path) -> Result<bool, String> {
+    let read_error = RefCell::new(None);
+    let lines = |path: &Path| {
+        let path = path.to_path_buf();
+        let read_error = &read_error;
+        File::open(&path)
+            .map_err(|err| format!("failed to open {:?}. err={:?}", path, err))
+            .map(|file| {
+                BufReader::new(file)
+                    .lines()
+                    .map_while(move |line| match line {
+                        Ok(val) => Some(val),
+                        Err(err) => {
+                            read_error
+                                .borrow_mut()
+                                .get_or_insert(format!("failed to read {:?}. err={:?}", path, err));
+                            None
+                        }
+                    })
+            })
+    };
+    let difference = first_difference(lines(left)?, lines(right)?);
+    if let Some(err) = read_error.into_inner() {
+        return Err(err);
+    }
+    let difference = match difference {
+        Some(val) => val,
+        None => {
+            println!("summary sequences are equal");
+            return Ok(true);
+        }
+    };
+    let event = difference
+        .event
+        .map(|event| event.to_string())
+        .unwrap_or_default();
+    println!(
+        "first difference at line {} event {}",
+        difference.line + 1,
+        event
+    );
+    let end = "<end of sequence>".to_string();
+    println!(
+        "{:?}: {}",
+        left,
+        difference.left.unwrap_or_else(|| end.clone())
+    );
+    println!("{:?}: {}", right, difference.right.unwrap_or(end));
+    Ok(false)
+}
+
+async fn execute(command: Command) -> Result<bool, String> {
+    match command {
+        Command::Run {
+            capture,
+            merger,
+            output,
+        } => run_merger(&capture, merger, &output).await.map(|_| true),
+        Command::Diff { left, right } => diff(&left, &right),
+        Command::Compare {
+            capture,
+            left_merger,
+            right_merger,
+            output_dir,
+        } => {
+            std::fs::create_dir_all(&output_dir)
+                .map_err(|err| format!("failed to create {:?}. err={:?}", output_dir, err))?;
+            let left = output_dir.join("left.summaries");
+            let right = output_dir.join("right.summaries");
+            run_merger(&capture, left_merger, &left).await?;
+            run_merger(&capture, right_merger, &right).await?;
+            diff(&left, &right)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let args = Args::parse();
+    match execute(args.command).await {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(2);
+        }
+    }
+}
@@ -1,8 +1,13 @@
 use clap::Parser;
 use futures_util::StreamExt;
+use lob::orderbook::delta::apply_delta;
+use lob::orderbook::execution_request::{Amount, Side};
 use lob::orderbook::orderbook_aggregator_client::OrderbookAggregatorClient;
-use lob::orderbook::Empty;
-use tonic::transport::Endpoint;
+use lob::orderbook::{
+    BookSummaryRequest, CancelPaperOrderRequest, ExecutionRequest, PaperAccountRequest,
+    PaperOrderRequest, RouteOrderRequest, Summary,
+};
+use tonic::transport::{Channel, Endpoint};
 use tonic::Request;
 
 #[derive(Parser, Debug)]
@@ -10,6 +15,86 @@ use tonic::Request;
 struct Args {
     #[clap(short, long, default_value_t = 50051)]
     port: usize,
+    /// Aggregated symbol, may be omitted when the server aggregates one symbol.
+    #[clap(short, long, default_value = "")]
+    symbol: String,
+    /// Levels per side, the server depth by default.
+    #[clap(short, long, default_value_t = 0)]
+    depth: u32,
+    /// Exchanges to include, all exchanges by default.
+    #[clap(short, long, value_delimiter = ',')]
+    exchanges: Vec<String>,
+    /// Levels with a smaller amount are dropped.
+    #[clap(short, long, default_value_t = 0.0)]
+    min_qty: f64,
+    /// Print the current book once instead of subscribing to updates.
+    #[clap(long)]
+    once: bool,
+    /// Subscribe to level changes and keep the book locally.
+    #[clap(long)]
+    deltas: bool,
+    /// Subscribe to arbitrage opportunities between exchanges.
+    #[clap(long)]
+    arbitrage: bool,
+    /// Print the cross-exchange arbitrage report once.
+    #[clap(long)]
+    report: bool,
+    /// Estimate the cost of a market buy of the given amount.
+    #[clap(long, conflicts_with = "sell")]
+    buy: Option<f64>,
+    /// Estimate the cost of a market sell of the given amount.
+    #[clap(long)]
+    sell: Option<f64>,
+    /// Buy and sell amounts are in the quote currency.
+    #[clap(long)]
+    notional: bool,
+    /// Split the buy or sell qty across exchange books instead of estimating its cost.
+    #[clap(long)]
+    route: bool,
+    /// Worst price of child orders of a routed order.
+    #[clap(long, default_value_t = 0.0)]
+    limit_price: f64,
+    /// Submit the buy or sell qty as a paper order on the exchange, a limit order with --limit-price.
+    #[clap(long)]
+    paper: Option<String>,
+    /// Cancel a resting paper order.
+    #[clap(long)]
+    paper_cancel: Option<u64>,
+    /// Print paper positions and resting orders once.
+    #[clap(long)]
+    paper_account: bool,
+    /// Subscribe to fills of paper orders.
+    #[clap(long)]
+    paper_fills: bool,
+}
+
+/// Keeps the book from the delta stream, the stream is reopened for a new snapshot on a sequence gap.
+async fn book_deltas(
+    client: &mut OrderbookAggregatorClient<Channel>,
+    request: BookSummaryRequest,
+) -> Result<(), Box<dyn std::error::Error>> {
+    'snapshot_loop: loop {
+        let mut response = client
+            .book_deltas(Request::new(request.clone()))
+            .await?
+            .into_inner();
+        let mut book = Summary::default();
+        let mut sequence = 0;
+        while let Some(book_delta) = response.next().await {
+            let book_delta = book_delta?;
+            if book_delta.sequence != sequence + 1 {
+                eprintln!(
+                    "book delta gap. sequence={} last={}",
+                    book_delta.sequence, sequence
+                );
+                continue 'snapshot_loop;
+            }
+            sequence = book_delta.sequence;
+            apply_delta(&mut book, &book_delta);
+            println!("new: {:#?}", &book);
+        }
+        return Ok(());
+    }
 }
 
 #[tokio::main]
@@ -19,7 +104,102 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = Endpoint::from_shared(url)?;
 
     let mut client = OrderbookAggregatorClient::connect(addr).await?;
-    let request = Request::new(Empty {});
+    let execution = match (args.buy, args.sell) {
+        (Some(val), _) => Some((Side::Buy, val)),
+        (_, Some(val)) => Some((Side::Sell, val)),
+        _ => None,
+    };
+    if let (Some((side, qty)), Some(exchange)) = (execution, &args.paper) {
+        let request = Request::new(PaperOrderRequest {
+            symbol: args.symbol,
+            exchange: exchange.clone(),
+            side: side.into(),
+            qty,
+            limit_price: args.limit_price,
+        });
+        let ack = client.submit_paper_order(request).await?.into_inner();
+        println!("{:#?}", &ack);
+        return Ok(());
+    }
+    if let Some(order_id) = args.paper_cancel {
+        let request = Request::new(CancelPaperOrderRequest {
+            symbol: args.symbol,
+            order_id,
+        });
+        let order = client.cancel_paper_order(request).await?.into_inner();
+        println!("{:#?}", &order);
+        return Ok(());
+    }
+    if args.paper_account || args.paper_fills {
+        let request = Request::new(PaperAccountRequest {
+            symbol: args.symbol,
+        });
+        if args.paper_account {
+            let account = client.get_paper_account(request).await?.into_inner();
+            println!("{:#?}", &account);
+            return Ok(());
+        }
+        let mut response = client.paper_fills(request).await?.into_inner();
+        while let Some(fill) = response.next().await {
+            println!("fill: {:#?}", &fill?);
+        }
+        return Ok(());
+    }
+    if let (Some((side, qty)), true) = (execution, args.route) {
+        let request = Request::new(RouteOrderRequest {
+            symbol: args.symbol,
+            side: side.into(),
+            qty,
+            limit_price: args.limit_price,
+            venues: vec![],
+            exchanges: args.exchanges,
+        });
+        let route = client.route_order(request).await?.into_inner();
+        println!("{:#?}", &route);
+        return Ok(());
+    }
+    if let Some((side, val)) = execution {
+        let amount = if args.notional {
+            Amount::Notional(val)
+        } else {
+            Amount::Qty(val)
+        };
+        let request = Request::new(ExecutionRequest {
+            symbol: args.symbol,
+            side: side.into(),
+            amount: Some(amount),
+            exchanges: args.exchanges,
+        });
+        let estimate = client.get_execution_estimate(request).await?.into_inner();
+        println!("{:#?}", &estimate);
+        return Ok(());
+    }
+    let request = Request::new(BookSummaryRequest {
+        symbol: args.symbol,
+        depth: args.depth,
+        exchanges: args.exchanges,
+        min_qty: args.min_qty,
+    });
+    if args.once {
+        let summary = client.get_book_snapshot(request).await?.into_inner();
+        println!("{:#?}", &summary);
+        return Ok(());
+    }
+    if args.report {
+        let report = client.get_arbitrage_report(request).await?.into_inner();
+        println!("{:#?}", &report);
+        return Ok(());
+    }
+    if args.arbitrage {
+        let mut response = client.arbitrage_opportunities(request).await?.into_inner();
+        while let Some(opportunity) = response.next().await {
+            println!("opportunity: {:#?}", &opportunity?);
+        }
+        return Ok(());
+    }
+    if args.deltas {
+        return book_deltas(&mut client, request.into_inner()).await;
+    }
     let mut response = client.book_summary(request).await.unwrap().into_inner();
 
     while let Some(row) = response.next().await {
@@ -1,38 +1,198 @@
 use clap::Parser;
 use lob::aggregation::aggregator::OrderBookAggregator;
+use lob::aggregation::arbitrage::ArbitrageTracker;
+use lob::aggregation::fees::FeeSchedule;
+use lob::aggregation::fx::{quote_currency, FxConversionStage};
+use lob::aggregation::paper::PaperEngine;
 use lob::aggregation::quote_merge::{IterativeMergeQuotes, MergeQuotes};
-use lob::common::model::OrderBookUpdate;
-use lob::connectors::binance::BinanceOrderBookListener;
-use lob::connectors::bitstamp::BitstampOrderBookListener;
-use lob::orderbook::OrderbookAggregatorPublisher;
+use lob::aggregation::synthetic::{self, Leg, SyntheticCross};
+use lob::common::model::{Instrument, OrderBookUpdate, Price};
+use lob::connectors::binance::{self, BinanceOrderBookListener, BinanceStreamMode};
+use lob::connectors::bitstamp::{self, BitstampOrderBookListener, BitstampStreamMode};
+use lob::connectors::recorder::{FrameRecorder, RotatingWriter};
+use lob::connectors::replay::{replay_files, Replay, ReplayFormat, ReplaySpeed};
+use lob::connectors::{run_connectors, ConnectorRegistry, ExchangeConnector};
+use lob::orderbook::execution::{execution_estimate, ExecutionQuery};
+use lob::orderbook::paper::{PaperChannels, PaperQuery, PaperTrading};
+use lob::orderbook::routing::{order_route, RouteQuery};
 use lob::orderbook::{orderbook_aggregator_server::OrderbookAggregatorServer, Summary};
-use std::collections::HashMap;
+use lob::orderbook::{
+    ArbitrageChannels, ArbitrageOpportunity, ArbitrageReport, OrderbookAggregatorPublisher,
+};
+use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
-use tokio::sync::mpsc::{channel, Receiver};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::watch::Receiver as WatchReceiver;
-use tonic;
+use tokio::time::interval;
 use tonic::transport::Server;
 use tracing::{error, info};
-use tracing_subscriber;
+
+/// Sends arbitrage opportunities of the aggregated book to subscribers and keeps the report.
+struct ArbitragePublisher {
+    tracker: ArbitrageTracker,
+    events: broadcast::Sender<ArbitrageOpportunity>,
+    report: tokio::sync::watch::Sender<ArbitrageReport>,
+}
+
+impl ArbitragePublisher {
+    fn publish<T: MergeQuotes>(&mut self, order_book_aggregator: &OrderBookAggregator<T>) {
+        let events = self
+            .tracker
+            .update(order_book_aggregator.arbitrage_opportunities());
+        if events.is_empty() {
+            return;
+        }
+        for event in events {
+            info!("arbitrage opportunity: {:?}", &event);
+            // error means there are no subscribers
+            let _ = self.events.send(event);
+        }
+        self.report.send_replace(self.tracker.report());
+    }
+}
+
+/// Channels of a symbol aggregation.
+struct AggregationChannels {
+    /// Updates of the symbol exchanges.
+    updates: Receiver<OrderBookUpdate>,
+    summary: tokio::sync::watch::Sender<Summary>,
+    routes: Receiver<RouteQuery>,
+    executions: Receiver<ExecutionQuery>,
+    paper: Receiver<PaperQuery>,
+}
 
 async fn order_book_aggregation<T: MergeQuotes>(
-    mut receiver: Receiver<OrderBookUpdate>,
-    sender: tokio::sync::watch::Sender<Summary>,
+    channels: AggregationChannels,
+    mut synthetic_cross: SyntheticCross,
+    mut fx_conversion: FxConversionStage,
     mut order_book_aggregator: OrderBookAggregator<T>,
+    mut arbitrage: ArbitragePublisher,
+    mut paper_trading: PaperTrading,
+    stale_check_period: Duration,
+) {
+    let AggregationChannels {
+        updates: mut receiver,
+        summary: sender,
+        mut routes,
+        mut executions,
+        mut paper,
+    } = channels;
+    let mut stale_check = interval(stale_check_period);
+    loop {
+        let updates = tokio::select! {
+            message = receiver.recv() => match message {
+                Some(message) => {
+                    info!("received new order book update: {:?}", &message);
+                    synthetic_cross
+                        .process(message)
+                        .into_iter()
+                        .flat_map(|update| fx_conversion.process(update))
+                        .collect()
+                }
+                None => return,
+            },
+            Some(query) = routes.recv() => {
+                let exchanges_id_mapping = order_book_aggregator.exchanges_id_mapping();
+                let parent = query.request.parent_order(exchanges_id_mapping);
+                let plan = order_book_aggregator.route(&parent);
+                info!("order route. parent={:?} plan={:?}", &parent, &plan);
+                // error means the client is gone
+                let _ = query.response.send(order_route(&plan, exchanges_id_mapping));
+                vec![]
+            }
+            Some(query) = executions.recv() => {
+                let exchanges_id_mapping = order_book_aggregator.exchanges_id_mapping();
+                let exchange_ids = query.request.exchange_ids(exchanges_id_mapping);
+                let execution = order_book_aggregator.execution(
+                    query.request.execution_side(),
+                    query.amount,
+                    exchange_ids.as_deref(),
+                );
+                info!("execution estimate. request={:?} execution={:?}", &query.request, &execution);
+                // error means the client is gone
+                let _ = query.response.send(execution_estimate(&execution, exchanges_id_mapping));
+                vec![]
+            }
+            Some(query) = paper.recv() => {
+                paper_trading.handle(
+                    query,
+                    order_book_aggregator.exchanges_books(),
+                    |exchange_id| order_book_aggregator.is_stale(exchange_id),
+                );
+                vec![]
+            }
+            _ = stale_check.tick() => {
+                if let Some(new_top) = order_book_aggregator.check_stale() {
+                    info!("book top updated on stale exchange: {:?}", &new_top);
+                    if let Err(err) = sender.send(new_top) {
+                        error!("failed to send new top. err={:?}", err)
+                    }
+                    arbitrage.publish(&order_book_aggregator);
+                }
+                vec![]
+            }
+        };
+        for update in updates {
+            let exchange_id = update.exchange_id;
+            if let Some(new_top) = order_book_aggregator.process(update) {
+                info!("book top updated: {:?}", &new_top);
+                if let Err(err) = sender.send(new_top) {
+                    error!("failed to send new top. err={:?}", err)
+                }
+            }
+            arbitrage.publish(&order_book_aggregator);
+            if let Some(exchange_id) = exchange_id {
+                paper_trading.on_book_update(
+                    exchange_id,
+                    order_book_aggregator.exchanges_books(),
+                    |exchange_id| order_book_aggregator.is_stale(exchange_id),
+                );
+            }
+        }
+    }
+}
+
+/// Forwards updates of the shared connector channel to symbol aggregations.
+/// `first_exchange_ids` are the first global exchange ids of symbols in increasing order,
+/// updates are forwarded with exchange ids local to the symbol.
+async fn route_updates(
+    mut receiver: Receiver<OrderBookUpdate>,
+    first_exchange_ids: Vec<usize>,
+    senders: Vec<Sender<OrderBookUpdate>>,
 ) {
-    while let Some(message) = receiver.recv().await {
-        info!("received new order book update: {:?}", &message);
-        if let Some(new_top) = order_book_aggregator.process(message) {
-            info!("book top updated: {:?}", &new_top);
-            if let Err(err) = sender.send(new_top) {
-                error!("failed to send new top. err={:?}", err)
+    while let Some(mut message) = receiver.recv().await {
+        let exchange_id = match message.exchange_id {
+            Some(val) => val,
+            None => {
+                error!("order book update without exchange id: {:?}", &message);
+                continue;
             }
+        };
+        let symbol_index = first_exchange_ids.partition_point(|first| *first <= exchange_id) - 1;
+        message.exchange_id = Some(exchange_id - first_exchange_ids[symbol_index]);
+        if let Err(err) = senders[symbol_index].send(message).await {
+            error!("failed to route order book update. err={:?}", err);
+            return;
         }
     }
 }
 
-async fn grpc_server(rx: WatchReceiver<Summary>, addr: SocketAddr) {
-    let publisher = OrderbookAggregatorPublisher::new(rx);
+async fn grpc_server(
+    receivers: HashMap<String, WatchReceiver<Summary>>,
+    arbitrage: HashMap<String, ArbitrageChannels>,
+    routes: HashMap<String, Sender<RouteQuery>>,
+    executions: HashMap<String, Sender<ExecutionQuery>>,
+    paper: HashMap<String, PaperChannels>,
+    addr: SocketAddr,
+) {
+    let publisher = OrderbookAggregatorPublisher::new(receivers)
+        .with_arbitrage(arbitrage)
+        .with_routes(routes)
+        .with_executions(executions)
+        .with_paper(paper);
     let server = OrderbookAggregatorServer::new(publisher);
 
     Server::builder()
@@ -45,70 +205,470 @@ async fn grpc_server(rx: WatchReceiver<Summary>, addr: SocketAddr) {
 #[derive(Parser, Debug)]
 #[clap(about, version, author)]
 struct Args {
-    #[clap(short, long)]
-    symbol: String,
+    /// Aggregated symbols, one aggregator per symbol.
+    #[clap(
+        short,
+        long = "symbols",
+        alias = "symbol",
+        value_delimiter = ',',
+        required = true
+    )]
+    symbols: Vec<String>,
     #[clap(short, long, default_value_t = 10)]
     top_book_depth: usize,
     #[clap(short, long, default_value_t = 50051)]
     port: usize,
+    #[clap(short, long, value_delimiter = ',', default_value = "binance,bitstamp")]
+    exchanges: Vec<String>,
+    #[clap(short, long, value_enum, default_value_t = BinanceStreamMode::Partial)]
+    binance_mode: BinanceStreamMode,
+    #[clap(long, value_enum, default_value_t = BitstampStreamMode::Partial)]
+    bitstamp_mode: BitstampStreamMode,
+    #[clap(long, default_value_t = 8)]
+    price_scale: u32,
+    #[clap(long, default_value_t = 8)]
+    qty_scale: u32,
+    /// Sum levels of all exchanges at the same price into consolidated levels.
+    #[clap(long)]
+    consolidated: bool,
+    /// Exchange fee rates `exchange=maker:taker`, quotes are ranked by the fee-inclusive price.
+    #[clap(long, value_delimiter = ',')]
+    fees: Vec<String>,
+    /// Exchange specific symbols `exchange=SYMBOL` or `exchange:SYMBOL=SYMBOL` for one of several symbols,
+    /// e.g. `bitstamp=BTC/USD`.
+    #[clap(long, value_delimiter = ',')]
+    symbol_map: Vec<String>,
+    /// Configured conversion rates `CURRENCY=rate` into the symbol quote currency.
+    #[clap(long, value_delimiter = ',')]
+    fx_rates: Vec<String>,
+    /// Live conversion rates from mid price of `exchange=PAIR` books, e.g. `kraken=USDT/USD`.
+    #[clap(long, value_delimiter = ',')]
+    fx_feeds: Vec<String>,
+    /// Legs `exchange=BASE/X:QUOTE/X` of the synthetic `BASE/QUOTE` book, e.g. `coinbase=ETH/USD:BTC/USD`.
+    #[clap(long, value_delimiter = ',')]
+    synthetic_legs: Vec<String>,
+    /// Exchanges without updates within the timeout are excluded from the book until the next update.
+    #[clap(long)]
+    stale_timeout_ms: Option<u64>,
+    /// Record raw websocket frames of all connections to rotating `frames-*.jsonl.gz` files of the directory.
+    #[clap(long)]
+    record_dir: Option<PathBuf>,
+    /// Size of uncompressed frames per recording file.
+    #[clap(long, default_value_t = 100)]
+    record_file_mb: u64,
+    /// Age of a recording file before the next one is started.
+    #[clap(long, default_value_t = 3600)]
+    record_file_secs: u64,
+    /// Replay a recorded file, or `frames-*.jsonl.gz` files of a directory, instead of connecting to exchanges.
+    #[clap(long, conflicts_with = "record_dir")]
+    replay: Option<PathBuf>,
+    #[clap(long, value_enum, default_value_t = ReplayFormat::Frames)]
+    replay_format: ReplayFormat,
+    /// `realtime`, `max` or a speed-up factor like `10x`.
+    #[clap(long, default_value = "realtime")]
+    replay_speed: ReplaySpeed,
 }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt::init();
+/// Parses `key=value` options.
+fn parse_key_values<V: std::str::FromStr>(values: &[String]) -> Result<Vec<(String, V)>, String>
+where
+    V::Err: std::fmt::Display,
+{
+    let mut parsed = Vec::with_capacity(values.len());
+    for value in values {
+        let (key, val) = value
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got {}", value))?;
+        let val = val
+            .parse()
+            .map_err(|err| format!("invalid value {}: {}", value, err))?;
+        parsed.push((key.to_string(), val));
+    }
+    Ok(parsed)
+}
 
-    let args = Args::parse();
-    let symbol = args.symbol;
+/// Options shared by aggregations of all symbols.
+struct PipelineConfig {
+    symbol_map: HashMap<String, String>,
+    synthetic_legs: Vec<(String, String)>,
+    fx_rates: Vec<(String, Price)>,
+    fx_feeds: Vec<(String, String)>,
+    fee_schedules: HashMap<String, FeeSchedule>,
+}
 
-    let (exchange_order_book_sender, exchange_order_book_receiver) = channel(3);
+impl PipelineConfig {
+    fn parse(args: &Args) -> Result<Self, String> {
+        Ok(Self {
+            symbol_map: parse_key_values(&args.symbol_map)
+                .map_err(|err| format!("invalid symbol map. err={}", err))?
+                .into_iter()
+                .collect(),
+            synthetic_legs: parse_key_values(&args.synthetic_legs)
+                .map_err(|err| format!("invalid synthetic legs. err={}", err))?,
+            fx_rates: parse_key_values(&args.fx_rates)
+                .map_err(|err| format!("invalid fx rates. err={}", err))?,
+            fx_feeds: parse_key_values(&args.fx_feeds)
+                .map_err(|err| format!("invalid fx feeds. err={}", err))?,
+            fee_schedules: parse_key_values(&args.fees)
+                .map_err(|err| format!("invalid fees. err={}", err))?
+                .into_iter()
+                .collect(),
+        })
+    }
 
-    let (summary_sender, summary_receiver) = tokio::sync::watch::channel(Summary {
-        spread: 0.0,
-        bids: vec![],
-        asks: vec![],
-    });
+    /// Exchange symbol of `symbol`, `exchange:SYMBOL` mapping takes precedence over `exchange`.
+    fn exchange_symbol<'a>(&'a self, exchange: &str, symbol: &'a str) -> &'a str {
+        self.symbol_map
+            .get(&format!("{}:{}", exchange, symbol))
+            .or_else(|| self.symbol_map.get(exchange))
+            .map(String::as_str)
+            .unwrap_or(symbol)
+    }
+}
+
+/// Connectors and processing stages of one symbol.
+struct SymbolPipeline {
+    connectors: Vec<Box<dyn ExchangeConnector>>,
+    synthetic_cross: SyntheticCross,
+    fx_conversion: FxConversionStage,
+    order_book_aggregator: OrderBookAggregator<IterativeMergeQuotes>,
+    arbitrage_tracker: ArbitrageTracker,
+    paper_engine: PaperEngine,
+}
 
-    let binance = BinanceOrderBookListener::new(&symbol, 0);
-    let bitstamp = BitstampOrderBookListener::new(&symbol, 1);
+/// Builds aggregation of `symbol`. Stages use exchange ids local to the symbol,
+/// connectors publish them shifted by `first_exchange_id`.
+fn symbol_pipeline(
+    symbol: &str,
+    first_exchange_id: usize,
+    args: &Args,
+    config: &PipelineConfig,
+    registry: &ConnectorRegistry,
+) -> Result<SymbolPipeline, String> {
+    let build = |name: &str, pair: &str, exchange_id: usize| {
+        registry
+            .build(name, pair, first_exchange_id + exchange_id)
+            .ok_or_else(|| {
+                format!(
+                    "unknown exchange={}. available exchanges={:?}",
+                    name,
+                    registry.names()
+                )
+            })
+    };
 
+    let mut connectors = Vec::with_capacity(args.exchanges.len());
     let mut exchange_id_mapping = HashMap::new();
-    exchange_id_mapping.insert(0, "binance".to_string());
-    exchange_id_mapping.insert(1, "bitstamp".to_string());
+    let mut exchanges_currency = Vec::with_capacity(args.exchanges.len());
+    for (exchange_id, name) in args.exchanges.iter().enumerate() {
+        let exchange_symbol = config.exchange_symbol(name, symbol);
+        exchanges_currency.push(quote_currency(exchange_symbol));
+        let connector = build(name, exchange_symbol, exchange_id)?;
+        exchange_id_mapping.insert(exchange_id, connector.name().to_string());
+        connectors.push(connector);
+    }
+
+    // legs `BASE/X:QUOTE/X` belong to the `BASE/QUOTE` symbol
+    let mut synthetic_legs = Vec::new();
+    for (name, legs) in &config.synthetic_legs {
+        let (base_pair, quote_pair) = legs
+            .split_once(':')
+            .ok_or_else(|| format!("expected BASE/X:QUOTE/X synthetic legs, got {}", legs))?;
+        let base = base_pair.split('/').next().unwrap_or_default();
+        let quote = quote_pair.split('/').next().unwrap_or_default();
+        if format!("{}/{}", base, quote).eq_ignore_ascii_case(symbol) {
+            synthetic_legs.push((name, base_pair, quote_pair));
+        }
+    }
+    let mut synthetic_cross = SyntheticCross::new(exchange_id_mapping.len(), args.top_book_depth);
+    if !synthetic_legs.is_empty() {
+        exchange_id_mapping.insert(exchange_id_mapping.len(), synthetic::NAME.to_string());
+        exchanges_currency.push(quote_currency(symbol));
+    }
 
     let exchanges_number = exchange_id_mapping.len();
+    // leg and fx books are published with ids after the aggregated exchanges
+    let mut feed_exchange_id = exchanges_number;
+
+    for (name, base_pair, quote_pair) in synthetic_legs {
+        for (pair, leg) in [(base_pair, Leg::Base), (quote_pair, Leg::Quote)] {
+            connectors.push(build(name, pair, feed_exchange_id)?);
+            synthetic_cross = synthetic_cross.with_leg(feed_exchange_id, leg);
+            feed_exchange_id += 1;
+        }
+    }
 
-    let quotes_merger = IterativeMergeQuotes::new(args.top_book_depth, exchanges_number);
+    let currency = quote_currency(symbol);
+    let mut fx_conversion =
+        FxConversionStage::new(&currency, exchanges_currency.clone(), args.top_book_depth);
+    for (currency, rate) in &config.fx_rates {
+        fx_conversion = fx_conversion.with_rate(currency, *rate);
+    }
+    // pairs of other currencies are subscribed only for symbols that need them
+    for (name, pair) in &config.fx_feeds {
+        let converts_into_symbol_currency = pair
+            .split('/')
+            .any(|pair_currency| pair_currency.eq_ignore_ascii_case(&currency));
+        if !converts_into_symbol_currency {
+            continue;
+        }
+        connectors.push(build(name, pair, feed_exchange_id)?);
+        fx_conversion = fx_conversion.with_feed(feed_exchange_id, pair)?;
+        feed_exchange_id += 1;
+    }
 
+    let fees: Vec<FeeSchedule> = (0..exchanges_number)
+        .map(|exchange_id| {
+            config
+                .fee_schedules
+                .get(&exchange_id_mapping[&exchange_id])
+                .copied()
+                .unwrap_or_default()
+        })
+        .collect();
+    let paper_engine = PaperEngine::new().with_fees(fees.iter().copied().enumerate().collect());
+    let mut quotes_merger = IterativeMergeQuotes::new(args.top_book_depth, exchanges_number);
+    if !config.fee_schedules.is_empty() {
+        quotes_merger = quotes_merger.with_fees(fees);
+    }
+
+    let arbitrage_tracker = ArbitrageTracker::new(exchange_id_mapping.clone());
     let order_book_aggregator = OrderBookAggregator::new(
         quotes_merger,
         exchanges_number,
         args.top_book_depth,
         exchange_id_mapping,
-    );
+    )
+    .with_instrument(Instrument::new(args.price_scale, args.qty_scale))
+    .with_consolidated(args.consolidated)
+    .with_currencies(exchanges_currency.into_iter().enumerate().collect());
+    let order_book_aggregator = match args.stale_timeout_ms {
+        Some(val) => order_book_aggregator.with_stale_timeout(Duration::from_millis(val)),
+        None => order_book_aggregator,
+    };
+
+    Ok(SymbolPipeline {
+        connectors,
+        synthetic_cross,
+        fx_conversion,
+        order_book_aggregator,
+        arbitrage_tracker,
+        paper_engine,
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let args = Args::parse();
+
+    let mut registry = ConnectorRegistry::default();
+    if args.binance_mode == BinanceStreamMode::Diff {
+        registry.register(binance::NAME, |pair, exchange_id| {
+            Box::new(
+                BinanceOrderBookListener::new(pair, exchange_id).with_mode(BinanceStreamMode::Diff),
+            )
+        });
+    }
+    if args.bitstamp_mode == BitstampStreamMode::Diff {
+        registry.register(bitstamp::NAME, |pair, exchange_id| {
+            Box::new(
+                BitstampOrderBookListener::new(pair, exchange_id)
+                    .with_mode(BitstampStreamMode::Diff),
+            )
+        });
+    }
+    let config = match PipelineConfig::parse(&args) {
+        Ok(val) => val,
+        Err(err) => {
+            error!("{}", err);
+            return;
+        }
+    };
+
+    let (exchange_order_book_sender, exchange_order_book_receiver) = channel(3);
+    // stale exchanges are detected within half of the timeout
+    let stale_check_period = args
+        .stale_timeout_ms
+        .map(|val| Duration::from_millis(val / 2).max(Duration::from_millis(1)))
+        .unwrap_or(Duration::from_secs(1));
+
+    let mut connectors = Vec::new();
+    let mut first_exchange_ids = Vec::with_capacity(args.symbols.len());
+    let mut symbol_senders = Vec::with_capacity(args.symbols.len());
+    let mut summary_receivers = HashMap::with_capacity(args.symbols.len());
+    let mut arbitrage_channels = HashMap::with_capacity(args.symbols.len());
+    let mut route_senders = HashMap::with_capacity(args.symbols.len());
+    let mut execution_senders = HashMap::with_capacity(args.symbols.len());
+    let mut paper_channels = HashMap::with_capacity(args.symbols.len());
+    let mut order_book_aggregations = Vec::with_capacity(args.symbols.len());
+    for symbol in &args.symbols {
+        let symbol = symbol.to_uppercase();
+        let pipeline = match symbol_pipeline(&symbol, connectors.len(), &args, &config, &registry) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to build {} aggregation. err={}", &symbol, err);
+                return;
+            }
+        };
+        first_exchange_ids.push(connectors.len());
+        connectors.extend(pipeline.connectors);
 
-    let order_book_aggregation = tokio::spawn(async move {
-        order_book_aggregation(
+        let (symbol_sender, symbol_receiver) = channel(3);
+        let (summary_sender, summary_receiver) = tokio::sync::watch::channel(Summary::default());
+        symbol_senders.push(symbol_sender);
+        let (arbitrage_events, _) = broadcast::channel(64);
+        let (arbitrage_report, arbitrage_report_receiver) =
+            tokio::sync::watch::channel(ArbitrageReport::default());
+        let arbitrage = ArbitragePublisher {
+            tracker: pipeline.arbitrage_tracker,
+            events: arbitrage_events.clone(),
+            report: arbitrage_report,
+        };
+        arbitrage_channels.insert(
+            symbol.clone(),
+            ArbitrageChannels {
+                events: arbitrage_events,
+                report: arbitrage_report_receiver,
+            },
+        );
+        let (route_sender, route_receiver) = channel(16);
+        route_senders.insert(symbol.clone(), route_sender);
+        let (execution_sender, execution_receiver) = channel(16);
+        execution_senders.insert(symbol.clone(), execution_sender);
+        let (paper_sender, paper_receiver) = channel(16);
+        let (paper_fills, _) = broadcast::channel(64);
+        let paper_trading = PaperTrading::new(
+            pipeline.paper_engine,
+            pipeline
+                .order_book_aggregator
+                .exchanges_id_mapping()
+                .clone(),
+            paper_fills.clone(),
+        );
+        paper_channels.insert(
+            symbol.clone(),
+            PaperChannels {
+                queries: paper_sender,
+                fills: paper_fills,
+            },
+        );
+        summary_receivers.insert(symbol, summary_receiver);
+        order_book_aggregations.push(tokio::spawn(async move {
+            order_book_aggregation(
+                AggregationChannels {
+                    updates: symbol_receiver,
+                    summary: summary_sender,
+                    routes: route_receiver,
+                    executions: execution_receiver,
+                    paper: paper_receiver,
+                },
+                pipeline.synthetic_cross,
+                pipeline.fx_conversion,
+                pipeline.order_book_aggregator,
+                arbitrage,
+                paper_trading,
+                stale_check_period,
+            )
+            .await
+        }));
+    }
+
+    let router = tokio::spawn(async move {
+        route_updates(
             exchange_order_book_receiver,
-            summary_sender,
-            order_book_aggregator,
+            first_exchange_ids,
+            symbol_senders,
         )
         .await
     });
 
-    let binance_order_book_handler = {
+    if let Some(path) = &args.replay {
+        let files = match replay_files(path) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to list replay files. path={:?} err={:?}", path, err);
+                return;
+            }
+        };
+        // exchange ids are assigned as in the recorded session with the same options
+        let feeds = connectors
+            .iter()
+            .map(|connector| (connector.exchange_id(), connector.feed()))
+            .collect();
+        let replay = Replay::new(files).with_speed(args.replay_speed);
+        let format = args.replay_format;
         let sender = exchange_order_book_sender.clone();
-        tokio::spawn(async move { binance.run(sender).await })
+        // replay replaces live connections
+        connectors = vec![];
+        tokio::spawn(async move {
+            if let Err(err) = replay.run(format, feeds, sender).await {
+                error!("replay failed. err={}", err);
+            }
+        });
+    }
+
+    // subscriptions of all symbols share one connection where the venue allows it
+    let mut multiplexed: BTreeMap<&'static str, Vec<Box<dyn ExchangeConnector>>> = BTreeMap::new();
+    let (recorder, recording) = match &args.record_dir {
+        Some(dir) => {
+            let writer = RotatingWriter::new(dir, "frames")
+                .with_max_file_bytes(args.record_file_mb * 1024 * 1024)
+                .with_max_file_age(Duration::from_secs(args.record_file_secs));
+            let (recorder, recording) = FrameRecorder::start(writer, 1024);
+            (Some(recorder), Some(recording))
+        }
+        None => (None, None),
     };
-    let bitstamp_order_book_handler = {
+    let mut order_book_handlers = Vec::with_capacity(connectors.len());
+    for connector in connectors {
+        if connector.capabilities().multiplex {
+            multiplexed
+                .entry(connector.name())
+                .or_default()
+                .push(connector);
+            continue;
+        }
         let sender = exchange_order_book_sender.clone();
-        tokio::spawn(async move { bitstamp.run(sender).await })
-    };
+        let recorder = recorder.clone();
+        order_book_handlers.push(tokio::spawn(async move {
+            connector.run(sender, recorder).await
+        }));
+    }
+    for (_, connectors) in multiplexed {
+        let sender = exchange_order_book_sender.clone();
+        let recorder = recorder.clone();
+        order_book_handlers.push(tokio::spawn(async move {
+            run_connectors(connectors, sender, recorder).await
+        }));
+    }
 
     let addr = format!("0.0.0.0:{}", args.port).parse().unwrap();
-    let grpc_server = tokio::spawn(async move { grpc_server(summary_receiver, addr).await });
+    let grpc_server = tokio::spawn(async move {
+        grpc_server(
+            summary_receivers,
+            arbitrage_channels,
+            route_senders,
+            execution_senders,
+            paper_channels,
+            addr,
+        )
+        .await
+    });
+
+    // the recording is complete once all connections are done
+    drop(recorder);
 
-    binance_order_book_handler.await.unwrap();
-    bitstamp_order_book_handler.await.unwrap();
-    order_book_aggregation.await.unwrap();
+    for order_book_handler in order_book_handlers {
+        order_book_handler.await.unwrap();
+    }
+    if let Some(recording) = recording {
+        recording.await.unwrap();
+    }
+    router.await.unwrap();
+    for order_book_aggregation in order_book_aggregations {
+        order_book_aggregation.await.unwrap();
+    }
     grpc_server.await.unwrap();
 }
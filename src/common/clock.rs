@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of pipeline timestamps in microseconds since epoch.
+pub trait Clock: Send + Sync {
+    fn now_micros(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_micros(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_micros() as u64)
+            .unwrap_or_default()
+    }
+}
+
+/// Clock set by the caller, e.g. to receive times of replayed updates.
+#[derive(Debug, Default)]
+pub struct SimulatedClock(AtomicU64);
+
+impl SimulatedClock {
+    pub fn at(micros: u64) -> Arc<Self> {
+        Arc::new(Self(AtomicU64::new(micros)))
+    }
+
+    pub fn set(&self, micros: u64) {
+        self.0.store(micros, Ordering::Relaxed);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now_micros(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -1,23 +1,27 @@
+pub mod clock;
 pub mod model;
 
 #[cfg(test)]
 mod tests{
     use std::cmp::Ordering;
-    use super::model::AggregatedBookQuote;
+    use rust_decimal_macros::dec;
+    use super::model::{AggregatedBookQuote, ExchangeQuote, Instrument};
 
     #[test]
     fn aggregated_quote_cmp(){
         {
             let q_bigger = AggregatedBookQuote{
                 exchange: 0,
-                price: 1.001,
-                qty: 2.0
+                price: dec!(1.001),
+                effective_price: dec!(1.001),
+                qty: dec!(2.0)
             };
 
             let q_smaller = AggregatedBookQuote{
                 exchange: 2,
-                price: 1.00,
-                qty: 2.0
+                price: dec!(1.00),
+                effective_price: dec!(1.00),
+                qty: dec!(2.0)
             };
 
             assert_eq!(q_bigger.cmp(&q_smaller), Ordering::Greater);
@@ -27,13 +31,15 @@ mod tests{
         {
             let q_bigger = AggregatedBookQuote{
                 exchange: 0,
-                price: 1.001,
-                qty: 2.5
+                price: dec!(1.001),
+                effective_price: dec!(1.001),
+                qty: dec!(2.5)
             };
             let q_smaller = AggregatedBookQuote{
                 exchange: 2,
-                price: 1.001,
-                qty: 2.0
+                price: dec!(1.001),
+                effective_price: dec!(1.001),
+                qty: dec!(2.0)
             };
             assert_eq!(q_bigger.cmp(&q_smaller), Ordering::Greater);
         }
@@ -41,17 +47,71 @@ mod tests{
         {
             let q_bigger = AggregatedBookQuote{
                 exchange: 2,
-                price: 1.001,
-                qty: 2.5
+                price: dec!(1.001),
+                effective_price: dec!(1.001),
+                qty: dec!(2.5)
             };
             let q_smaller = AggregatedBookQuote{
                 exchange: 0,
-                price: 1.001,
-                qty: 2.5
+                price: dec!(1.001),
+                effective_price: dec!(1.001),
+                qty: dec!(2.5)
             };
             assert_eq!(q_bigger.cmp(&q_smaller), Ordering::Greater);
         }
 
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn effective_price_ranks_first(){
+        let q_bigger = AggregatedBookQuote{
+            exchange: 0,
+            price: dec!(100),
+            effective_price: dec!(100.2),
+            qty: dec!(1)
+        };
+        let q_smaller = AggregatedBookQuote{
+            exchange: 1,
+            price: dec!(100.1),
+            effective_price: dec!(100.1),
+            qty: dec!(1)
+        };
+        assert_eq!(q_bigger.cmp(&q_smaller), Ordering::Greater);
+    }
+
+    #[test]
+    fn same_price_with_different_scale(){
+        let q1 = AggregatedBookQuote{
+            exchange: 0,
+            price: "30000.10".parse().unwrap(),
+            effective_price: "30000.10".parse().unwrap(),
+            qty: dec!(1)
+        };
+        let q2 = AggregatedBookQuote{
+            exchange: 0,
+            price: "30000.1".parse().unwrap(),
+            effective_price: "30000.1".parse().unwrap(),
+            qty: dec!(1.000)
+        };
+        assert_eq!(q1.cmp(&q2), Ordering::Equal);
+    }
+
+    #[test]
+    fn instrument_normalize(){
+        let instrument = Instrument::new(2, 4);
+        let quote = ExchangeQuote{
+            price: dec!(30000.125),
+            qty: dec!(0.5)
+        };
+        let normalized = instrument.normalize(&quote).unwrap();
+        assert_eq!(normalized.price.to_string(), "30000.12");
+        assert_eq!(normalized.qty.to_string(), "0.5000");
+
+        let dust = ExchangeQuote{
+            price: dec!(30000),
+            qty: dec!(0.00001)
+        };
+        assert!(instrument.normalize(&dust).is_none());
+    }
+}
@@ -1,50 +1,114 @@
 use std::cmp::Ordering;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 
+/// Exact decimal price, exchange strings are parsed without float rounding.
+pub type Price = Decimal;
+/// Exact decimal quantity.
+pub type Qty = Decimal;
+
 #[serde_as]
 #[derive(Deserialize, Debug, Serialize, Clone)]
 pub struct ExchangeQuote{
     #[serde_as(as = "DisplayFromStr")]
-    pub price: f64,
+    pub price: Price,
     #[serde_as(as = "DisplayFromStr")]
-    pub qty: f64,
+    pub qty: Qty,
 }
 
 
-#[derive(Deserialize, Debug, Serialize)]
+#[derive(Deserialize, Debug, Serialize, Default)]
 pub struct OrderBookUpdate{
     pub exchange_id: Option<usize>,
     pub bid_changes: Vec<ExchangeQuote>,
     pub ask_changes: Vec<ExchangeQuote>,
+    /// Snapshot replaces exchange book, otherwise changes are applied on top of it
+    /// and zero qty removes the level.
+    pub is_snapshot: bool,
+    /// Venue event time in microseconds since epoch, when the venue publishes one.
+    #[serde(default)]
+    pub exchange_time: Option<u64>,
+    /// Local receive time of the message in microseconds since epoch.
+    #[serde(default)]
+    pub receive_time: u64,
+}
+
+/// Side of an order taking liquidity: buy walks asks, sell walks bids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// Number of decimal places of the aggregated instrument price and qty.
+/// Venues quote the same pair with different scale, e.g. "30000" and "30000.00".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instrument {
+    pub price_scale: u32,
+    pub qty_scale: u32,
+}
+
+impl Instrument {
+    pub fn new(price_scale: u32, qty_scale: u32) -> Self {
+        Self {
+            price_scale,
+            qty_scale,
+        }
+    }
+
+    /// Rounds quote to the instrument scale. Returns `None` if qty is rounded to zero.
+    pub fn normalize(&self, quote: &ExchangeQuote) -> Option<ExchangeQuote> {
+        let qty = Self::rescale(quote.qty, self.qty_scale);
+        if qty.is_zero() {
+            return None;
+        }
+        Some(ExchangeQuote {
+            price: Self::rescale(quote.price, self.price_scale),
+            qty,
+        })
+    }
+
+    fn rescale(value: Decimal, scale: u32) -> Decimal {
+        let mut value = value.round_dp_with_strategy(scale, RoundingStrategy::MidpointNearestEven);
+        value.rescale(scale);
+        value
+    }
+}
+
+impl Default for Instrument {
+    fn default() -> Self {
+        Self::new(8, 8)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AggregatedBookQuote{
     pub exchange: usize,
-    pub price: f64,
-    pub qty: f64,
+    pub price: Price,
+    /// Fee-inclusive price, equals to `price` when fees are not configured.
+    pub effective_price: Price,
+    pub qty: Qty,
 }
 
 impl Eq for AggregatedBookQuote {
 
 }
 
+impl PartialOrd for AggregatedBookQuote {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
 
 
 impl Ord for AggregatedBookQuote {
     fn cmp(&self, other: &Self) -> Ordering {
-        match self.price.partial_cmp(&other.price){
-            Some(Ordering::Equal) | None => {},
-            Some(val) => return val,
-        };
-
-        match self.qty.partial_cmp(&other.qty) {
-            Some(Ordering::Equal) | None => {},
-            Some(val) => return val,
-        };
-
-        self.exchange.cmp(&other.exchange)
+        self.effective_price
+            .cmp(&other.effective_price)
+            .then_with(|| self.price.cmp(&other.price))
+            .then_with(|| self.qty.cmp(&other.qty))
+            .then_with(|| self.exchange.cmp(&other.exchange))
     }
-}
\ No newline at end of file
+}
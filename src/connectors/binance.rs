@@ -1,22 +1,30 @@
 use crate::common::model::{ExchangeQuote, OrderBookUpdate};
-use flate2::read::GzDecoder;
-use futures::{SinkExt, StreamExt};
+use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+use crate::connectors::{ConnectorCapabilities, ExchangeConnector};
+use crate::orderbook::book::L2Book;
+use async_trait::async_trait;
 use serde::Deserialize;
-use serde_json;
-use std::io::Read;
-use std::thread;
-use std::time::Duration;
-use tokio::sync::mpsc::Sender;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::protocol::{frame::coding::CloseCode, CloseFrame},
-    tungstenite::Message,
-};
+use tokio_tungstenite::tungstenite::Message;
 use tracing::{error, info};
 
+pub const NAME: &str = "binance";
+
+const DEPTH: usize = 20;
+const DIFF_DEPTH: usize = 100;
+const SNAPSHOT_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BinanceStreamMode {
+    /// `@depth20@100ms` partial book stream, every message is a top 20 snapshot.
+    Partial,
+    /// `@depth@100ms` diff stream synced against `/api/v3/depth` snapshot.
+    Diff,
+}
+
 pub struct BinanceOrderBookListener {
     exchange_symbol: String,
     exchange_id: usize,
+    mode: BinanceStreamMode,
 }
 
 //{
@@ -41,109 +49,344 @@ struct BinanceOrderBookUpdate {
     asks: Vec<ExchangeQuote>,
 }
 
-impl Into<OrderBookUpdate> for BinanceOrderBookUpdate {
-    fn into(self) -> OrderBookUpdate {
+// {
+//   "lastUpdateId": 1027024,
+//   "bids": [["4.00000000", "431.00000000"]],
+//   "asks": [["4.00000200", "12.00000000"]]
+// }
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BinanceDepthSnapshot {
+    last_update_id: u64,
+    bids: Vec<ExchangeQuote>,
+    asks: Vec<ExchangeQuote>,
+}
+
+// {
+//   "e": "depthUpdate",   // Event type
+//   "E": 123456789,       // Event time
+//   "s": "BNBBTC",        // Symbol
+//   "U": 157,             // First update ID in event
+//   "u": 160,             // Final update ID in event
+//   "b": [["0.0024", "10"]],
+//   "a": [["0.0026", "100"]]
+// }
+
+#[derive(Deserialize, Debug)]
+struct BinanceDepthUpdate {
+    #[serde(rename = "E")]
+    event_time: u64,
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    #[serde(rename = "u")]
+    final_update_id: u64,
+    #[serde(rename = "b")]
+    bids: Vec<ExchangeQuote>,
+    #[serde(rename = "a")]
+    asks: Vec<ExchangeQuote>,
+}
+
+impl From<BinanceOrderBookUpdate> for OrderBookUpdate {
+    fn from(value: BinanceOrderBookUpdate) -> Self {
         OrderBookUpdate {
-            bid_changes: self.bids,
-            ask_changes: self.asks,
+            bid_changes: value.bids,
+            ask_changes: value.asks,
             exchange_id: None,
+            is_snapshot: true,
+            exchange_time: None,
+            receive_time: 0,
         }
     }
 }
 
 impl BinanceOrderBookListener {
     pub fn new(pair: &str, exchange_id: usize) -> Self {
-        let exchange_symbol = pair.replace("/", "");
+        let exchange_symbol = pair.replace('/', "");
         Self {
             exchange_symbol,
             exchange_id,
+            mode: BinanceStreamMode::Partial,
         }
     }
-    pub async fn run(&self, pub_chan: Sender<OrderBookUpdate>) {
-        let subscription_url = format!(
-            "wss://stream.binance.com:443/ws/{}@depth{}@100ms",
-            &self.exchange_symbol.to_lowercase(),
-            20
-        );
 
-        'reconnection_loop: loop {
-            {
-                // Send empty bid and asks to reset collector quotes
-                let order_book_update = OrderBookUpdate {
-                    exchange_id: Some(self.exchange_id),
-                    bid_changes: vec![],
-                    ask_changes: vec![],
-                };
-
-                if let Err(err) = pub_chan.send(order_book_update).await {
-                    error!("can't send update to chan. err={:?}", err);
-                    return;
-                }
+    pub fn with_mode(mut self, mode: BinanceStreamMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+impl ExchangeConnector for BinanceOrderBookListener {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn exchange_id(&self) -> usize {
+        self.exchange_id
+    }
+
+    fn capabilities(&self) -> ConnectorCapabilities {
+        match self.mode {
+            BinanceStreamMode::Partial => ConnectorCapabilities {
+                max_depth: Some(DEPTH),
+                incremental: false,
+                checksum: false,
+                // stream is selected by url
+                multiplex: false,
+            },
+            BinanceStreamMode::Diff => ConnectorCapabilities {
+                max_depth: Some(DIFF_DEPTH),
+                incremental: true,
+                checksum: false,
+                // stream is selected by url
+                multiplex: false,
+            },
+        }
+    }
+
+    fn feed(&self) -> Box<dyn FeedProtocol> {
+        match self.mode {
+            BinanceStreamMode::Partial => Box::new(BinanceFeed {
+                subscription_url: format!(
+                    "wss://stream.binance.com:443/ws/{}@depth{}@100ms",
+                    &self.exchange_symbol.to_lowercase(),
+                    DEPTH
+                ),
+            }),
+            BinanceStreamMode::Diff => Box::new(BinanceDiffFeed::new(&self.exchange_symbol)),
+        }
+    }
+}
+
+struct BinanceFeed {
+    subscription_url: String,
+}
+
+impl FeedProtocol for BinanceFeed {
+    fn url(&self) -> String {
+        self.subscription_url.clone()
+    }
+
+    fn subscription_messages(&self) -> Vec<Message> {
+        // stream is selected by url
+        vec![]
+    }
+
+    fn parse(&mut self, raw_msg: &str) -> FeedEvent {
+        match serde_json::from_str::<BinanceOrderBookUpdate>(raw_msg) {
+            Ok(val) => FeedEvent::Update(val.into()),
+            Err(err) => {
+                error!("failed to parse binance message={}. err={:?}", raw_msg, err);
+                FeedEvent::Skip
             }
-            thread::sleep(Duration::from_secs(1)); // prevents ws spamming
-            info!("subscribing to binance websocket data");
-            let (mut stream, _) = match connect_async(subscription_url.clone()).await {
-                Ok(val) => val,
-                Err(err) => {
-                    error!("failed to connect. err={:?}", err);
-                    thread::sleep(Duration::from_secs(5));
-                    continue 'reconnection_loop;
-                }
-            };
+        }
+    }
+}
 
-            loop {
-                let rcv = stream.next().await;
-                if rcv.is_none() {
-                    continue;
-                }
-                let raw_msg = match rcv.unwrap() {
-                    Ok(val) => val,
-                    Err(err) => {
-                        error!("error in websocket recv {:?}", err);
-                        if let Err(err) = stream
-                            .close(Some(CloseFrame {
-                                code: CloseCode::Normal,
-                                reason: "Client requested connection close.".into(),
-                            }))
-                            .await
-                        {
-                            error!("can't close websocket err={:?}", err);
-                        };
-                        continue 'reconnection_loop;
-                    }
-                };
-
-                let binance_message = match raw_msg {
-                    Message::Text(raw_msg) => {
-                        let value: BinanceOrderBookUpdate = serde_json::from_str(&raw_msg).unwrap();
-                        value
-                    }
-                    Message::Binary(raw_msg) => {
-                        let mut d = GzDecoder::new(&*raw_msg);
-                        let mut s = String::new();
-                        d.read_to_string(&mut s).unwrap();
-                        let value: BinanceOrderBookUpdate = serde_json::from_str(&s).unwrap();
-                        value
-                    }
-                    Message::Ping(_) => {
-                        if let Err(err) = stream.send(Message::Pong("pong".into())).await {
-                            error!("failed to send pong. err={:?}", err)
-                        };
-                        continue;
-                    }
-                    other => {
-                        error!("received unexpected message={:?}", other);
-                        continue;
-                    }
-                };
-
-                let mut order_book_update: OrderBookUpdate = binance_message.into();
-                order_book_update.exchange_id = Some(self.exchange_id);
-                if let Err(err) = pub_chan.send(order_book_update).await {
-                    error!("can't send update to chan. err={:?}", err);
-                    return;
+/// Local book maintained from diff depth events, see
+/// https://binance-docs.github.io/apidocs/spot/en/#how-to-manage-a-local-order-book-correctly
+struct BinanceDiffFeed {
+    subscription_url: String,
+    snapshot_url: String,
+    book: L2Book,
+    /// `lastUpdateId` of the snapshot, then `u` of the last applied event.
+    last_update_id: Option<u64>,
+    first_event_applied: bool,
+}
+
+impl BinanceDiffFeed {
+    fn new(exchange_symbol: &str) -> Self {
+        Self {
+            subscription_url: format!(
+                "wss://stream.binance.com:443/ws/{}@depth@100ms",
+                exchange_symbol.to_lowercase()
+            ),
+            snapshot_url: format!(
+                "https://api.binance.com/api/v3/depth?symbol={}&limit={}",
+                exchange_symbol.to_uppercase(),
+                SNAPSHOT_LIMIT
+            ),
+            book: L2Book::new(),
+            last_update_id: None,
+            first_event_applied: false,
+        }
+    }
+
+    fn apply_snapshot(&mut self, snapshot: BinanceDepthSnapshot) {
+        self.book.clear();
+        for quote in snapshot.bids {
+            self.book.update_bid(quote.price, quote.qty);
+        }
+        for quote in snapshot.asks {
+            self.book.update_ask(quote.price, quote.qty);
+        }
+        self.last_update_id = Some(snapshot.last_update_id);
+        self.first_event_applied = false;
+    }
+}
+
+#[async_trait]
+impl FeedProtocol for BinanceDiffFeed {
+    fn url(&self) -> String {
+        self.subscription_url.clone()
+    }
+
+    fn subscription_messages(&self) -> Vec<Message> {
+        // stream is selected by url
+        vec![]
+    }
+
+    fn reset(&mut self) {
+        self.book.clear();
+        self.last_update_id = None;
+        self.first_event_applied = false;
+    }
+
+    async fn fetch_sync_snapshot(&mut self) -> Result<Option<String>, String> {
+        let response = reqwest::get(&self.snapshot_url)
+            .await
+            .map_err(|err| format!("failed to request binance depth snapshot. err={:?}", err))?;
+        let snapshot = response
+            .text()
+            .await
+            .map_err(|err| format!("failed to request binance depth snapshot. err={:?}", err))?;
+        Ok(Some(snapshot))
+    }
+
+    fn sync(&mut self, snapshot: &str) -> bool {
+        self.reset();
+        match serde_json::from_str::<BinanceDepthSnapshot>(snapshot) {
+            Ok(snapshot) => {
+                info!(
+                    "binance depth snapshot lastUpdateId={}",
+                    snapshot.last_update_id
+                );
+                self.apply_snapshot(snapshot);
+                true
+            }
+            Err(err) => {
+                error!("failed to parse binance depth snapshot. err={:?}", err);
+                false
+            }
+        }
+    }
+
+    fn parse(&mut self, raw_msg: &str) -> FeedEvent {
+        let event: BinanceDepthUpdate = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to parse binance message={}. err={:?}", raw_msg, err);
+                return FeedEvent::Skip;
+            }
+        };
+        let last_update_id = match self.last_update_id {
+            Some(val) => val,
+            None => return FeedEvent::Resync,
+        };
+
+        // buffered event is already part of the snapshot
+        if event.final_update_id <= last_update_id {
+            return FeedEvent::Skip;
+        }
+        if self.first_event_applied {
+            if event.first_update_id != last_update_id + 1 {
+                error!(
+                    "binance depth gap. U={} last u={}",
+                    event.first_update_id, last_update_id
+                );
+                return FeedEvent::Resync;
+            }
+        } else if event.first_update_id > last_update_id + 1 {
+            error!(
+                "binance depth snapshot is older than stream. U={} lastUpdateId={}",
+                event.first_update_id, last_update_id
+            );
+            return FeedEvent::Resync;
+        }
+
+        for quote in event.bids {
+            self.book.update_bid(quote.price, quote.qty);
+        }
+        for quote in event.asks {
+            self.book.update_ask(quote.price, quote.qty);
+        }
+        self.last_update_id = Some(event.final_update_id);
+        self.first_event_applied = true;
+
+        let mut order_book_update = self.book.snapshot(DIFF_DEPTH);
+        // event time is in milliseconds
+        order_book_update.exchange_time = Some(event.event_time * 1000);
+        FeedEvent::Update(order_book_update)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinanceDepthSnapshot, BinanceDiffFeed};
+    use crate::common::model::OrderBookUpdate;
+    use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+    use rust_decimal_macros::dec;
+
+    const SNAPSHOT_FIXTURE: &str = include_str!("../../fixtures/binance_depth_snapshot.json");
+    const DIFF_FIXTURE: &str = include_str!("../../fixtures/binance_diff_depth.jsonl");
+
+    fn synced_feed() -> BinanceDiffFeed {
+        let mut feed = BinanceDiffFeed::new("BTCUSDT");
+        let snapshot: BinanceDepthSnapshot = serde_json::from_str(SNAPSHOT_FIXTURE).unwrap();
+        feed.apply_snapshot(snapshot);
+        feed
+    }
+
+    #[test]
+    fn diff_events_applied_after_snapshot() {
+        let mut feed = synced_feed();
+        let mut updates: Vec<OrderBookUpdate> = vec![];
+        let mut resynced = false;
+        for line in DIFF_FIXTURE.lines() {
+            match feed.parse(line) {
+                FeedEvent::Update(val) => updates.push(val),
+                FeedEvent::Skip => {}
+                FeedEvent::Resync => {
+                    resynced = true;
+                    break;
                 }
+                _ => panic!("unexpected feed event on {}", line),
             }
         }
+
+        assert!(resynced, "gap in update ids must trigger resync");
+        // first event is covered by snapshot, next one straddles lastUpdateId
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].exchange_time, Some(1672515782236000));
+
+        let last = updates.last().unwrap();
+        let bids: Vec<_> = last.bid_changes.iter().map(|q| (q.price, q.qty)).collect();
+        let asks: Vec<_> = last.ask_changes.iter().map(|q| (q.price, q.qty)).collect();
+        assert_eq!(
+            bids,
+            vec![
+                (dec!(30000.5), dec!(1.0)),
+                (dec!(30000.0), dec!(2.5)),
+                (dec!(29999.0), dec!(3.0))
+            ]
+        );
+        assert_eq!(
+            asks,
+            vec![(dec!(30001.5), dec!(0.7)), (dec!(30002.0), dec!(2.0))]
+        );
+    }
+
+    #[test]
+    fn snapshot_older_than_stream_resyncs() {
+        let mut feed = synced_feed();
+        let last = DIFF_FIXTURE.lines().last().unwrap();
+        assert!(matches!(feed.parse(last), FeedEvent::Resync));
+    }
+
+    #[test]
+    fn events_before_sync_resync() {
+        let mut feed = BinanceDiffFeed::new("BTCUSDT");
+        let first = DIFF_FIXTURE.lines().next().unwrap();
+        assert!(matches!(feed.parse(first), FeedEvent::Resync));
     }
 }
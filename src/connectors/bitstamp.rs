@@ -1,171 +1,388 @@
 use crate::common::model::{ExchangeQuote, OrderBookUpdate};
-use futures::{SinkExt, StreamExt};
+use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+use crate::connectors::{ConnectorCapabilities, ExchangeConnector};
+use crate::orderbook::book::L2Book;
+use async_trait::async_trait;
 use serde::Deserialize;
 use serde_json::json;
 use serde_with::{serde_as, DisplayFromStr};
-use std::thread;
-use std::time::Duration;
-use tokio::sync::mpsc::Sender;
-use tokio_tungstenite::connect_async;
-use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
-use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{error, info};
 
+pub const NAME: &str = "bitstamp";
+
+const DEPTH: usize = 100;
+const DIFF_DEPTH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BitstampStreamMode {
+    /// `order_book_{pair}` channel, every message is a top 100 snapshot.
+    Partial,
+    /// `diff_order_book_{pair}` channel synced against `/api/v2/order_book/{pair}/` snapshot.
+    Diff,
+}
+
 pub struct BitstampOrderBookListener {
     exchange_symbol: String,
     exchange_id: usize,
+    mode: BitstampStreamMode,
 }
 
 #[derive(Deserialize)]
-struct WsResponseSubscribe {
+struct WsResponse {
     event: String,
     channel: String,
 }
 
 #[derive(Deserialize, Debug)]
 struct WsResponseOrderBookUpdate {
-    event: String,
-    channel: String,
     data: OrderBookUpdateData,
 }
 
+#[serde_as]
 #[derive(Deserialize, Debug)]
 struct OrderBookUpdateData {
+    #[serde_as(as = "DisplayFromStr")]
+    microtimestamp: u64,
     bids: Vec<ExchangeQuote>,
     asks: Vec<ExchangeQuote>,
 }
 
-impl Into<OrderBookUpdate> for WsResponseOrderBookUpdate {
-    fn into(self) -> OrderBookUpdate {
+impl From<WsResponseOrderBookUpdate> for OrderBookUpdate {
+    fn from(value: WsResponseOrderBookUpdate) -> Self {
         OrderBookUpdate {
-            bid_changes: self.data.bids,
-            ask_changes: self.data.asks,
+            bid_changes: value.data.bids,
+            ask_changes: value.data.asks,
             exchange_id: None,
+            is_snapshot: true,
+            exchange_time: Some(value.data.microtimestamp),
+            receive_time: 0,
         }
     }
 }
 
+// {"data": {"timestamp": "1672515782", "microtimestamp": "1672515782136422",
+//  "bids": [["30000", "0.00000000"]], "asks": [["30001", "0.25000000"]]},
+//  "channel": "diff_order_book_btcusd", "event": "data"}
+
+#[derive(Deserialize, Debug)]
+struct WsResponseDiffOrderBook {
+    data: DiffOrderBookData,
+}
+
+/// Same layout is used by diff messages and `/api/v2/order_book/{pair}/` snapshot.
 #[serde_as]
 #[derive(Deserialize, Debug)]
-struct QuoteTuple(
-    #[serde_as(as = "DisplayFromStr")] pub f64,
-    #[serde_as(as = "DisplayFromStr")] pub f64,
-);
+struct DiffOrderBookData {
+    #[serde_as(as = "DisplayFromStr")]
+    microtimestamp: u64,
+    bids: Vec<ExchangeQuote>,
+    asks: Vec<ExchangeQuote>,
+}
 
 impl BitstampOrderBookListener {
     pub fn new(pair: &str, exchange_id: usize) -> Self {
-        let exchange_symbol = pair.replace("/", "");
+        let exchange_symbol = pair.replace('/', "");
         Self {
             exchange_symbol,
             exchange_id,
+            mode: BitstampStreamMode::Partial,
         }
     }
 
-    pub async fn run(&self, pub_chan: Sender<OrderBookUpdate>) {
-        let subscription_url = "wss://ws.bitstamp.net";
-        let channel_name = format!("order_book_{}", &self.exchange_symbol.to_lowercase());
-        let sub_message_json = json!({
-            "event": "bts:subscribe",
-            "data": {
-            "channel": channel_name
-            }
-        });
-
-        info!("bitstamp sub_message_json={:?}", &sub_message_json);
-
-        let sub_message = serde_json::to_vec(&sub_message_json).unwrap();
-        'reconnection_loop: loop {
-            {
-                // Send empty bid and asks to reset collector quotes
-                let order_book_update = OrderBookUpdate {
-                    exchange_id: Some(self.exchange_id),
-                    bid_changes: vec![],
-                    ask_changes: vec![],
-                };
-
-                if let Err(err) = pub_chan.send(order_book_update).await {
-                    error!("can't send update to chan. err={:?}", err);
-                    return;
-                }
+    pub fn with_mode(mut self, mode: BitstampStreamMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+impl ExchangeConnector for BitstampOrderBookListener {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn exchange_id(&self) -> usize {
+        self.exchange_id
+    }
+
+    fn capabilities(&self) -> ConnectorCapabilities {
+        match self.mode {
+            BitstampStreamMode::Partial => ConnectorCapabilities {
+                max_depth: Some(DEPTH),
+                incremental: false,
+                checksum: false,
+                multiplex: true,
+            },
+            BitstampStreamMode::Diff => ConnectorCapabilities {
+                max_depth: Some(DIFF_DEPTH),
+                incremental: true,
+                checksum: false,
+                multiplex: true,
+            },
+        }
+    }
+
+    fn feed(&self) -> Box<dyn FeedProtocol> {
+        let pair = self.exchange_symbol.to_lowercase();
+        match self.mode {
+            BitstampStreamMode::Partial => Box::new(BitstampFeed {
+                channel_name: format!("order_book_{}", pair),
+            }),
+            BitstampStreamMode::Diff => Box::new(BitstampDiffFeed::new(&pair)),
+        }
+    }
+}
+
+fn subscription_message(channel_name: &str) -> Message {
+    let sub_message_json = json!({
+        "event": "bts:subscribe",
+        "data": {
+        "channel": channel_name
+        }
+    });
+    Message::Text(sub_message_json.to_string())
+}
+
+/// Handles service events. Returns `None` for book data of the subscribed channel.
+fn service_event(raw_msg: &str, channel_name: &str) -> Option<FeedEvent> {
+    let response: WsResponse = match serde_json::from_str(raw_msg) {
+        Ok(val) => val,
+        Err(err) => {
+            error!(
+                "failed to parse bitstamp message={}. err={:?}",
+                raw_msg, err
+            );
+            return Some(FeedEvent::Skip);
+        }
+    };
+    if response.channel != channel_name {
+        // other channel of the same connection
+        return Some(FeedEvent::Skip);
+    }
+
+    match response.event.as_str() {
+        "data" => None,
+        "bts:subscription_succeeded" => {
+            info!("subscribed to bitstamp channel={}", channel_name);
+            Some(FeedEvent::Skip)
+        }
+        "bts:request_reconnect" => Some(FeedEvent::Reconnect),
+        _ => {
+            error!("unexpected bitstamp response={}", raw_msg);
+            Some(FeedEvent::Reconnect)
+        }
+    }
+}
+
+struct BitstampFeed {
+    channel_name: String,
+}
+
+impl FeedProtocol for BitstampFeed {
+    fn url(&self) -> String {
+        "wss://ws.bitstamp.net".to_string()
+    }
+
+    fn subscription_messages(&self) -> Vec<Message> {
+        vec![subscription_message(&self.channel_name)]
+    }
+
+    fn parse(&mut self, raw_msg: &str) -> FeedEvent {
+        if let Some(event) = service_event(raw_msg, &self.channel_name) {
+            return event;
+        }
+        match serde_json::from_str::<WsResponseOrderBookUpdate>(raw_msg) {
+            Ok(val) => FeedEvent::Update(val.into()),
+            Err(err) => {
+                error!("failed to parse bitstamp book={}. err={:?}", raw_msg, err);
+                FeedEvent::Skip
             }
+        }
+    }
+}
 
-            thread::sleep(Duration::from_secs(1)); // prevents ws spamming
-            info!("subscribing to bistamp websocket data");
+/// Local book maintained from diff messages, see "How to reconstruct the order book"
+/// at https://www.bitstamp.net/websocket/v2/
+struct BitstampDiffFeed {
+    channel_name: String,
+    snapshot_url: String,
+    book: L2Book,
+    /// `microtimestamp` of the snapshot, then of the last applied diff.
+    last_microtimestamp: Option<u64>,
+    diff_applied: bool,
+}
 
-            let (mut stream, _) = match connect_async(subscription_url.clone()).await {
-                Ok(val) => val,
-                Err(err) => {
-                    error!("failed to connect. err={:?}", err);
-                    thread::sleep(Duration::from_secs(5));
-                    continue 'reconnection_loop;
-                }
-            };
+impl BitstampDiffFeed {
+    fn new(pair: &str) -> Self {
+        Self {
+            channel_name: format!("diff_order_book_{}", pair),
+            snapshot_url: format!("https://www.bitstamp.net/api/v2/order_book/{}/", pair),
+            book: L2Book::new(),
+            last_microtimestamp: None,
+            diff_applied: false,
+        }
+    }
 
-            if let Err(err) = stream.send(Message::Binary(sub_message.clone())).await {
-                error!("failed to send sub message. err={:?}", err);
-                continue 'reconnection_loop;
-            };
+    fn apply_snapshot(&mut self, snapshot: DiffOrderBookData) {
+        self.book.clear();
+        self.apply(snapshot);
+        self.diff_applied = false;
+    }
 
-            let sub_confirmation = stream.next().await;
-            if sub_confirmation.is_none() {
-                error!("expected confirmation message, got none instead");
-                continue 'reconnection_loop;
+    fn apply(&mut self, data: DiffOrderBookData) {
+        for quote in data.bids {
+            self.book.update_bid(quote.price, quote.qty);
+        }
+        for quote in data.asks {
+            self.book.update_ask(quote.price, quote.qty);
+        }
+        self.last_microtimestamp = Some(data.microtimestamp);
+    }
+}
+
+#[async_trait]
+impl FeedProtocol for BitstampDiffFeed {
+    fn url(&self) -> String {
+        "wss://ws.bitstamp.net".to_string()
+    }
+
+    fn subscription_messages(&self) -> Vec<Message> {
+        vec![subscription_message(&self.channel_name)]
+    }
+
+    fn reset(&mut self) {
+        self.book.clear();
+        self.last_microtimestamp = None;
+        self.diff_applied = false;
+    }
+
+    async fn fetch_sync_snapshot(&mut self) -> Result<Option<String>, String> {
+        let response = reqwest::get(&self.snapshot_url)
+            .await
+            .map_err(|err| format!("failed to request bitstamp order book. err={:?}", err))?;
+        let snapshot = response
+            .text()
+            .await
+            .map_err(|err| format!("failed to request bitstamp order book. err={:?}", err))?;
+        Ok(Some(snapshot))
+    }
+
+    fn sync(&mut self, snapshot: &str) -> bool {
+        self.reset();
+        match serde_json::from_str::<DiffOrderBookData>(snapshot) {
+            Ok(snapshot) => {
+                info!(
+                    "bitstamp order book snapshot microtimestamp={}",
+                    snapshot.microtimestamp
+                );
+                self.apply_snapshot(snapshot);
+                true
+            }
+            Err(err) => {
+                error!("failed to parse bitstamp order book. err={:?}", err);
+                false
             }
+        }
+    }
 
-            let sub_confirmation: WsResponseSubscribe = match sub_confirmation.unwrap() {
-                Ok(Message::Text(raw_msg)) => serde_json::from_str(&raw_msg).unwrap(),
-                other => {
-                    error!("unexpected message ={:?}", other);
-                    continue 'reconnection_loop;
-                }
-            };
-            if sub_confirmation.channel != channel_name
-                || sub_confirmation.event != "bts:subscription_succeeded"
-            {
-                error!("subscription failed: {:?}", sub_message);
-                continue 'reconnection_loop;
+    fn parse(&mut self, raw_msg: &str) -> FeedEvent {
+        if let Some(event) = service_event(raw_msg, &self.channel_name) {
+            return event;
+        }
+        let message: WsResponseDiffOrderBook = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to parse bitstamp diff={}. err={:?}", raw_msg, err);
+                return FeedEvent::Skip;
             }
-            loop {
-                let rcv = stream.next().await;
-                if rcv.is_none() {
-                    continue;
-                }
-                let raw_msg = match rcv.unwrap() {
-                    Ok(val) => val,
-                    Err(err) => {
-                        error!("failed to receive websocket message. err={:?}", err);
-                        if let Err(err) = stream
-                            .close(Some(CloseFrame {
-                                code: CloseCode::Normal,
-                                reason: "Client requested connection close.".into(),
-                            }))
-                            .await
-                        {
-                            error!("can't close websocket err={:?}", err);
-                        };
-                        continue 'reconnection_loop;
-                    }
-                };
-
-                let bitstamp_message = match raw_msg {
-                    Message::Text(msg) => {
-                        let value: WsResponseOrderBookUpdate = serde_json::from_str(&msg).unwrap();
-                        value
-                    }
-                    other => {
-                        error!("unexpected response ={:?}", other);
-                        continue;
-                    }
-                };
-
-                let mut order_book_update: OrderBookUpdate = bitstamp_message.into();
-                order_book_update.exchange_id = Some(self.exchange_id);
-                if let Err(err) = pub_chan.send(order_book_update).await {
-                    error!("can't send update to chan. err={:?}", err);
-                    return;
+        };
+        let last_microtimestamp = match self.last_microtimestamp {
+            Some(val) => val,
+            None => return FeedEvent::Resync,
+        };
+
+        if message.data.microtimestamp <= last_microtimestamp {
+            if !self.diff_applied {
+                // buffered diff is already part of the snapshot
+                return FeedEvent::Skip;
+            }
+            error!(
+                "bitstamp diff out of order. microtimestamp={} last={}",
+                message.data.microtimestamp, last_microtimestamp
+            );
+            return FeedEvent::Resync;
+        }
+
+        let microtimestamp = message.data.microtimestamp;
+        self.apply(message.data);
+        self.diff_applied = true;
+        let mut order_book_update = self.book.snapshot(DIFF_DEPTH);
+        order_book_update.exchange_time = Some(microtimestamp);
+        FeedEvent::Update(order_book_update)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BitstampDiffFeed, DiffOrderBookData};
+    use crate::common::model::OrderBookUpdate;
+    use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+    use rust_decimal_macros::dec;
+
+    const SNAPSHOT_FIXTURE: &str = include_str!("../../fixtures/bitstamp_order_book_snapshot.json");
+    const DIFF_FIXTURE: &str = include_str!("../../fixtures/bitstamp_diff_order_book.jsonl");
+
+    fn synced_feed() -> BitstampDiffFeed {
+        let mut feed = BitstampDiffFeed::new("btcusd");
+        let snapshot: DiffOrderBookData = serde_json::from_str(SNAPSHOT_FIXTURE).unwrap();
+        feed.apply_snapshot(snapshot);
+        feed
+    }
+
+    #[test]
+    fn diffs_applied_after_snapshot() {
+        let mut feed = synced_feed();
+        let mut updates: Vec<OrderBookUpdate> = vec![];
+        let mut resynced = false;
+        for line in DIFF_FIXTURE.lines() {
+            match feed.parse(line) {
+                FeedEvent::Update(val) => updates.push(val),
+                FeedEvent::Skip => {}
+                FeedEvent::Resync => {
+                    resynced = true;
+                    break;
                 }
+                _ => panic!("unexpected feed event on {}", line),
             }
         }
+
+        assert!(resynced, "out of order diff must trigger resync");
+        assert_eq!(updates.len(), 2);
+
+        let last = updates.last().unwrap();
+        let bids: Vec<_> = last.bid_changes.iter().map(|q| (q.price, q.qty)).collect();
+        let asks: Vec<_> = last.ask_changes.iter().map(|q| (q.price, q.qty)).collect();
+        assert_eq!(
+            bids,
+            vec![(dec!(30000.5), dec!(0.4)), (dec!(30000.0), dec!(1.0))]
+        );
+        assert_eq!(
+            asks,
+            vec![
+                (dec!(30001.0), dec!(0.25)),
+                (dec!(30002.0), dec!(3.0)),
+                (dec!(30010.0), dec!(5.0))
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_before_sync_resyncs() {
+        let mut feed = BitstampDiffFeed::new("btcusd");
+        let diff = DIFF_FIXTURE
+            .lines()
+            .find(|line| line.contains("\"event\":\"data\""))
+            .unwrap();
+        assert!(matches!(feed.parse(diff), FeedEvent::Resync));
     }
 }
@@ -0,0 +1,260 @@
+use crate::common::model::ExchangeQuote;
+use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+use crate::connectors::{ConnectorCapabilities, ExchangeConnector};
+use crate::orderbook::book::L2Book;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, info};
+
+pub const NAME: &str = "bybit";
+
+const DEPTH: usize = 50;
+
+pub struct BybitOrderBookListener {
+    exchange_symbol: String,
+    exchange_id: usize,
+}
+
+// {"topic": "orderbook.50.BTCUSDT", "type": "delta", "ts": 1672304484978,
+//  "data": {"s": "BTCUSDT", "b": [["16493.50", "0.006"]], "a": [["16611.00", "0"]], "u": 18521289, "seq": 7961638725}}
+
+#[derive(Deserialize, Debug)]
+struct WsResponse {
+    op: Option<String>,
+    success: Option<bool>,
+    ret_msg: Option<String>,
+    topic: Option<String>,
+    #[serde(rename = "type")]
+    message_type: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookMessage {
+    /// Event time in milliseconds.
+    ts: u64,
+    data: BookData,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookData {
+    b: Vec<ExchangeQuote>,
+    a: Vec<ExchangeQuote>,
+    u: u64,
+    seq: u64,
+}
+
+impl BybitOrderBookListener {
+    pub fn new(pair: &str, exchange_id: usize) -> Self {
+        Self {
+            exchange_symbol: pair.replace('/', "").to_uppercase(),
+            exchange_id,
+        }
+    }
+}
+
+impl ExchangeConnector for BybitOrderBookListener {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn exchange_id(&self) -> usize {
+        self.exchange_id
+    }
+
+    fn capabilities(&self) -> ConnectorCapabilities {
+        ConnectorCapabilities {
+            max_depth: Some(DEPTH),
+            incremental: true,
+            checksum: false,
+            multiplex: true,
+        }
+    }
+
+    fn feed(&self) -> Box<dyn FeedProtocol> {
+        Box::new(BybitFeed::new(&self.exchange_symbol))
+    }
+}
+
+struct BybitFeed {
+    topic: String,
+    book: L2Book,
+    update_id: Option<u64>,
+    seq: u64,
+}
+
+impl BybitFeed {
+    fn new(exchange_symbol: &str) -> Self {
+        Self {
+            topic: format!("orderbook.{}.{}", DEPTH, exchange_symbol),
+            book: L2Book::new(),
+            update_id: None,
+            seq: 0,
+        }
+    }
+
+    fn subscription(&self, op: &str) -> Message {
+        let message_json = json!({"op": op, "args": [self.topic]});
+        Message::Text(message_json.to_string())
+    }
+
+    fn on_book(&mut self, raw_msg: &str, is_snapshot: bool) -> FeedEvent {
+        let message: BookMessage = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to parse bybit book={}. err={:?}", raw_msg, err);
+                return FeedEvent::Skip;
+            }
+        };
+        let exchange_time = message.ts * 1000;
+        let data = message.data;
+
+        // u=1 is a snapshot sent after bybit service restart
+        if is_snapshot || data.u == 1 {
+            self.book.clear();
+        } else {
+            let last_update_id = match self.update_id {
+                Some(val) => val,
+                // updates of dropped subscription, waiting for the new snapshot
+                None => return FeedEvent::Skip,
+            };
+            if data.seq < self.seq {
+                info!("bybit stale delta. seq={} last seq={}", data.seq, self.seq);
+                return FeedEvent::Skip;
+            }
+            if data.u != last_update_id + 1 {
+                error!(
+                    "bybit update id gap. u={} last u={}",
+                    data.u, last_update_id
+                );
+                self.book.clear();
+                self.update_id = None;
+                return FeedEvent::Resubscribe(vec![
+                    self.subscription("unsubscribe"),
+                    self.subscription("subscribe"),
+                ]);
+            }
+        }
+
+        for quote in data.b {
+            self.book.update_bid(quote.price, quote.qty);
+        }
+        for quote in data.a {
+            self.book.update_ask(quote.price, quote.qty);
+        }
+        self.update_id = Some(data.u);
+        self.seq = data.seq;
+
+        let mut order_book_update = self.book.snapshot(DEPTH);
+        order_book_update.exchange_time = Some(exchange_time);
+        FeedEvent::Update(order_book_update)
+    }
+}
+
+impl FeedProtocol for BybitFeed {
+    fn url(&self) -> String {
+        "wss://stream.bybit.com/v5/public/spot".to_string()
+    }
+
+    fn subscription_messages(&self) -> Vec<Message> {
+        vec![self.subscription("subscribe")]
+    }
+
+    fn reset(&mut self) {
+        self.book.clear();
+        self.update_id = None;
+        self.seq = 0;
+    }
+
+    fn heartbeat(&self) -> Option<(Duration, Message)> {
+        let ping = json!({"op": "ping"});
+        Some((Duration::from_secs(20), Message::Text(ping.to_string())))
+    }
+
+    fn parse(&mut self, raw_msg: &str) -> FeedEvent {
+        let response: WsResponse = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to parse bybit message={}. err={:?}", raw_msg, err);
+                return FeedEvent::Skip;
+            }
+        };
+
+        if response.topic.as_deref() == Some(self.topic.as_str()) {
+            return match response.message_type.as_deref() {
+                Some("snapshot") => self.on_book(raw_msg, true),
+                Some("delta") => self.on_book(raw_msg, false),
+                other => {
+                    error!("unexpected bybit book message type={:?}", other);
+                    FeedEvent::Skip
+                }
+            };
+        }
+
+        match (response.op.as_deref(), response.success) {
+            (Some(op), Some(false)) => {
+                error!("bybit {} failed. err={:?}", op, response.ret_msg);
+                FeedEvent::Reconnect
+            }
+            (Some("subscribe"), _) => {
+                info!("subscribed to bybit topic={}", &self.topic);
+                FeedEvent::Skip
+            }
+            _ => FeedEvent::Skip,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BybitFeed;
+    use crate::common::model::OrderBookUpdate;
+    use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+    use rust_decimal_macros::dec;
+
+    const FIXTURE: &str = include_str!("../../fixtures/bybit_orderbook.jsonl");
+
+    fn replay_fixture(feed: &mut BybitFeed) -> (Vec<OrderBookUpdate>, bool) {
+        let mut updates = vec![];
+        for line in FIXTURE.lines() {
+            match feed.parse(line) {
+                FeedEvent::Update(val) => updates.push(val),
+                FeedEvent::Skip => {}
+                FeedEvent::Resubscribe(_) => return (updates, true),
+                _ => panic!("unexpected feed event on {}", line),
+            }
+        }
+        (updates, false)
+    }
+
+    #[test]
+    fn snapshot_and_deltas() {
+        let mut feed = BybitFeed::new("BTCUSDT");
+        let (updates, resubscribed) = replay_fixture(&mut feed);
+
+        assert!(resubscribed, "update id gap must trigger resubscribe");
+        // snapshot, two deltas, restart snapshot with u=1 and its delta; stale seq is skipped
+        assert_eq!(updates.len(), 5);
+
+        let before_restart = &updates[2];
+        assert_eq!(before_restart.bid_changes[0].price, dec!(16493.5));
+        assert_eq!(before_restart.bid_changes[0].qty, dec!(0.5));
+        assert_eq!(before_restart.ask_changes[0].price, dec!(16612.0));
+
+        let last = &updates[4];
+        assert_eq!(last.bid_changes.len(), 2);
+        assert_eq!(last.bid_changes[0].price, dec!(16500.0));
+        assert_eq!(last.ask_changes.len(), 1);
+        assert_eq!(last.ask_changes[0].price, dec!(16600.0));
+        assert_eq!(last.ask_changes[0].qty, dec!(0.7));
+    }
+
+    #[test]
+    fn other_topics_are_skipped() {
+        let mut feed = BybitFeed::new("ETHUSDT");
+        let (updates, resubscribed) = replay_fixture(&mut feed);
+        assert!(updates.is_empty());
+        assert!(!resubscribed);
+    }
+}
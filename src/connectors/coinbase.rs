@@ -0,0 +1,286 @@
+use crate::common::model::{ExchangeQuote, OrderBookUpdate, Price, Qty};
+use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+use crate::connectors::{ConnectorCapabilities, ExchangeConnector};
+use crate::orderbook::book::L2Book;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use serde_with::{serde_as, DisplayFromStr};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, info};
+
+pub const NAME: &str = "coinbase";
+
+const DEPTH: usize = 50;
+
+pub struct CoinbaseOrderBookListener {
+    product_id: String,
+    exchange_id: usize,
+}
+
+// {"type": "snapshot", "product_id": "BTC-USD", "bids": [["10101.10", "0.45054140"]], "asks": [["10102.55", "0.57753524"]]}
+// {"type": "l2update", "product_id": "BTC-USD", "time": "2019-08-14T20:42:27.265Z", "changes": [["buy", "10101.80000000", "0.162567"]]}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsResponse {
+    Subscriptions,
+    Snapshot(Snapshot),
+    L2update(L2Update),
+    Error {
+        message: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct Snapshot {
+    product_id: String,
+    bids: Vec<ExchangeQuote>,
+    asks: Vec<ExchangeQuote>,
+}
+
+#[serde_as]
+#[derive(Deserialize, Debug)]
+struct L2Update {
+    product_id: String,
+    #[serde_as(as = "DisplayFromStr")]
+    time: DateTime<Utc>,
+    changes: Vec<Change>,
+}
+
+#[serde_as]
+#[derive(Deserialize, Debug)]
+struct Change(
+    Side,
+    #[serde_as(as = "DisplayFromStr")] Price,
+    #[serde_as(as = "DisplayFromStr")] Qty,
+);
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Side {
+    Buy,
+    Sell,
+}
+
+impl CoinbaseOrderBookListener {
+    pub fn new(pair: &str, exchange_id: usize) -> Self {
+        let product_id = pair.replace('/', "-").to_uppercase();
+        Self {
+            product_id,
+            exchange_id,
+        }
+    }
+}
+
+impl ExchangeConnector for CoinbaseOrderBookListener {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn exchange_id(&self) -> usize {
+        self.exchange_id
+    }
+
+    fn capabilities(&self) -> ConnectorCapabilities {
+        ConnectorCapabilities {
+            // level2 snapshot is a full book, top levels of the local book are published
+            max_depth: None,
+            incremental: true,
+            checksum: false,
+            multiplex: true,
+        }
+    }
+
+    fn feed(&self) -> Box<dyn FeedProtocol> {
+        Box::new(CoinbaseFeed::new(&self.product_id))
+    }
+}
+
+struct CoinbaseFeed {
+    product_id: String,
+    snapshot_received: bool,
+    book: L2Book,
+}
+
+impl CoinbaseFeed {
+    fn new(product_id: &str) -> Self {
+        Self {
+            product_id: product_id.to_string(),
+            snapshot_received: false,
+            book: L2Book::new(),
+        }
+    }
+}
+
+impl FeedProtocol for CoinbaseFeed {
+    fn url(&self) -> String {
+        "wss://ws-feed.exchange.coinbase.com".to_string()
+    }
+
+    fn subscription_messages(&self) -> Vec<Message> {
+        let sub_message_json = json!({
+            "type": "subscribe",
+            "product_ids": [self.product_id],
+            "channels": ["level2"]
+        });
+        vec![Message::Text(sub_message_json.to_string())]
+    }
+
+    fn reset(&mut self) {
+        self.snapshot_received = false;
+        self.book.clear();
+    }
+
+    fn parse(&mut self, raw_msg: &str) -> FeedEvent {
+        let response: WsResponse = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!(
+                    "failed to parse coinbase message={}. err={:?}",
+                    raw_msg, err
+                );
+                return FeedEvent::Skip;
+            }
+        };
+
+        match response {
+            WsResponse::Snapshot(snapshot) => {
+                if snapshot.product_id != self.product_id {
+                    return FeedEvent::Skip;
+                }
+                self.snapshot_received = true;
+                self.book.apply(&OrderBookUpdate {
+                    bid_changes: snapshot.bids,
+                    ask_changes: snapshot.asks,
+                    is_snapshot: true,
+                    ..Default::default()
+                });
+                FeedEvent::Update(self.book.snapshot(DEPTH))
+            }
+            WsResponse::L2update(update) => {
+                if update.product_id != self.product_id {
+                    return FeedEvent::Skip;
+                }
+                if !self.snapshot_received {
+                    error!("coinbase l2update received before snapshot");
+                    return FeedEvent::Reconnect;
+                }
+                for Change(side, price, qty) in update.changes {
+                    match side {
+                        Side::Buy => self.book.update_bid(price, qty),
+                        Side::Sell => self.book.update_ask(price, qty),
+                    }
+                }
+                let mut order_book_update = self.book.snapshot(DEPTH);
+                order_book_update.exchange_time = Some(update.time.timestamp_micros() as u64);
+                FeedEvent::Update(order_book_update)
+            }
+            WsResponse::Subscriptions => {
+                info!("subscribed to coinbase level2 product={}", &self.product_id);
+                FeedEvent::Skip
+            }
+            WsResponse::Error { message } => {
+                error!("coinbase error response={}", message);
+                FeedEvent::Reconnect
+            }
+            WsResponse::Other => FeedEvent::Skip,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CoinbaseFeed, DEPTH};
+    use crate::common::model::OrderBookUpdate;
+    use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+    use crate::orderbook::book::L2Book;
+    use rust_decimal_macros::dec;
+
+    const FIXTURE: &str = include_str!("../../fixtures/coinbase_level2.jsonl");
+
+    fn replay_fixture(feed: &mut CoinbaseFeed) -> Vec<OrderBookUpdate> {
+        FIXTURE
+            .lines()
+            .filter_map(|line| match feed.parse(line) {
+                FeedEvent::Update(val) => Some(val),
+                FeedEvent::Skip => None,
+                _ => panic!("unexpected feed event on {}", line),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn snapshot_and_updates() {
+        let mut feed = CoinbaseFeed::new("BTC-USD");
+        let updates = replay_fixture(&mut feed);
+        assert_eq!(updates.len(), 4);
+
+        let snapshot = &updates[0];
+        assert_eq!(snapshot.bid_changes.len(), 3);
+        assert_eq!(snapshot.ask_changes.len(), 3);
+        assert_eq!(snapshot.bid_changes[0].price, dec!(30000.10));
+        assert_eq!(snapshot.ask_changes[0].price, dec!(30000.55));
+
+        // every update is a snapshot of the local book
+        assert!(updates.iter().all(|update| update.is_snapshot));
+        assert_eq!(snapshot.exchange_time, None);
+        assert_eq!(updates[1].exchange_time, Some(1672913701102000));
+        assert_eq!(updates[3].exchange_time, Some(1672913701511000));
+
+        let mut book = L2Book::new();
+        for update in &updates {
+            book.apply(update);
+        }
+        let bids: Vec<_> = book.bids().map(|q| (q.price, q.qty)).collect();
+        let asks: Vec<_> = book.asks().map(|q| (q.price, q.qty)).collect();
+        assert_eq!(
+            bids,
+            vec![
+                (dec!(30000.2), dec!(0.5)),
+                (dec!(30000.1), dec!(0.25)),
+                (dec!(29999.5), dec!(2.0))
+            ]
+        );
+        assert_eq!(
+            asks,
+            vec![(dec!(30000.6), dec!(1.5)), (dec!(30001.0), dec!(3.0))]
+        );
+    }
+
+    #[test]
+    fn update_before_snapshot_reconnects() {
+        let mut feed = CoinbaseFeed::new("BTC-USD");
+        let update = FIXTURE
+            .lines()
+            .find(|line| line.contains("l2update"))
+            .unwrap();
+        assert!(matches!(feed.parse(update), FeedEvent::Reconnect));
+    }
+
+    #[test]
+    fn other_products_are_skipped() {
+        let mut feed = CoinbaseFeed::new("ETH-USD");
+        assert!(replay_fixture(&mut feed).is_empty());
+    }
+
+    #[test]
+    fn snapshot_is_limited_to_depth() {
+        let mut feed = CoinbaseFeed::new("BTC-USD");
+        let bids: Vec<String> = (0..DEPTH + 10)
+            .map(|level| format!("[\"{}\",\"1\"]", 30000 - level))
+            .collect();
+        let snapshot = format!(
+            r#"{{"type":"snapshot","product_id":"BTC-USD","bids":[{}],"asks":[["30001","1"]]}}"#,
+            bids.join(",")
+        );
+        let update = match feed.parse(&snapshot) {
+            FeedEvent::Update(val) => val,
+            _ => panic!("snapshot is not parsed"),
+        };
+        assert_eq!(update.bid_changes.len(), DEPTH);
+        assert_eq!(update.ask_changes.len(), 1);
+    }
+}
@@ -0,0 +1,370 @@
+use crate::common::model::{ExchangeQuote, Price, Qty};
+use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+use crate::connectors::{ConnectorCapabilities, ExchangeConnector};
+use crate::orderbook::book::L2Book;
+use serde::Deserialize;
+use serde_json::json;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, info};
+
+pub const NAME: &str = "kraken";
+
+const DEPTH: usize = 25;
+const CHECKSUM_DEPTH: usize = 10;
+
+pub struct KrakenOrderBookListener {
+    exchange_symbol: String,
+    exchange_id: usize,
+}
+
+// {"channel": "book", "type": "update", "data": [{"symbol": "BTC/USD", "bids": [{"price": 30000.1, "qty": 0.5}],
+//  "asks": [], "checksum": 2114181697, "timestamp": "2023-10-06T17:35:55.440295Z"}]}
+
+#[derive(Deserialize, Debug)]
+struct WsResponse {
+    channel: Option<String>,
+    #[serde(rename = "type")]
+    message_type: Option<String>,
+    method: Option<String>,
+    success: Option<bool>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookMessage {
+    data: Vec<BookData>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookData {
+    symbol: String,
+    bids: Vec<KrakenQuote>,
+    asks: Vec<KrakenQuote>,
+    checksum: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct KrakenQuote {
+    price: Price,
+    qty: Qty,
+}
+
+#[derive(Deserialize, Debug)]
+struct InstrumentMessage {
+    data: InstrumentData,
+}
+
+#[derive(Deserialize, Debug)]
+struct InstrumentData {
+    pairs: Vec<InstrumentPair>,
+}
+
+#[derive(Deserialize, Debug)]
+struct InstrumentPair {
+    symbol: String,
+    price_precision: usize,
+    qty_precision: usize,
+}
+
+impl KrakenOrderBookListener {
+    pub fn new(pair: &str, exchange_id: usize) -> Self {
+        Self {
+            exchange_symbol: pair.to_uppercase(),
+            exchange_id,
+        }
+    }
+}
+
+impl ExchangeConnector for KrakenOrderBookListener {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn exchange_id(&self) -> usize {
+        self.exchange_id
+    }
+
+    fn capabilities(&self) -> ConnectorCapabilities {
+        ConnectorCapabilities {
+            max_depth: Some(DEPTH),
+            incremental: true,
+            checksum: true,
+            // instrument channel is subscribed and dropped by every feed
+            multiplex: false,
+        }
+    }
+
+    fn feed(&self) -> Box<dyn FeedProtocol> {
+        Box::new(KrakenFeed::new(&self.exchange_symbol))
+    }
+}
+
+/// Kraken book checksum: top 10 asks then top 10 bids, every price and qty is formatted
+/// with instrument precision, stripped of the decimal point and leading zeros.
+fn book_checksum(
+    asks: &[ExchangeQuote],
+    bids: &[ExchangeQuote],
+    price_precision: usize,
+    qty_precision: usize,
+) -> u32 {
+    let mut payload = String::new();
+    for quote in asks
+        .iter()
+        .take(CHECKSUM_DEPTH)
+        .chain(bids.iter().take(CHECKSUM_DEPTH))
+    {
+        for (value, precision) in [(quote.price, price_precision), (quote.qty, qty_precision)] {
+            let formatted = format!("{:.*}", precision, value).replace('.', "");
+            payload.push_str(formatted.trim_start_matches('0'));
+        }
+    }
+    crc32fast::hash(payload.as_bytes())
+}
+
+struct KrakenFeed {
+    symbol: String,
+    precision: Option<(usize, usize)>,
+    book: L2Book,
+    snapshot_received: bool,
+}
+
+impl KrakenFeed {
+    fn new(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            precision: None,
+            book: L2Book::new(),
+            snapshot_received: false,
+        }
+    }
+
+    fn book_subscription(&self, method: &str) -> Message {
+        let message_json = json!({
+            "method": method,
+            "params": {
+                "channel": "book",
+                "symbol": [self.symbol],
+                "depth": DEPTH,
+            }
+        });
+        Message::Text(message_json.to_string())
+    }
+
+    fn on_instrument(&mut self, raw_msg: &str) -> FeedEvent {
+        let message: InstrumentMessage = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to parse kraken instruments. err={:?}", err);
+                return FeedEvent::Reconnect;
+            }
+        };
+        let pair = match message.data.pairs.iter().find(|p| p.symbol == self.symbol) {
+            Some(val) => val,
+            None => {
+                error!("kraken doesn't list symbol={}", &self.symbol);
+                return FeedEvent::Reconnect;
+            }
+        };
+        self.precision = Some((pair.price_precision, pair.qty_precision));
+        info!(
+            "kraken symbol={} price_precision={} qty_precision={}",
+            &self.symbol, pair.price_precision, pair.qty_precision
+        );
+
+        let unsubscribe_instrument = json!({
+            "method": "unsubscribe",
+            "params": {"channel": "instrument"}
+        });
+        FeedEvent::Send(vec![
+            Message::Text(unsubscribe_instrument.to_string()),
+            self.book_subscription("subscribe"),
+        ])
+    }
+
+    fn on_book(&mut self, raw_msg: &str, is_snapshot: bool) -> FeedEvent {
+        let (price_precision, qty_precision) = match self.precision {
+            Some(val) => val,
+            None => {
+                error!("kraken book received before instrument precision");
+                return FeedEvent::Reconnect;
+            }
+        };
+        let message: BookMessage = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to parse kraken book={}. err={:?}", raw_msg, err);
+                return FeedEvent::Skip;
+            }
+        };
+
+        let mut book_changed = false;
+        for data in message.data {
+            if data.symbol != self.symbol {
+                continue;
+            }
+            if is_snapshot {
+                self.book.clear();
+                self.snapshot_received = true;
+            } else if !self.snapshot_received {
+                // updates of dropped subscription, waiting for the new snapshot
+                continue;
+            }
+
+            for quote in data.bids {
+                self.book.update_bid(quote.price, quote.qty);
+            }
+            for quote in data.asks {
+                self.book.update_ask(quote.price, quote.qty);
+            }
+            self.book.truncate(DEPTH);
+
+            let checksum = book_checksum(
+                &self.book.top_asks(CHECKSUM_DEPTH),
+                &self.book.top_bids(CHECKSUM_DEPTH),
+                price_precision,
+                qty_precision,
+            );
+            if checksum != data.checksum {
+                error!(
+                    "kraken checksum mismatch. expected={} calculated={}",
+                    data.checksum, checksum
+                );
+                self.book.clear();
+                self.snapshot_received = false;
+                return FeedEvent::Resubscribe(vec![
+                    self.book_subscription("unsubscribe"),
+                    self.book_subscription("subscribe"),
+                ]);
+            }
+            book_changed = true;
+        }
+
+        if !book_changed {
+            return FeedEvent::Skip;
+        }
+        FeedEvent::Update(self.book.snapshot(DEPTH))
+    }
+}
+
+impl FeedProtocol for KrakenFeed {
+    fn url(&self) -> String {
+        "wss://ws.kraken.com/v2".to_string()
+    }
+
+    fn subscription_messages(&self) -> Vec<Message> {
+        // book is subscribed once instrument precision is known
+        let sub_message_json = json!({
+            "method": "subscribe",
+            "params": {"channel": "instrument", "snapshot": true}
+        });
+        vec![Message::Text(sub_message_json.to_string())]
+    }
+
+    fn reset(&mut self) {
+        self.precision = None;
+        self.book.clear();
+        self.snapshot_received = false;
+    }
+
+    fn parse(&mut self, raw_msg: &str) -> FeedEvent {
+        let response: WsResponse = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to parse kraken message={}. err={:?}", raw_msg, err);
+                return FeedEvent::Skip;
+            }
+        };
+
+        match (
+            response.channel.as_deref(),
+            response.message_type.as_deref(),
+        ) {
+            (Some("book"), Some("snapshot")) => self.on_book(raw_msg, true),
+            (Some("book"), Some("update")) => self.on_book(raw_msg, false),
+            (Some("instrument"), Some("snapshot")) => self.on_instrument(raw_msg),
+            (Some(_), _) => FeedEvent::Skip,
+            (None, _) => {
+                if response.success == Some(false) {
+                    error!(
+                        "kraken {:?} failed. err={:?}",
+                        response.method, response.error
+                    );
+                    return FeedEvent::Reconnect;
+                }
+                FeedEvent::Skip
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{book_checksum, KrakenFeed};
+    use crate::common::model::{ExchangeQuote, OrderBookUpdate};
+    use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+    use rust_decimal_macros::dec;
+
+    const FIXTURE: &str = include_str!("../../fixtures/kraken_book.jsonl");
+
+    #[test]
+    fn checksum_formatting() {
+        let asks = vec![ExchangeQuote {
+            price: dec!(0.05005),
+            qty: dec!(0.00000500),
+        }];
+        let bids = vec![ExchangeQuote {
+            price: dec!(0.05),
+            qty: dec!(1.5),
+        }];
+        // "5005" + "500" + "5000" + "150000000"
+        assert_eq!(
+            book_checksum(&asks, &bids, 5, 8),
+            crc32fast::hash(b"50055005000150000000")
+        );
+    }
+
+    #[test]
+    fn snapshot_updates_and_checksum_mismatch() {
+        let mut feed = KrakenFeed::new("BTC/USD");
+        let mut lines = FIXTURE.lines();
+
+        match feed.parse(lines.next().unwrap()) {
+            FeedEvent::Send(messages) => assert_eq!(messages.len(), 2),
+            _ => panic!("book subscription expected after instrument snapshot"),
+        }
+        assert_eq!(feed.precision, Some((1, 8)));
+
+        let mut updates: Vec<OrderBookUpdate> = vec![];
+        let mut resubscribed = false;
+        for line in lines {
+            match feed.parse(line) {
+                FeedEvent::Update(val) => updates.push(val),
+                FeedEvent::Skip => {}
+                FeedEvent::Resubscribe(messages) => {
+                    assert_eq!(messages.len(), 2);
+                    resubscribed = true;
+                    break;
+                }
+                _ => panic!("unexpected feed event on {}", line),
+            }
+        }
+
+        assert!(resubscribed, "corrupted update must trigger resubscribe");
+        assert_eq!(updates.len(), 3);
+
+        let snapshot = &updates[0];
+        assert_eq!(snapshot.bid_changes.len(), 12);
+        assert_eq!(snapshot.ask_changes.len(), 12);
+
+        let last = updates.last().unwrap();
+        assert_eq!(last.bid_changes[0].price, dec!(30000.2));
+        assert_eq!(last.bid_changes[0].qty, dec!(0.75));
+        assert_eq!(last.ask_changes[0].price, dec!(30000.6));
+        assert_eq!(last.ask_changes[0].qty, dec!(0.25));
+        assert!(!last.ask_changes.iter().any(|q| q.price == dec!(30000.5)));
+
+        // book is dropped until the next snapshot
+        assert!(feed.book.top_bids(1).is_empty());
+        assert!(!feed.snapshot_received);
+    }
+}
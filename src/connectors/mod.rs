@@ -1,5 +1,160 @@
 pub mod binance;
 pub mod bitstamp;
+pub mod bybit;
+pub mod coinbase;
+pub mod kraken;
+pub mod okx;
+pub mod recorder;
+pub mod replay;
+pub mod websocket;
 
+use crate::common::model::OrderBookUpdate;
+use async_trait::async_trait;
+use recorder::FrameRecorder;
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+use websocket::{run_feeds, FeedProtocol};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectorCapabilities {
+    /// Maximum number of levels per side the venue publishes, `None` when the full book is available.
+    pub max_depth: Option<usize>,
+    /// Venue sends incremental changes on top of an initial snapshot.
+    pub incremental: bool,
+    /// Venue publishes a checksum the local book can be validated against.
+    pub checksum: bool,
+    /// Feeds of several symbols can share one connection, see [`run_connectors`].
+    pub multiplex: bool,
+}
 
+#[async_trait]
+pub trait ExchangeConnector: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn exchange_id(&self) -> usize;
+    fn capabilities(&self) -> ConnectorCapabilities;
+
+    /// Builds protocol state for a new `run` session. State is reset on every reconnect.
+    fn feed(&self) -> Box<dyn FeedProtocol>;
+
+    /// Frames are recorded with a `recorder`, see [`recorder::FrameRecorder`].
+    async fn run(&self, pub_chan: Sender<OrderBookUpdate>, recorder: Option<FrameRecorder>) {
+        run_feeds(
+            vec![(self.exchange_id(), self.feed())],
+            self.name(),
+            pub_chan,
+            recorder,
+        )
+        .await
+    }
+}
+
+/// Runs feeds of connectors of the same venue on one connection.
+pub async fn run_connectors(
+    connectors: Vec<Box<dyn ExchangeConnector>>,
+    pub_chan: Sender<OrderBookUpdate>,
+    recorder: Option<FrameRecorder>,
+) {
+    let name = match connectors.first() {
+        Some(val) => val.name(),
+        None => return,
+    };
+    let feeds = connectors
+        .iter()
+        .map(|connector| (connector.exchange_id(), connector.feed()))
+        .collect();
+    run_feeds(feeds, name, pub_chan, recorder).await
+}
+
+pub type ConnectorFactory = fn(&str, usize) -> Box<dyn ExchangeConnector>;
+
+pub struct ConnectorRegistry {
+    factories: HashMap<&'static str, ConnectorFactory>,
+}
+
+impl ConnectorRegistry {
+    pub fn new() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, name: &'static str, factory: ConnectorFactory) {
+        self.factories.insert(name, factory);
+    }
+
+    pub fn build(
+        &self,
+        name: &str,
+        pair: &str,
+        exchange_id: usize,
+    ) -> Option<Box<dyn ExchangeConnector>> {
+        self.factories
+            .get(name)
+            .map(|factory| factory(pair, exchange_id))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = self.factories.keys().copied().collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl Default for ConnectorRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(binance::NAME, |pair, exchange_id| {
+            Box::new(binance::BinanceOrderBookListener::new(pair, exchange_id))
+        });
+        registry.register(bitstamp::NAME, |pair, exchange_id| {
+            Box::new(bitstamp::BitstampOrderBookListener::new(pair, exchange_id))
+        });
+        registry.register(coinbase::NAME, |pair, exchange_id| {
+            Box::new(coinbase::CoinbaseOrderBookListener::new(pair, exchange_id))
+        });
+        registry.register(kraken::NAME, |pair, exchange_id| {
+            Box::new(kraken::KrakenOrderBookListener::new(pair, exchange_id))
+        });
+        registry.register(okx::NAME, |pair, exchange_id| {
+            Box::new(okx::OkxOrderBookListener::new(pair, exchange_id))
+        });
+        registry.register(bybit::NAME, |pair, exchange_id| {
+            Box::new(bybit::BybitOrderBookListener::new(pair, exchange_id))
+        });
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{binance, bitstamp, kraken, ConnectorRegistry};
+
+    #[test]
+    fn registry_lookup() {
+        let registry = ConnectorRegistry::default();
+        let connector = registry.build(kraken::NAME, "XBT/USD", 4).unwrap();
+        assert_eq!(connector.name(), kraken::NAME);
+        assert_eq!(connector.exchange_id(), 4);
+        assert!(registry.build("unknown", "BTCUSDT", 0).is_none());
+        assert_eq!(
+            registry.names(),
+            vec!["binance", "bitstamp", "bybit", "coinbase", "kraken", "okx"]
+        );
+    }
+
+    #[test]
+    fn registry_replaces_factory_of_same_name() {
+        let mut registry = ConnectorRegistry::new();
+        assert!(registry.names().is_empty());
+        registry.register(binance::NAME, |pair, exchange_id| {
+            Box::new(binance::BinanceOrderBookListener::new(pair, exchange_id))
+        });
+        // a factory of a name in use replaces the previous one
+        registry.register(binance::NAME, |pair, exchange_id| {
+            Box::new(bitstamp::BitstampOrderBookListener::new(pair, exchange_id))
+        });
+        assert_eq!(registry.names(), vec![binance::NAME]);
+        let connector = registry.build(binance::NAME, "btcusdt", 0).unwrap();
+        assert_eq!(connector.name(), bitstamp::NAME);
+    }
+}
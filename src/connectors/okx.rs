@@ -0,0 +1,357 @@
+use crate::common::model::{ExchangeQuote, Price, Qty};
+use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+use crate::connectors::{ConnectorCapabilities, ExchangeConnector};
+use crate::orderbook::book::L2Book;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use serde_json::json;
+use serde_with::{serde_as, DisplayFromStr};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, info};
+
+pub const NAME: &str = "okx";
+
+const DEPTH: usize = 50;
+const CHECKSUM_DEPTH: usize = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OkxBookChannel {
+    /// 400 levels, snapshot followed by incremental updates with checksum.
+    Books,
+    /// 5 levels, every message is a snapshot.
+    Books5,
+}
+
+impl OkxBookChannel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OkxBookChannel::Books => "books",
+            OkxBookChannel::Books5 => "books5",
+        }
+    }
+}
+
+pub struct OkxOrderBookListener {
+    inst_id: String,
+    exchange_id: usize,
+    channel: OkxBookChannel,
+}
+
+// {"arg": {"channel": "books", "instId": "BTC-USDT"}, "action": "update", "data": [{"asks": [["8476.98", "415", "0", "13"]],
+//  "bids": [], "ts": "1597026383085", "checksum": -855196043, "prevSeqId": 123455, "seqId": 123456}]}
+
+#[derive(Deserialize, Debug)]
+struct WsResponse {
+    event: Option<String>,
+    msg: Option<String>,
+    arg: Option<Arg>,
+    action: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Arg {
+    channel: String,
+    inst_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BookMessage {
+    data: Vec<BookData>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BookData {
+    bids: Vec<OkxQuote>,
+    asks: Vec<OkxQuote>,
+    checksum: Option<i32>,
+    seq_id: Option<i64>,
+    prev_seq_id: Option<i64>,
+}
+
+// [price, size, deprecated liquidated orders, number of orders]
+#[serde_as]
+#[derive(Deserialize, Debug)]
+struct OkxQuote(
+    #[serde_as(as = "DisplayFromStr")] Price,
+    #[serde_as(as = "DisplayFromStr")] Qty,
+    IgnoredAny,
+    IgnoredAny,
+);
+
+impl OkxOrderBookListener {
+    pub fn new(pair: &str, exchange_id: usize) -> Self {
+        Self {
+            inst_id: pair.replace('/', "-").to_uppercase(),
+            exchange_id,
+            channel: OkxBookChannel::Books,
+        }
+    }
+
+    pub fn with_channel(mut self, channel: OkxBookChannel) -> Self {
+        self.channel = channel;
+        self
+    }
+}
+
+impl ExchangeConnector for OkxOrderBookListener {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn exchange_id(&self) -> usize {
+        self.exchange_id
+    }
+
+    fn capabilities(&self) -> ConnectorCapabilities {
+        match self.channel {
+            OkxBookChannel::Books => ConnectorCapabilities {
+                max_depth: Some(DEPTH),
+                incremental: true,
+                checksum: true,
+                multiplex: true,
+            },
+            OkxBookChannel::Books5 => ConnectorCapabilities {
+                max_depth: Some(5),
+                incremental: false,
+                checksum: false,
+                multiplex: true,
+            },
+        }
+    }
+
+    fn feed(&self) -> Box<dyn FeedProtocol> {
+        Box::new(OkxFeed::new(&self.inst_id, self.channel))
+    }
+}
+
+/// OKX book checksum: top 25 bids and asks interleaved as `bid:qty:ask:qty:...`,
+/// remaining levels of the deeper side are appended. Decimals keep the exchange string scale,
+/// so prices and sizes are hashed as received. Crc32 is compared as signed integer.
+fn book_checksum(bids: &[ExchangeQuote], asks: &[ExchangeQuote]) -> i32 {
+    let mut fields = Vec::with_capacity(CHECKSUM_DEPTH * 4);
+    for i in 0..CHECKSUM_DEPTH {
+        for side in [bids, asks] {
+            if let Some(quote) = side.get(i) {
+                fields.push(quote.price.to_string());
+                fields.push(quote.qty.to_string());
+            }
+        }
+    }
+    crc32fast::hash(fields.join(":").as_bytes()) as i32
+}
+
+struct OkxFeed {
+    inst_id: String,
+    channel: OkxBookChannel,
+    book: L2Book,
+    seq_id: Option<i64>,
+}
+
+impl OkxFeed {
+    fn new(inst_id: &str, channel: OkxBookChannel) -> Self {
+        Self {
+            inst_id: inst_id.to_string(),
+            channel,
+            book: L2Book::new(),
+            seq_id: None,
+        }
+    }
+
+    fn subscription(&self, op: &str) -> Message {
+        let message_json = json!({
+            "op": op,
+            "args": [{"channel": self.channel.as_str(), "instId": self.inst_id}]
+        });
+        Message::Text(message_json.to_string())
+    }
+
+    fn resubscribe(&mut self) -> FeedEvent {
+        self.book.clear();
+        self.seq_id = None;
+        FeedEvent::Resubscribe(vec![
+            self.subscription("unsubscribe"),
+            self.subscription("subscribe"),
+        ])
+    }
+
+    fn on_book(&mut self, raw_msg: &str, action: Option<&str>) -> FeedEvent {
+        let message: BookMessage = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to parse okx book={}. err={:?}", raw_msg, err);
+                return FeedEvent::Skip;
+            }
+        };
+
+        for data in message.data {
+            match action {
+                // books5 has no action, every message is a full book
+                Some("snapshot") | None => self.book.clear(),
+                _ => {
+                    if self.seq_id.is_none() {
+                        // updates of dropped subscription, waiting for the new snapshot
+                        return FeedEvent::Skip;
+                    }
+                    if data.prev_seq_id != self.seq_id {
+                        error!(
+                            "okx sequence gap. prev_seq_id={:?} last seq_id={:?}",
+                            data.prev_seq_id, self.seq_id
+                        );
+                        return self.resubscribe();
+                    }
+                }
+            }
+            for OkxQuote(price, qty, _, _) in data.bids {
+                self.book.update_bid(price, qty);
+            }
+            for OkxQuote(price, qty, _, _) in data.asks {
+                self.book.update_ask(price, qty);
+            }
+            self.seq_id = data.seq_id;
+
+            if let Some(expected) = data.checksum {
+                let checksum = book_checksum(
+                    &self.book.top_bids(CHECKSUM_DEPTH),
+                    &self.book.top_asks(CHECKSUM_DEPTH),
+                );
+                if checksum != expected {
+                    error!(
+                        "okx checksum mismatch. expected={} calculated={}",
+                        expected, checksum
+                    );
+                    return self.resubscribe();
+                }
+            }
+        }
+
+        FeedEvent::Update(self.book.snapshot(DEPTH))
+    }
+}
+
+impl FeedProtocol for OkxFeed {
+    fn url(&self) -> String {
+        "wss://ws.okx.com:8443/ws/v5/public".to_string()
+    }
+
+    fn subscription_messages(&self) -> Vec<Message> {
+        vec![self.subscription("subscribe")]
+    }
+
+    fn reset(&mut self) {
+        self.book.clear();
+        self.seq_id = None;
+    }
+
+    fn heartbeat(&self) -> Option<(Duration, Message)> {
+        Some((Duration::from_secs(25), Message::Text("ping".to_string())))
+    }
+
+    fn parse(&mut self, raw_msg: &str) -> FeedEvent {
+        if raw_msg == "pong" {
+            return FeedEvent::Skip;
+        }
+        let response: WsResponse = match serde_json::from_str(raw_msg) {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to parse okx message={}. err={:?}", raw_msg, err);
+                return FeedEvent::Skip;
+            }
+        };
+
+        match (response.event.as_deref(), response.arg) {
+            (Some("error"), _) => {
+                error!("okx error response={:?}", response.msg);
+                FeedEvent::Reconnect
+            }
+            (Some(event), _) => {
+                info!("okx event={} channel={}", event, self.channel.as_str());
+                FeedEvent::Skip
+            }
+            (None, Some(arg))
+                if arg.channel == self.channel.as_str() && arg.inst_id == self.inst_id =>
+            {
+                self.on_book(raw_msg, response.action.as_deref())
+            }
+            (None, _) => FeedEvent::Skip,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OkxBookChannel, OkxFeed};
+    use crate::common::model::OrderBookUpdate;
+    use crate::connectors::websocket::{FeedEvent, FeedProtocol};
+    use rust_decimal_macros::dec;
+
+    const BOOKS_FIXTURE: &str = include_str!("../../fixtures/okx_books.jsonl");
+    const BOOKS5_FIXTURE: &str = include_str!("../../fixtures/okx_books5.jsonl");
+
+    #[test]
+    fn books_snapshot_updates_and_checksum() {
+        let mut feed = OkxFeed::new("BTC-USDT", OkxBookChannel::Books);
+        let mut updates: Vec<OrderBookUpdate> = vec![];
+        let mut resubscribed = false;
+        for line in BOOKS_FIXTURE.lines() {
+            match feed.parse(line) {
+                FeedEvent::Update(val) => updates.push(val),
+                FeedEvent::Skip => {}
+                FeedEvent::Resubscribe(_) => {
+                    resubscribed = true;
+                    break;
+                }
+                _ => panic!("unexpected feed event on {}", line),
+            }
+        }
+
+        assert!(resubscribed, "checksum mismatch must trigger resubscribe");
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[0].bid_changes.len(), 30);
+        assert_eq!(updates[0].ask_changes.len(), 30);
+
+        let last = updates.last().unwrap();
+        assert_eq!(last.bid_changes[0].price, dec!(30000.2));
+        assert_eq!(last.bid_changes[0].qty, dec!(1.25));
+        assert_eq!(last.ask_changes[0].price, dec!(30000.9));
+        assert_eq!(last.ask_changes[0].qty, dec!(0.4));
+    }
+
+    #[test]
+    fn books_sequence_gap_resubscribes() {
+        let mut feed = OkxFeed::new("BTC-USDT", OkxBookChannel::Books);
+        let mut lines = BOOKS_FIXTURE
+            .lines()
+            .filter(|line| line.contains("\"data\""));
+        assert!(matches!(
+            feed.parse(lines.next().unwrap()),
+            FeedEvent::Update(_)
+        ));
+        lines.next();
+        assert!(matches!(
+            feed.parse(lines.next().unwrap()),
+            FeedEvent::Resubscribe(_)
+        ));
+    }
+
+    #[test]
+    fn books5_snapshots() {
+        let mut feed = OkxFeed::new("BTC-USDT", OkxBookChannel::Books5);
+        let updates: Vec<_> = BOOKS5_FIXTURE
+            .lines()
+            .filter_map(|line| match feed.parse(line) {
+                FeedEvent::Update(val) => Some(val),
+                FeedEvent::Skip => None,
+                _ => panic!("unexpected feed event on {}", line),
+            })
+            .collect();
+        assert_eq!(updates.len(), 2);
+        let last = &updates[1];
+        assert_eq!(last.bid_changes.len(), 5);
+        assert_eq!(last.ask_changes.len(), 5);
+        assert_eq!(last.bid_changes[0].price, dec!(30001.0));
+        assert_eq!(last.ask_changes[0].price, dec!(30001.5));
+    }
+}
@@ -0,0 +1,329 @@
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{channel, error::TrySendError, Sender};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Data of a [`RecordedFrame`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    /// Websocket frame, or a new connection.
+    #[default]
+    Frame,
+    /// Out of band book state loaded by [`crate::connectors::websocket::FeedProtocol::sync`]
+    /// of one feed, e.g. the body of a REST depth snapshot.
+    SyncSnapshot,
+}
+
+/// Websocket frame of one connection as it was received.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// Local receive time in microseconds since epoch.
+    pub receive_time: u64,
+    /// Exchange ids of the feeds of the connection, the synced feed of a sync snapshot.
+    pub exchange_ids: Vec<usize>,
+    /// Records written before sync snapshots were recorded are frames.
+    #[serde(default)]
+    pub kind: RecordKind,
+    /// Text frame, binary frames are stored decompressed.
+    /// `None` marks a new connection, collector quotes of the feeds were reset.
+    pub frame: Option<String>,
+}
+
+/// Writes records as gzip compressed json lines to append-only files of `dir`.
+/// A new file `{prefix}-{first record time}-{counter}.jsonl.gz` is started once the current one
+/// reaches `max_file_bytes` of uncompressed data or gets older than `max_file_age`. The counter
+/// tells apart files started at the same time.
+pub struct RotatingWriter {
+    dir: PathBuf,
+    prefix: String,
+    max_file_bytes: u64,
+    max_file_age: Duration,
+    flush_period: Duration,
+    file: Option<OpenFile>,
+}
+
+struct OpenFile {
+    path: PathBuf,
+    encoder: GzEncoder<File>,
+    written: u64,
+    opened_at: Instant,
+    flushed_at: Instant,
+}
+
+impl RotatingWriter {
+    pub fn new(dir: impl Into<PathBuf>, prefix: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            prefix: prefix.to_string(),
+            max_file_bytes: 100 * 1024 * 1024,
+            max_file_age: Duration::from_secs(3600),
+            flush_period: Duration::from_secs(1),
+            file: None,
+        }
+    }
+
+    pub fn with_max_file_bytes(mut self, max_file_bytes: u64) -> Self {
+        self.max_file_bytes = max_file_bytes;
+        self
+    }
+
+    pub fn with_max_file_age(mut self, max_file_age: Duration) -> Self {
+        self.max_file_age = max_file_age;
+        self
+    }
+
+    /// Compressed data is flushed to the file at most once per period, a crash loses the
+    /// records of the last period only.
+    pub fn with_flush_period(mut self, flush_period: Duration) -> Self {
+        self.flush_period = flush_period;
+        self
+    }
+
+    /// `time` names a new file, it should grow with every record.
+    pub fn write<T: Serialize>(&mut self, time: u64, record: &T) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let rotate = match &self.file {
+            Some(file) => {
+                file.written >= self.max_file_bytes || file.opened_at.elapsed() >= self.max_file_age
+            }
+            None => true,
+        };
+        if rotate {
+            self.finish()?;
+            self.file = Some(self.open(time)?);
+        }
+
+        let file = self.file.as_mut().unwrap();
+        file.encoder.write_all(&line)?;
+        file.written += line.len() as u64;
+        if file.flushed_at.elapsed() >= self.flush_period {
+            file.encoder.flush()?;
+            file.flushed_at = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Completes the current file, the next record starts a new one.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.encoder.finish()?;
+            info!("recording file is complete. path={:?}", &file.path);
+        }
+        Ok(())
+    }
+
+    fn open(&self, time: u64) -> std::io::Result<OpenFile> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut counter = 0;
+        let (path, file) = loop {
+            let path = self.dir.join(format!(
+                "{}-{:016}-{:03}.jsonl.gz",
+                self.prefix, time, counter
+            ));
+            match OpenOptions::new().create_new(true).append(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => counter += 1,
+                Err(err) => return Err(err),
+            }
+        };
+        info!("new recording file. path={:?}", &path);
+        let now = Instant::now();
+        Ok(OpenFile {
+            path,
+            encoder: GzEncoder::new(file, Compression::default()),
+            written: 0,
+            opened_at: now,
+            flushed_at: now,
+        })
+    }
+}
+
+impl Drop for RotatingWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            error!("failed to complete recording file. err={:?}", err);
+        }
+    }
+}
+
+/// Handle of the frame recording task, cloned into every connection.
+/// Frames are dropped rather than slowing down the feed when the writer falls behind.
+#[derive(Debug, Clone)]
+pub struct FrameRecorder {
+    sender: Sender<RecordedFrame>,
+}
+
+impl FrameRecorder {
+    /// Starts writing frames on a blocking thread, the thread ends once all handles are dropped.
+    pub fn start(mut writer: RotatingWriter, capacity: usize) -> (Self, JoinHandle<()>) {
+        let (sender, mut receiver) = channel::<RecordedFrame>(capacity);
+        let handle = tokio::task::spawn_blocking(move || {
+            while let Some(frame) = receiver.blocking_recv() {
+                if let Err(err) = writer.write(frame.receive_time, &frame) {
+                    error!("failed to record frame. err={:?}", err);
+                }
+            }
+            if let Err(err) = writer.finish() {
+                error!("failed to complete recording file. err={:?}", err);
+            }
+        });
+        (Self { sender }, handle)
+    }
+
+    pub fn record(&self, frame: RecordedFrame) {
+        match self.sender.try_send(frame) {
+            Ok(()) => {}
+            Err(TrySendError::Full(frame)) => {
+                error!(
+                    "frame recorder is full, frame is dropped. exchange_ids={:?}",
+                    frame.exchange_ids
+                );
+            }
+            Err(TrySendError::Closed(_)) => error!("frame recorder is stopped"),
+        }
+    }
+}
+
+/// Reads records of a file written by [`RotatingWriter`]. Reading stops at a truncated
+/// end of a file that was not completed. Files without the `.gz` extension are read as
+/// plain json lines.
+pub fn read_records<T: DeserializeOwned>(path: &Path) -> std::io::Result<impl Iterator<Item = T>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn BufRead + Send> = if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let path = path.to_path_buf();
+    Ok(reader
+        .lines()
+        .map_while(move |line| match line {
+            Ok(val) => Some(val),
+            Err(err) => {
+                error!("failed to read record. path={:?} err={:?}", &path, err);
+                None
+            }
+        })
+        .filter_map(|line| match serde_json::from_str(&line) {
+            Ok(val) => Some(val),
+            Err(err) => {
+                error!("failed to parse record. line={} err={:?}", line, err);
+                None
+            }
+        }))
+}
+
+/// Recording files of `dir` with `prefix` in the order they were written.
+pub fn recording_files(dir: &Path, prefix: &str) -> std::io::Result<Vec<PathBuf>> {
+    let file_prefix = format!("{}-", prefix);
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&file_prefix) && name.ends_with(".jsonl.gz"))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_records, recording_files, FrameRecorder, RecordKind, RecordedFrame, RotatingWriter,
+    };
+    use std::time::Duration;
+
+    fn frame(receive_time: u64, frame: Option<&str>) -> RecordedFrame {
+        RecordedFrame {
+            receive_time,
+            exchange_ids: vec![0, 2],
+            kind: RecordKind::Frame,
+            frame: frame.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn record_rotate_and_read() {
+        let dir = std::env::temp_dir().join(format!("lob-recorder-{}", std::process::id()));
+        let writer = RotatingWriter::new(&dir, "frames")
+            .with_max_file_bytes(100)
+            .with_flush_period(Duration::ZERO);
+        let (recorder, handle) = FrameRecorder::start(writer, 16);
+
+        let frames = vec![
+            frame(1672515782136422, None),
+            frame(
+                1672515782136423,
+                Some(r#"{"lastUpdateId":1,"bids":[],"asks":[]}"#),
+            ),
+            frame(
+                1672515782136424,
+                Some(r#"{"lastUpdateId":2,"bids":[],"asks":[]}"#),
+            ),
+        ];
+        for frame in &frames {
+            recorder.record(frame.clone());
+        }
+        drop(recorder);
+        handle.await.unwrap();
+
+        // every file but the last one is full
+        let files = recording_files(&dir, "frames").unwrap();
+        assert_eq!(files.len(), 2);
+        let read: Vec<RecordedFrame> = files
+            .iter()
+            .flat_map(|path| read_records(path).unwrap())
+            .collect();
+        assert_eq!(read, frames);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_at_same_time() {
+        let dir = std::env::temp_dir().join(format!("lob-rotate-{}", std::process::id()));
+        let mut writer = RotatingWriter::new(&dir, "frames").with_max_file_bytes(1);
+        let snapshot = RecordedFrame {
+            receive_time: 1672515782136422,
+            exchange_ids: vec![1],
+            kind: RecordKind::SyncSnapshot,
+            frame: Some(r#"{"lastUpdateId":1,"bids":[],"asks":[]}"#.to_string()),
+        };
+        let records = vec![frame(1672515782136422, None), snapshot];
+        for record in &records {
+            writer.write(1672515782136422, record).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let files = recording_files(&dir, "frames").unwrap();
+        assert_eq!(files.len(), 2);
+        let read: Vec<RecordedFrame> = files
+            .iter()
+            .flat_map(|path| read_records(path).unwrap())
+            .collect();
+        assert_eq!(read, records);
+
+        // records written before sync snapshots were recorded
+        let path = dir.join("frames.jsonl");
+        std::fs::write(
+            &path,
+            "{\"receive_time\":1,\"exchange_ids\":[0],\"frame\":null}\n",
+        )
+        .unwrap();
+        let read: Vec<RecordedFrame> = read_records(&path).unwrap().collect();
+        assert_eq!(read[0].kind, RecordKind::Frame);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
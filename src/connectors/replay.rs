@@ -0,0 +1,365 @@
+use crate::common::model::OrderBookUpdate;
+use crate::connectors::recorder::{read_records, recording_files, RecordKind, RecordedFrame};
+use crate::connectors::websocket::{reset_update, FeedEvent, FeedProtocol};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::time::{sleep_until, Instant};
+use tracing::{error, info};
+
+/// Pace of a replay relative to the recorded receive times.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    RealTime,
+    /// Recorded time passes the given number of times faster.
+    Faster(f64),
+    /// Records are published as fast as the aggregation takes them.
+    Max,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    /// `realtime`, `max` or a speed-up factor like `10` or `10x`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "realtime" => Ok(ReplaySpeed::RealTime),
+            "max" => Ok(ReplaySpeed::Max),
+            factor => {
+                let factor: f64 = factor
+                    .trim_end_matches('x')
+                    .parse()
+                    .map_err(|_| format!("expected realtime, max or a factor, got {}", s))?;
+                if !(factor > 0.0 && factor.is_finite()) {
+                    return Err(format!("factor must be positive, got {}", s));
+                }
+                Ok(ReplaySpeed::Faster(factor))
+            }
+        }
+    }
+}
+
+/// Content of replayed files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReplayFormat {
+    /// [`RecordedFrame`]s of a [`crate::connectors::recorder::FrameRecorder`], parsed again by
+    /// the feeds of the exchanges.
+    Frames,
+    /// [`OrderBookUpdate`] json lines, published as they are.
+    Updates,
+}
+
+/// `frames-*.jsonl.gz` files of a recording directory or the given file.
+pub fn replay_files(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if path.is_dir() {
+        recording_files(path, "frames")
+    } else {
+        Ok(vec![path.to_path_buf()])
+    }
+}
+
+/// Sleeps until the replay clock reaches the receive time of the next record.
+struct Pacer {
+    speed: ReplaySpeed,
+    /// Receive time of the first record and its replay instant.
+    start: Option<(u64, Instant)>,
+}
+
+impl Pacer {
+    async fn wait(&mut self, receive_time: u64) {
+        let factor = match self.speed {
+            ReplaySpeed::RealTime => 1.0,
+            ReplaySpeed::Faster(val) => val,
+            ReplaySpeed::Max => return,
+        };
+        let (first_time, start) = *self
+            .start
+            .get_or_insert_with(|| (receive_time, Instant::now()));
+        let elapsed = receive_time.saturating_sub(first_time) as f64 / factor;
+        sleep_until(start + Duration::from_micros(elapsed as u64)).await;
+    }
+}
+
+/// Publishes recorded data in place of live connectors. Updates keep their recorded receive
+/// times, so the aggregated book shows the same times as the recorded session.
+pub struct Replay {
+    files: Vec<PathBuf>,
+    speed: ReplaySpeed,
+}
+
+impl Replay {
+    /// Files are replayed in the given order, see [`crate::connectors::recorder::recording_files`].
+    pub fn new(files: Vec<PathBuf>) -> Self {
+        Self {
+            files,
+            speed: ReplaySpeed::RealTime,
+        }
+    }
+
+    pub fn with_speed(mut self, speed: ReplaySpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Fails when the capture can't be replayed, see [`Replay::run_frames`].
+    pub async fn run(
+        self,
+        format: ReplayFormat,
+        feeds: Vec<(usize, Box<dyn FeedProtocol>)>,
+        pub_chan: Sender<OrderBookUpdate>,
+    ) -> Result<(), String> {
+        match format {
+            ReplayFormat::Frames => self.run_frames(feeds, pub_chan).await,
+            ReplayFormat::Updates => self.run_updates(pub_chan).await,
+        }
+    }
+
+    /// Parses recorded frames with `feeds` of the same exchange ids as the recording. Frames of
+    /// exchanges without a feed are skipped. Recorded sync snapshots are loaded by
+    /// [`FeedProtocol::sync`] of their feed; a feed that asks for a resync before any snapshot
+    /// of its exchange was replayed fails the replay, as its book can't be built from the
+    /// capture.
+    pub async fn run_frames(
+        self,
+        feeds: Vec<(usize, Box<dyn FeedProtocol>)>,
+        pub_chan: Sender<OrderBookUpdate>,
+    ) -> Result<(), String> {
+        let mut feeds: HashMap<usize, Box<dyn FeedProtocol>> = feeds.into_iter().collect();
+        let mut synced: HashSet<usize> = HashSet::new();
+        let mut pacer = Pacer {
+            speed: self.speed,
+            start: None,
+        };
+        for path in &self.files {
+            info!("replaying frames. path={:?}", path);
+            let frames = match read_records::<RecordedFrame>(path) {
+                Ok(val) => val,
+                Err(err) => {
+                    error!("failed to open replay file. path={:?} err={:?}", path, err);
+                    continue;
+                }
+            };
+            for frame in frames {
+                pacer.wait(frame.receive_time).await;
+                for exchange_id in &frame.exchange_ids {
+                    let protocol = match feeds.get_mut(exchange_id) {
+                        Some(val) => val,
+                        None => continue,
+                    };
+                    let update = match (frame.kind, &frame.frame) {
+                        (RecordKind::SyncSnapshot, Some(snapshot)) => {
+                            synced.insert(*exchange_id);
+                            if protocol.sync(snapshot) {
+                                None
+                            } else {
+                                // the live connection was dropped
+                                error!("failed to sync replayed book. exchange_id={}", exchange_id);
+                                protocol.reset();
+                                Some(reset_update(*exchange_id))
+                            }
+                        }
+                        (RecordKind::SyncSnapshot, None) => None,
+                        // new connection
+                        (RecordKind::Frame, None) => {
+                            protocol.reset();
+                            Some(reset_update(*exchange_id))
+                        }
+                        (RecordKind::Frame, Some(msg)) => match protocol.parse(msg) {
+                            FeedEvent::Update(val) => Some(val),
+                            FeedEvent::Skip | FeedEvent::Send(_) => None,
+                            FeedEvent::Resync if !synced.contains(exchange_id) => {
+                                return Err(format!(
+                                    "feed of exchange_id={} needs a sync snapshot, none is recorded in {:?}",
+                                    exchange_id, path
+                                ));
+                            }
+                            // the next recorded sync snapshot loads the book
+                            FeedEvent::Resubscribe(_) | FeedEvent::Resync => {
+                                Some(reset_update(*exchange_id))
+                            }
+                            FeedEvent::Reconnect => {
+                                protocol.reset();
+                                Some(reset_update(*exchange_id))
+                            }
+                        },
+                    };
+                    let mut update = match update {
+                        Some(val) => val,
+                        None => continue,
+                    };
+                    update.exchange_id = Some(*exchange_id);
+                    update.receive_time = frame.receive_time;
+                    if pub_chan.send(update).await.is_err() {
+                        return Err("can't send replayed update to chan".to_string());
+                    }
+                }
+            }
+        }
+        info!("replay is complete");
+        Ok(())
+    }
+
+    /// Publishes recorded updates with their exchange ids.
+    pub async fn run_updates(self, pub_chan: Sender<OrderBookUpdate>) -> Result<(), String> {
+        let mut pacer = Pacer {
+            speed: self.speed,
+            start: None,
+        };
+        for path in &self.files {
+            info!("replaying updates. path={:?}", path);
+            let updates = match read_records::<OrderBookUpdate>(path) {
+                Ok(val) => val,
+                Err(err) => {
+                    error!("failed to open replay file. path={:?} err={:?}", path, err);
+                    continue;
+                }
+            };
+            for update in updates {
+                pacer.wait(update.receive_time).await;
+                if pub_chan.send(update).await.is_err() {
+                    return Err("can't send replayed update to chan".to_string());
+                }
+            }
+        }
+        info!("replay is complete");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Replay, ReplayFormat, ReplaySpeed};
+    use crate::common::model::OrderBookUpdate;
+    use crate::connectors::binance::{BinanceOrderBookListener, BinanceStreamMode};
+    use crate::connectors::recorder::{RecordKind, RecordedFrame, RotatingWriter};
+    use crate::connectors::ExchangeConnector;
+    use rust_decimal_macros::dec;
+    use tokio::sync::mpsc::channel;
+
+    #[test]
+    fn parse_speed() {
+        assert_eq!("realtime".parse(), Ok(ReplaySpeed::RealTime));
+        assert_eq!("MAX".parse(), Ok(ReplaySpeed::Max));
+        assert_eq!("10x".parse(), Ok(ReplaySpeed::Faster(10.0)));
+        assert_eq!("0.5".parse(), Ok(ReplaySpeed::Faster(0.5)));
+        assert!("0".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_recorded_frames() {
+        let dir = std::env::temp_dir().join(format!("lob-replay-{}", std::process::id()));
+        let mut writer = RotatingWriter::new(&dir, "frames");
+        let frames = [
+            (1672515782136422, None),
+            (1672515782136423, Some("{\"result\":null,\"id\":1}")),
+            (
+                1672515782136424,
+                Some(r#"{"lastUpdateId":1,"bids":[["0.0024","10"]],"asks":[["0.0026","100"]]}"#),
+            ),
+        ];
+        for (receive_time, frame) in frames {
+            let frame = RecordedFrame {
+                receive_time,
+                // exchange 3 is not replayed
+                exchange_ids: vec![2, 3],
+                kind: RecordKind::Frame,
+                frame: frame.map(str::to_string),
+            };
+            writer.write(receive_time, &frame).unwrap();
+        }
+        writer.finish().unwrap();
+        let files = crate::connectors::recorder::recording_files(&dir, "frames").unwrap();
+
+        let (sender, mut receiver) = channel(16);
+        let feed = BinanceOrderBookListener::new("BTCUSDT", 2).feed();
+        Replay::new(files)
+            .with_speed(ReplaySpeed::Max)
+            .run(ReplayFormat::Frames, vec![(2, feed)], sender)
+            .await
+            .unwrap();
+
+        let reset = receiver.recv().await.unwrap();
+        assert_eq!(reset.exchange_id, Some(2));
+        assert!(reset.is_snapshot && reset.bid_changes.is_empty());
+        assert_eq!(reset.receive_time, 1672515782136422);
+        let update = receiver.recv().await.unwrap();
+        assert_eq!(update.exchange_id, Some(2));
+        assert_eq!(update.receive_time, 1672515782136424);
+        assert_eq!(update.bid_changes[0].price, dec!(0.0024));
+        assert_eq!(update.ask_changes[0].qty, dec!(100));
+        assert!(receiver.recv().await.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Replays `records` of exchange 0 with a binance diff feed.
+    async fn replay_diff_capture(
+        name: &str,
+        records: Vec<(RecordKind, Option<&str>)>,
+    ) -> (Result<(), String>, Vec<OrderBookUpdate>) {
+        let dir = std::env::temp_dir().join(format!("lob-{}-{}", name, std::process::id()));
+        let mut writer = RotatingWriter::new(&dir, "frames");
+        for (receive_time, (kind, frame)) in (1672515782136422..).zip(records) {
+            let frame = RecordedFrame {
+                receive_time,
+                exchange_ids: vec![0],
+                kind,
+                frame: frame.map(str::to_string),
+            };
+            writer.write(receive_time, &frame).unwrap();
+        }
+        writer.finish().unwrap();
+        let files = crate::connectors::recorder::recording_files(&dir, "frames").unwrap();
+
+        let (sender, mut receiver) = channel(16);
+        let feed = BinanceOrderBookListener::new("BTCUSDT", 0)
+            .with_mode(BinanceStreamMode::Diff)
+            .feed();
+        let result = Replay::new(files)
+            .with_speed(ReplaySpeed::Max)
+            .run(ReplayFormat::Frames, vec![(0, feed)], sender)
+            .await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut updates = vec![];
+        while let Some(update) = receiver.recv().await {
+            updates.push(update);
+        }
+        (result, updates)
+    }
+
+    #[tokio::test]
+    async fn replay_diff_capture_sync_snapshots() {
+        let snapshot = include_str!("../../fixtures/binance_depth_snapshot.json");
+        let diff = include_str!("../../fixtures/binance_diff_depth.jsonl")
+            .lines()
+            .nth(1)
+            .unwrap();
+
+        let (result, updates) = replay_diff_capture(
+            "replay-synced",
+            vec![
+                (RecordKind::Frame, None),
+                (RecordKind::SyncSnapshot, Some(snapshot)),
+                (RecordKind::Frame, Some(diff)),
+            ],
+        )
+        .await;
+        assert_eq!(result, Ok(()));
+        assert_eq!(updates.len(), 2);
+        assert!(updates[0].is_snapshot && updates[0].bid_changes.is_empty());
+        assert_eq!(updates[1].receive_time, 1672515782136424);
+        assert_eq!(updates[1].bid_changes[0].price, dec!(30000));
+        assert_eq!(updates[1].bid_changes[0].qty, dec!(2.5));
+
+        // books of a capture without sync snapshots can't be built
+        let (result, updates) = replay_diff_capture(
+            "replay-unsynced",
+            vec![(RecordKind::Frame, None), (RecordKind::Frame, Some(diff))],
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(updates.len(), 1);
+    }
+}
@@ -0,0 +1,428 @@
+use crate::common::clock::{Clock, SystemClock};
+use crate::common::model::OrderBookUpdate;
+use crate::connectors::recorder::{FrameRecorder, RecordKind, RecordedFrame};
+use async_trait::async_trait;
+use flate2::read::GzDecoder;
+use futures::{Sink, SinkExt, StreamExt};
+use std::io::Read;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::time::{interval_at, sleep, Instant};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::protocol::{frame::coding::CloseCode, CloseFrame},
+    tungstenite::Message,
+};
+use tracing::{error, info};
+
+pub enum FeedEvent {
+    Update(OrderBookUpdate),
+    /// Message carries no book data (subscription acks, heartbeats, ...).
+    Skip,
+    /// Reply on the same connection, collector quotes are kept.
+    Send(Vec<Message>),
+    /// Local book was dropped: reset collector quotes and resubscribe with given messages.
+    Resubscribe(Vec<Message>),
+    /// Local book was dropped: reset collector quotes and sync the feed again, see
+    /// [`FeedProtocol::fetch_sync_snapshot`].
+    Resync,
+    /// Local state can't be trusted anymore, drop the connection and start over.
+    Reconnect,
+}
+
+/// Venue specific part of a websocket order book feed.
+///
+/// Connection handling, reconnects and collector resets are done by [`run_feeds`],
+/// so a protocol only knows where to connect, what to subscribe to and how to parse messages.
+#[async_trait]
+pub trait FeedProtocol: Send {
+    fn url(&self) -> String;
+
+    fn subscription_messages(&self) -> Vec<Message>;
+
+    /// Called before every (re)connection.
+    fn reset(&mut self) {}
+
+    /// Fetches initial book state out of band (e.g. REST snapshot) loaded by
+    /// [`FeedProtocol::sync`]. Called once subscription messages are sent, stream messages are
+    /// buffered by the socket meanwhile. `None` when the feed needs no out of band state,
+    /// an error drops the connection.
+    async fn fetch_sync_snapshot(&mut self) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    /// Loads a fetched or recorded snapshot. Returning false drops the connection.
+    fn sync(&mut self, _snapshot: &str) -> bool {
+        true
+    }
+
+    /// Application level ping some venues expect from the client, sent every given interval.
+    fn heartbeat(&self) -> Option<(Duration, Message)> {
+        None
+    }
+
+    fn parse(&mut self, raw_msg: &str) -> FeedEvent;
+}
+
+pub(crate) fn reset_update(exchange_id: usize) -> OrderBookUpdate {
+    // Empty bid and asks reset collector quotes
+    OrderBookUpdate {
+        exchange_id: Some(exchange_id),
+        bid_changes: vec![],
+        ask_changes: vec![],
+        is_snapshot: true,
+        exchange_time: None,
+        receive_time: SystemClock.now_micros(),
+    }
+}
+
+async fn send_messages<S>(stream: &mut S, name: &str, messages: Vec<Message>) -> bool
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::fmt::Debug,
+{
+    for message in messages {
+        info!("{} send message={:?}", name, &message);
+        if let Err(err) = stream.send(message).await {
+            error!("failed to send {} message. err={:?}", name, err);
+            return false;
+        };
+    }
+    true
+}
+
+/// Fetches and loads out of band state of the feed, the fetched snapshot is recorded with
+/// a `recorder`.
+async fn sync_feed(
+    protocol: &mut dyn FeedProtocol,
+    exchange_id: usize,
+    name: &str,
+    recorder: Option<&FrameRecorder>,
+) -> bool {
+    let snapshot = match protocol.fetch_sync_snapshot().await {
+        Ok(Some(val)) => val,
+        Ok(None) => return true,
+        Err(err) => {
+            error!("failed to fetch {} book snapshot. err={}", name, err);
+            return false;
+        }
+    };
+    if let Some(recorder) = recorder {
+        recorder.record(RecordedFrame {
+            receive_time: SystemClock.now_micros(),
+            exchange_ids: vec![exchange_id],
+            kind: RecordKind::SyncSnapshot,
+            frame: Some(snapshot.clone()),
+        });
+    }
+    if !protocol.sync(&snapshot) {
+        error!("failed to sync {} book", name);
+        return false;
+    }
+    true
+}
+
+async fn publish(pub_chan: &Sender<OrderBookUpdate>, order_book_update: OrderBookUpdate) -> bool {
+    if let Err(err) = pub_chan.send(order_book_update).await {
+        error!("can't send update to chan. err={:?}", err);
+        return false;
+    }
+    true
+}
+
+/// Runs feeds on one connection to the url of the first feed. Every message is parsed by every
+/// feed, so a feed must skip messages of other subscriptions. Updates are published with
+/// the exchange id of the feed that produced them. With a `recorder` every received frame,
+/// every new connection and every sync snapshot is recorded.
+pub async fn run_feeds(
+    mut feeds: Vec<(usize, Box<dyn FeedProtocol>)>,
+    name: &str,
+    pub_chan: Sender<OrderBookUpdate>,
+    recorder: Option<FrameRecorder>,
+) {
+    if feeds.is_empty() {
+        return;
+    }
+    let exchange_ids: Vec<usize> = feeds.iter().map(|(exchange_id, _)| *exchange_id).collect();
+    let record = |receive_time: u64, frame: Option<&str>| {
+        if let Some(recorder) = &recorder {
+            recorder.record(RecordedFrame {
+                receive_time,
+                exchange_ids: exchange_ids.clone(),
+                kind: RecordKind::Frame,
+                frame: frame.map(str::to_string),
+            });
+        }
+    };
+
+    'reconnection_loop: loop {
+        for (exchange_id, protocol) in feeds.iter_mut() {
+            if !publish(&pub_chan, reset_update(*exchange_id)).await {
+                return;
+            }
+            protocol.reset();
+        }
+        record(SystemClock.now_micros(), None);
+
+        sleep(Duration::from_secs(1)).await; // prevents ws spamming
+        info!("subscribing to {} websocket data", name);
+
+        let (mut stream, _) = match connect_async(feeds[0].1.url()).await {
+            Ok(val) => val,
+            Err(err) => {
+                error!("failed to connect to {}. err={:?}", name, err);
+                sleep(Duration::from_secs(5)).await;
+                continue 'reconnection_loop;
+            }
+        };
+
+        for (_, protocol) in feeds.iter_mut() {
+            if !send_messages(&mut stream, name, protocol.subscription_messages()).await {
+                continue 'reconnection_loop;
+            }
+        }
+        for (exchange_id, protocol) in feeds.iter_mut() {
+            if !sync_feed(protocol.as_mut(), *exchange_id, name, recorder.as_ref()).await {
+                continue 'reconnection_loop;
+            }
+        }
+
+        let heartbeat = feeds[0].1.heartbeat();
+        let heartbeat_period = heartbeat
+            .as_ref()
+            .map(|(period, _)| *period)
+            .unwrap_or(Duration::from_secs(3600));
+        let mut heartbeat_interval =
+            interval_at(Instant::now() + heartbeat_period, heartbeat_period);
+
+        loop {
+            let rcv = tokio::select! {
+                rcv = stream.next() => rcv,
+                _ = heartbeat_interval.tick(), if heartbeat.is_some() => {
+                    let (_, ping) = heartbeat.as_ref().unwrap();
+                    if !send_messages(&mut stream, name, vec![ping.clone()]).await {
+                        continue 'reconnection_loop;
+                    }
+                    continue;
+                }
+            };
+            let raw_msg = match rcv {
+                Some(Ok(val)) => val,
+                Some(Err(err)) => {
+                    error!("error in {} websocket recv {:?}", name, err);
+                    if let Err(err) = stream
+                        .close(Some(CloseFrame {
+                            code: CloseCode::Normal,
+                            reason: "Client requested connection close.".into(),
+                        }))
+                        .await
+                    {
+                        error!("can't close websocket err={:?}", err);
+                    };
+                    continue 'reconnection_loop;
+                }
+                None => {
+                    error!("{} websocket stream is closed", name);
+                    continue 'reconnection_loop;
+                }
+            };
+
+            let receive_time = SystemClock.now_micros();
+            let msg = match raw_msg {
+                Message::Text(msg) => msg,
+                Message::Binary(raw_msg) => {
+                    let mut d = GzDecoder::new(&*raw_msg);
+                    let mut s = String::new();
+                    if let Err(err) = d.read_to_string(&mut s) {
+                        error!("failed to decompress {} message. err={:?}", name, err);
+                        continue;
+                    }
+                    s
+                }
+                Message::Ping(payload) => {
+                    if let Err(err) = stream.send(Message::Pong(payload)).await {
+                        error!("failed to send pong. err={:?}", err)
+                    };
+                    continue;
+                }
+                Message::Close(frame) => {
+                    error!("{} closed websocket. frame={:?}", name, frame);
+                    continue 'reconnection_loop;
+                }
+                other => {
+                    error!("received unexpected message={:?}", other);
+                    continue;
+                }
+            };
+            record(receive_time, Some(&msg));
+
+            for (exchange_id, protocol) in feeds.iter_mut() {
+                match protocol.parse(&msg) {
+                    FeedEvent::Update(mut order_book_update) => {
+                        order_book_update.exchange_id = Some(*exchange_id);
+                        order_book_update.receive_time = receive_time;
+                        if !publish(&pub_chan, order_book_update).await {
+                            return;
+                        }
+                    }
+                    FeedEvent::Skip => {}
+                    FeedEvent::Send(messages) => {
+                        if !send_messages(&mut stream, name, messages).await {
+                            continue 'reconnection_loop;
+                        }
+                    }
+                    FeedEvent::Resubscribe(messages) => {
+                        info!("{} requested resubscribe", name);
+                        if !publish(&pub_chan, reset_update(*exchange_id)).await {
+                            return;
+                        }
+                        if !send_messages(&mut stream, name, messages).await {
+                            continue 'reconnection_loop;
+                        }
+                    }
+                    FeedEvent::Resync => {
+                        info!("{} requested resync", name);
+                        if !publish(&pub_chan, reset_update(*exchange_id)).await {
+                            return;
+                        }
+                        if !sync_feed(protocol.as_mut(), *exchange_id, name, recorder.as_ref())
+                            .await
+                        {
+                            continue 'reconnection_loop;
+                        }
+                    }
+                    FeedEvent::Reconnect => {
+                        info!("{} requested reconnect", name);
+                        if let Err(err) = stream.close(None).await {
+                            error!("can't close websocket err={:?}", err);
+                        };
+                        continue 'reconnection_loop;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_feeds, FeedEvent, FeedProtocol};
+    use crate::common::model::{ExchangeQuote, OrderBookUpdate};
+    use futures::{SinkExt, StreamExt};
+    use rust_decimal::Decimal;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::oneshot;
+    use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+    /// Parses `skip`, `resubscribe`, `reconnect` or a bid price.
+    struct StubFeed {
+        url: String,
+        resets: Arc<AtomicUsize>,
+    }
+
+    impl FeedProtocol for StubFeed {
+        fn url(&self) -> String {
+            self.url.clone()
+        }
+
+        fn subscription_messages(&self) -> Vec<Message> {
+            vec![Message::Text("subscribe".to_string())]
+        }
+
+        fn reset(&mut self) {
+            self.resets.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn parse(&mut self, raw_msg: &str) -> FeedEvent {
+            match raw_msg {
+                "skip" => FeedEvent::Skip,
+                "resubscribe" => {
+                    FeedEvent::Resubscribe(vec![Message::Text("resubscribed".to_string())])
+                }
+                "reconnect" => FeedEvent::Reconnect,
+                price => FeedEvent::Update(OrderBookUpdate {
+                    bid_changes: vec![ExchangeQuote {
+                        price: price.parse().unwrap(),
+                        qty: Decimal::ONE,
+                    }],
+                    ..Default::default()
+                }),
+            }
+        }
+    }
+
+    fn is_reset(update: &OrderBookUpdate) -> bool {
+        update.is_snapshot && update.bid_changes.is_empty() && update.ask_changes.is_empty()
+    }
+
+    #[tokio::test]
+    async fn feed_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (done, stop) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            let mut received = vec![];
+            let mut sockets = vec![];
+            for frames in [vec!["skip", "1", "resubscribe", "reconnect"], vec!["2"]] {
+                let (socket, _) = listener.accept().await.unwrap();
+                let mut socket = accept_async(socket).await.unwrap();
+                received.push(socket.next().await.unwrap().unwrap());
+                for frame in frames {
+                    socket.send(Message::Text(frame.to_string())).await.unwrap();
+                    if frame == "resubscribe" {
+                        received.push(socket.next().await.unwrap().unwrap());
+                    }
+                }
+                sockets.push(socket);
+            }
+            // the last connection stays open until the feed is stopped
+            let _ = stop.await;
+            received
+        });
+
+        let resets = Arc::new(AtomicUsize::new(0));
+        let feed = StubFeed {
+            url,
+            resets: resets.clone(),
+        };
+        let (sender, mut receiver) = channel(16);
+        let feeds = tokio::spawn(async move {
+            run_feeds(
+                vec![(3, Box::new(feed) as Box<dyn FeedProtocol>)],
+                "stub",
+                sender,
+                None,
+            )
+            .await
+        });
+
+        let mut updates = vec![];
+        for _ in 0..5 {
+            updates.push(receiver.recv().await.unwrap());
+        }
+        feeds.abort();
+        assert!(updates.iter().all(|update| update.exchange_id == Some(3)));
+        // connection, update, resubscribe, connection after reconnect, update
+        assert!(is_reset(&updates[0]));
+        assert_eq!(updates[1].bid_changes[0].price, Decimal::ONE);
+        assert!(is_reset(&updates[2]));
+        assert!(is_reset(&updates[3]));
+        assert_eq!(updates[4].bid_changes[0].price, Decimal::TWO);
+        assert_eq!(resets.load(Ordering::Relaxed), 2);
+
+        done.send(()).unwrap();
+        let received = server.await.unwrap();
+        let subscribe = Message::Text("subscribe".to_string());
+        assert_eq!(
+            received,
+            vec![
+                subscribe.clone(),
+                Message::Text("resubscribed".to_string()),
+                subscribe
+            ]
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use crate::common::model::{ExchangeQuote, OrderBookUpdate, Price, Qty};
+use std::collections::BTreeMap;
+
+/// Price level (L2) book of a single venue.
+#[derive(Debug, Default, Clone)]
+pub struct L2Book {
+    bids: BTreeMap<Price, Qty>,
+    asks: BTreeMap<Price, Qty>,
+}
+
+impl L2Book {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    /// Snapshot replaces the whole book, delta changes only given levels.
+    pub fn apply(&mut self, order_book_update: &OrderBookUpdate) {
+        if order_book_update.is_snapshot {
+            self.clear();
+        }
+        for quote in &order_book_update.bid_changes {
+            self.update_bid(quote.price, quote.qty);
+        }
+        for quote in &order_book_update.ask_changes {
+            self.update_ask(quote.price, quote.qty);
+        }
+    }
+
+    /// Sets bid level quantity, zero quantity removes the level.
+    pub fn update_bid(&mut self, price: Price, qty: Qty) {
+        Self::update_level(&mut self.bids, price, qty)
+    }
+
+    /// Sets ask level quantity, zero quantity removes the level.
+    pub fn update_ask(&mut self, price: Price, qty: Qty) {
+        Self::update_level(&mut self.asks, price, qty)
+    }
+
+    /// Drops levels that are out of given depth.
+    pub fn truncate(&mut self, depth: usize) {
+        if let Some(key) = self.asks.keys().nth(depth).copied() {
+            self.asks.split_off(&key);
+        }
+        if self.bids.len() > depth {
+            let key = *self.bids.keys().nth(self.bids.len() - depth).unwrap();
+            self.bids = self.bids.split_off(&key);
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<ExchangeQuote> {
+        self.bids().next()
+    }
+
+    pub fn best_ask(&self) -> Option<ExchangeQuote> {
+        self.asks().next()
+    }
+
+    /// Qty of the bid level at `price`, zero when there is no level.
+    pub fn bid_qty(&self, price: Price) -> Qty {
+        self.bids.get(&price).copied().unwrap_or_default()
+    }
+
+    /// Qty of the ask level at `price`, zero when there is no level.
+    pub fn ask_qty(&self, price: Price) -> Qty {
+        self.asks.get(&price).copied().unwrap_or_default()
+    }
+
+    pub fn mid_price(&self) -> Option<Price> {
+        let (bid, ask) = (self.best_bid()?, self.best_ask()?);
+        Some((bid.price + ask.price) / Price::TWO)
+    }
+
+    /// Bids from the best (highest) price.
+    pub fn bids(&self) -> impl Iterator<Item = ExchangeQuote> + '_ {
+        self.bids.iter().rev().map(Self::quote)
+    }
+
+    /// Asks from the best (lowest) price.
+    pub fn asks(&self) -> impl Iterator<Item = ExchangeQuote> + '_ {
+        self.asks.iter().map(Self::quote)
+    }
+
+    pub fn bids_len(&self) -> usize {
+        self.bids.len()
+    }
+
+    pub fn asks_len(&self) -> usize {
+        self.asks.len()
+    }
+
+    pub fn top_bids(&self, depth: usize) -> Vec<ExchangeQuote> {
+        self.bids().take(depth).collect()
+    }
+
+    pub fn top_asks(&self, depth: usize) -> Vec<ExchangeQuote> {
+        self.asks().take(depth).collect()
+    }
+
+    /// Snapshot update with top `depth` levels of each side.
+    pub fn snapshot(&self, depth: usize) -> OrderBookUpdate {
+        OrderBookUpdate {
+            exchange_id: None,
+            bid_changes: self.top_bids(depth),
+            ask_changes: self.top_asks(depth),
+            is_snapshot: true,
+            exchange_time: None,
+            receive_time: 0,
+        }
+    }
+
+    fn update_level(side: &mut BTreeMap<Price, Qty>, price: Price, qty: Qty) {
+        if qty.is_zero() {
+            side.remove(&price);
+        } else {
+            side.insert(price, qty);
+        }
+    }
+
+    fn quote((price, qty): (&Price, &Qty)) -> ExchangeQuote {
+        ExchangeQuote {
+            price: *price,
+            qty: *qty,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::L2Book;
+    use crate::common::model::{ExchangeQuote, OrderBookUpdate, Price, Qty};
+    use rust_decimal_macros::dec;
+
+    fn quotes(levels: &[(Price, Qty)]) -> Vec<ExchangeQuote> {
+        levels
+            .iter()
+            .map(|(price, qty)| ExchangeQuote {
+                price: *price,
+                qty: *qty,
+            })
+            .collect()
+    }
+
+    fn levels(quotes: Vec<ExchangeQuote>) -> Vec<(Price, Qty)> {
+        quotes.iter().map(|q| (q.price, q.qty)).collect()
+    }
+
+    fn update(bids: &[(Price, Qty)], asks: &[(Price, Qty)], is_snapshot: bool) -> OrderBookUpdate {
+        OrderBookUpdate {
+            exchange_id: Some(0),
+            bid_changes: quotes(bids),
+            ask_changes: quotes(asks),
+            is_snapshot,
+            exchange_time: None,
+            receive_time: 0,
+        }
+    }
+
+    #[test]
+    fn insert_update_delete() {
+        let mut book = L2Book::new();
+        book.update_bid(dec!(1.0), dec!(2));
+        book.update_bid(dec!(1.1), dec!(1));
+        book.update_bid(dec!(0.9), dec!(3));
+        book.update_ask(dec!(1.3), dec!(1));
+        book.update_ask(dec!(1.2), dec!(4));
+
+        assert_eq!(
+            levels(book.top_bids(10)),
+            vec![
+                (dec!(1.1), dec!(1)),
+                (dec!(1.0), dec!(2)),
+                (dec!(0.9), dec!(3))
+            ]
+        );
+        assert_eq!(
+            levels(book.top_asks(10)),
+            vec![(dec!(1.2), dec!(4)), (dec!(1.3), dec!(1))]
+        );
+
+        // same price with a different scale updates the level
+        book.update_bid(dec!(1.00), dec!(5));
+        book.update_bid(dec!(1.1), dec!(0));
+        book.update_ask(dec!(1.2), dec!(0.000));
+        book.update_ask(dec!(1.25), dec!(0));
+
+        assert_eq!(
+            levels(book.top_bids(10)),
+            vec![(dec!(1.0), dec!(5)), (dec!(0.9), dec!(3))]
+        );
+        assert_eq!(levels(book.top_asks(10)), vec![(dec!(1.3), dec!(1))]);
+        assert_eq!(book.best_bid().unwrap().price, dec!(1.0));
+        assert_eq!(book.best_ask().unwrap().price, dec!(1.3));
+    }
+
+    #[test]
+    fn top_and_truncate() {
+        let mut book = L2Book::new();
+        for i in 0..10 {
+            book.update_bid(dec!(100) - Price::from(i), dec!(1));
+            book.update_ask(dec!(101) + Price::from(i), dec!(1));
+        }
+        assert_eq!(book.top_bids(3).len(), 3);
+        assert_eq!(book.top_bids(3)[2].price, dec!(98));
+        assert_eq!(book.top_asks(3)[2].price, dec!(103));
+
+        book.truncate(4);
+        assert_eq!(book.bids_len(), 4);
+        assert_eq!(book.asks_len(), 4);
+        assert_eq!(book.bids().last().unwrap().price, dec!(97));
+        assert_eq!(book.asks().last().unwrap().price, dec!(104));
+    }
+
+    #[test]
+    fn snapshot_and_delta_updates() {
+        let mut book = L2Book::new();
+        book.apply(&update(
+            &[(dec!(1.0), dec!(1)), (dec!(0.9), dec!(1))],
+            &[(dec!(1.1), dec!(1))],
+            true,
+        ));
+        book.apply(&update(
+            &[(dec!(0.9), dec!(0)), (dec!(0.95), dec!(2))],
+            &[(dec!(1.05), dec!(3))],
+            false,
+        ));
+
+        assert_eq!(
+            levels(book.top_bids(10)),
+            vec![(dec!(1.0), dec!(1)), (dec!(0.95), dec!(2))]
+        );
+        assert_eq!(
+            levels(book.top_asks(10)),
+            vec![(dec!(1.05), dec!(3)), (dec!(1.1), dec!(1))]
+        );
+
+        book.apply(&update(&[(dec!(0.5), dec!(1))], &[], true));
+        assert_eq!(levels(book.top_bids(10)), vec![(dec!(0.5), dec!(1))]);
+        assert!(book.best_ask().is_none());
+
+        book.apply(&update(&[], &[], true));
+        assert!(book.is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::orderbook::level_change::Action;
+use crate::orderbook::{BookDelta, Level, LevelChange, Summary, VenueStatus};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// Level of an exchange is identified by its price, other fields are updated in place.
+fn level_key(level: &Level) -> (&str, u64) {
+    (level.exchange.as_str(), level.price.to_bits())
+}
+
+fn level_change(action: Action, level: &Level) -> LevelChange {
+    LevelChange {
+        action: action.into(),
+        level: Some(level.clone()),
+    }
+}
+
+/// Changes turning `old` side into `new` one. Deletes go first.
+pub fn level_changes(old: &[Level], new: &[Level]) -> Vec<LevelChange> {
+    let old_levels: HashMap<_, _> = old.iter().map(|level| (level_key(level), level)).collect();
+    let new_keys: HashSet<_> = new.iter().map(level_key).collect();
+
+    let mut changes: Vec<LevelChange> = old
+        .iter()
+        .filter(|level| !new_keys.contains(&level_key(level)))
+        .map(|level| level_change(Action::Delete, level))
+        .collect();
+    for level in new {
+        match old_levels.get(&level_key(level)) {
+            None => changes.push(level_change(Action::Insert, level)),
+            Some(old_level) if *old_level != level => {
+                changes.push(level_change(Action::Update, level))
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+/// Venues differ by last update times only.
+pub fn same_venue_states(a: &[VenueStatus], b: &[VenueStatus]) -> bool {
+    a.iter()
+        .map(|venue| (&venue.exchange, venue.state))
+        .eq(b.iter().map(|venue| (&venue.exchange, venue.state)))
+}
+
+/// Delta between two published books, `None` when nothing changed.
+pub fn book_delta(sequence: u64, old: &Summary, new: &Summary) -> Option<BookDelta> {
+    let bids = level_changes(&old.bids, &new.bids);
+    let asks = level_changes(&old.asks, &new.asks);
+    let venues = if same_venue_states(&old.venues, &new.venues) {
+        vec![]
+    } else {
+        new.venues.clone()
+    };
+    if bids.is_empty() && asks.is_empty() && venues.is_empty() && old.spread == new.spread {
+        return None;
+    }
+    Some(BookDelta {
+        sequence,
+        snapshot: None,
+        spread: new.spread,
+        bids,
+        asks,
+        venues,
+    })
+}
+
+/// Same order as the aggregated book, ties go by exchange.
+fn cmp_levels(a: &Level, b: &Level) -> Ordering {
+    a.effective_price
+        .total_cmp(&b.effective_price)
+        .then_with(|| a.price.total_cmp(&b.price))
+        .then_with(|| a.amount.total_cmp(&b.amount))
+        .then_with(|| a.exchange.cmp(&b.exchange))
+}
+
+fn apply_changes(levels: &mut Vec<Level>, changes: &[LevelChange]) {
+    for change in changes {
+        let level = match &change.level {
+            Some(val) => val,
+            None => continue,
+        };
+        let position = levels
+            .iter()
+            .position(|current| level_key(current) == level_key(level));
+        match (change.action(), position) {
+            (Action::Delete, Some(index)) => {
+                levels.remove(index);
+            }
+            (Action::Update, Some(index)) => levels[index] = level.clone(),
+            (Action::Insert, None) => levels.push(level.clone()),
+            _ => {}
+        }
+    }
+}
+
+/// Applies a delta on top of the book a client keeps, the best levels go first.
+pub fn apply_delta(book: &mut Summary, delta: &BookDelta) {
+    if let Some(snapshot) = &delta.snapshot {
+        *book = snapshot.clone();
+        return;
+    }
+    apply_changes(&mut book.bids, &delta.bids);
+    apply_changes(&mut book.asks, &delta.asks);
+    book.bids.sort_by(|a, b| cmp_levels(b, a));
+    book.asks.sort_by(cmp_levels);
+    book.spread = delta.spread;
+    if !delta.venues.is_empty() {
+        book.venues = delta.venues.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, book_delta};
+    use crate::orderbook::level_change::Action;
+    use crate::orderbook::venue_status::State;
+    use crate::orderbook::{Level, Summary, VenueStatus};
+
+    fn level(exchange: &str, price: f64, amount: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price,
+            amount,
+            effective_price: price,
+            currency: "USD".to_string(),
+        }
+    }
+
+    #[test]
+    fn delta_restores_book() {
+        let old = Summary {
+            spread: 1.0,
+            bids: vec![level("binance", 100.0, 1.0), level("bitstamp", 99.0, 1.0)],
+            asks: vec![level("binance", 101.0, 1.0), level("bitstamp", 102.0, 1.0)],
+            ..Default::default()
+        };
+        let new = Summary {
+            spread: 0.5,
+            bids: vec![
+                level("bitstamp", 100.5, 2.0),
+                level("binance", 100.0, 3.0),
+                level("bitstamp", 99.0, 1.0),
+            ],
+            asks: vec![level("bitstamp", 102.0, 1.0)],
+            ..Default::default()
+        };
+
+        assert!(book_delta(1, &old, &old).is_none());
+
+        let delta = book_delta(2, &old, &new).unwrap();
+        assert_eq!(delta.sequence, 2);
+        let bid_actions: Vec<_> = delta.bids.iter().map(|change| change.action()).collect();
+        assert_eq!(bid_actions, vec![Action::Insert, Action::Update]);
+        let ask_actions: Vec<_> = delta.asks.iter().map(|change| change.action()).collect();
+        assert_eq!(ask_actions, vec![Action::Delete]);
+
+        let mut book = old.clone();
+        apply_delta(&mut book, &delta);
+        assert_eq!(book, new);
+    }
+
+    #[test]
+    fn delta_of_tied_levels() {
+        let old = Summary {
+            bids: vec![level("binance", 100.0, 1.0)],
+            asks: vec![level("bitstamp", 101.0, 1.0)],
+            ..Default::default()
+        };
+        // inserted levels tie with existing ones on everything but exchange
+        let new = Summary {
+            bids: vec![level("bitstamp", 100.0, 1.0), level("binance", 100.0, 1.0)],
+            asks: vec![level("binance", 101.0, 1.0), level("bitstamp", 101.0, 1.0)],
+            ..Default::default()
+        };
+
+        let delta = book_delta(1, &old, &new).unwrap();
+        let mut book = old.clone();
+        apply_delta(&mut book, &delta);
+        assert_eq!(book, new);
+    }
+
+    #[test]
+    fn delta_of_venue_states() {
+        let venue = |state: State, last_update_time| VenueStatus {
+            exchange: "binance".to_string(),
+            state: state.into(),
+            last_update_time,
+        };
+        let old = Summary {
+            spread: 1.0,
+            bids: vec![level("binance", 100.0, 1.0)],
+            asks: vec![level("binance", 101.0, 1.0)],
+            venues: vec![venue(State::Live, 1)],
+            ..Default::default()
+        };
+        let updated = Summary {
+            venues: vec![venue(State::Live, 2)],
+            ..old.clone()
+        };
+        assert!(book_delta(1, &old, &updated).is_none());
+
+        // the only venue stalls, its levels are dropped
+        let stale = Summary {
+            spread: 0.0,
+            bids: vec![],
+            asks: vec![],
+            venues: vec![venue(State::Stale, 2)],
+            ..Default::default()
+        };
+        let delta = book_delta(2, &old, &stale).unwrap();
+        assert_eq!(delta.venues, stale.venues);
+        let mut book = old.clone();
+        apply_delta(&mut book, &delta);
+        assert_eq!(book, stale);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::aggregation::execution::{Amount, Execution};
+use crate::common::model::Side;
+use crate::orderbook::execution_request::{self, Side as RequestSide};
+use crate::orderbook::{ExchangeFill, ExecutionEstimate, ExecutionRequest};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use tokio::sync::oneshot;
+
+/// Execution estimate request of the gRPC server answered by the aggregation of the symbol,
+/// which owns the aggregated top of book.
+#[derive(Debug)]
+pub struct ExecutionQuery {
+    pub request: ExecutionRequest,
+    pub amount: Amount,
+    pub response: oneshot::Sender<ExecutionEstimate>,
+}
+
+impl ExecutionRequest {
+    /// `None` when the amount is missing or not positive.
+    pub fn execution_amount(&self) -> Option<Amount> {
+        let amount = match self.amount.as_ref()? {
+            execution_request::Amount::Qty(val) => Amount::Qty(Decimal::from_f64(*val)?),
+            execution_request::Amount::Notional(val) => Amount::Notional(Decimal::from_f64(*val)?),
+        };
+        match amount {
+            Amount::Qty(val) | Amount::Notional(val) if val > Decimal::ZERO => Some(amount),
+            _ => None,
+        }
+    }
+
+    pub fn execution_side(&self) -> Side {
+        match self.side() {
+            RequestSide::Buy => Side::Buy,
+            RequestSide::Sell => Side::Sell,
+        }
+    }
+
+    /// Ids of the requested exchanges, `None` when every exchange is requested.
+    /// Unknown exchanges are ignored.
+    pub fn exchange_ids(
+        &self,
+        exchanges_id_mapping: &HashMap<usize, String>,
+    ) -> Option<Vec<usize>> {
+        if self.exchanges.is_empty() {
+            return None;
+        }
+        let exchange_ids = exchanges_id_mapping
+            .iter()
+            .filter(|(_, exchange)| self.exchanges.contains(exchange))
+            .map(|(exchange_id, _)| *exchange_id)
+            .collect();
+        Some(exchange_ids)
+    }
+}
+
+pub fn execution_estimate(
+    execution: &Execution,
+    exchanges_id_mapping: &HashMap<usize, String>,
+) -> ExecutionEstimate {
+    let to_f64 = |val: Option<Decimal>| val.and_then(|val| val.to_f64()).unwrap_or_default();
+    ExecutionEstimate {
+        qty: to_f64(Some(execution.qty)),
+        notional: to_f64(Some(execution.notional)),
+        vwap: to_f64(execution.vwap),
+        worst_price: to_f64(execution.worst_price),
+        mid_price: to_f64(execution.mid_price),
+        slippage_bps: to_f64(execution.slippage_bps),
+        fills: execution
+            .fills
+            .iter()
+            .map(|fill| ExchangeFill {
+                exchange: exchanges_id_mapping
+                    .get(&fill.exchange)
+                    .cloned()
+                    .unwrap_or_default(),
+                qty: to_f64(Some(fill.qty)),
+                notional: to_f64(Some(fill.notional)),
+                vwap: to_f64(Some(fill.vwap())),
+            })
+            .collect(),
+        depth_exhausted: execution.depth_exhausted,
+    }
+}
@@ -1,48 +1,950 @@
+pub mod book;
+pub mod delta;
+pub mod execution;
+pub mod paper;
+pub mod routing;
+
 tonic::include_proto!("orderbook");
 
+use crate::orderbook::execution::ExecutionQuery;
 use crate::orderbook::orderbook_aggregator_server::OrderbookAggregator;
-use tokio::sync::mpsc::channel;
+use crate::orderbook::paper::{PaperChannels, PaperQuery};
+use crate::orderbook::routing::RouteQuery;
+use std::collections::HashMap;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::oneshot;
+use tokio::sync::watch::Receiver;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic;
 use tonic::{Request, Response, Status};
 use tracing::{error, info};
 
+/// Arbitrage events and report of one symbol.
+#[derive(Debug, Clone)]
+pub struct ArbitrageChannels {
+    pub events: broadcast::Sender<ArbitrageOpportunity>,
+    pub report: Receiver<ArbitrageReport>,
+}
+
 #[derive(Debug)]
 pub struct OrderbookAggregatorPublisher {
-    receiver: tokio::sync::watch::Receiver<Summary>,
+    /// Summary receiver of every aggregated symbol.
+    receivers: HashMap<String, Receiver<Summary>>,
+    arbitrage: HashMap<String, ArbitrageChannels>,
+    /// Route queries are answered by the aggregation of the symbol.
+    routes: HashMap<String, Sender<RouteQuery>>,
+    /// Execution estimates are answered by the aggregation of the symbol.
+    executions: HashMap<String, Sender<ExecutionQuery>>,
+    paper: HashMap<String, PaperChannels>,
 }
 
 impl OrderbookAggregatorPublisher {
-    pub fn new(receiver: tokio::sync::watch::Receiver<Summary>) -> Self {
-        Self { receiver }
+    pub fn new(receivers: HashMap<String, Receiver<Summary>>) -> Self {
+        Self {
+            receivers,
+            arbitrage: HashMap::new(),
+            routes: HashMap::new(),
+            executions: HashMap::new(),
+            paper: HashMap::new(),
+        }
+    }
+
+    pub fn with_arbitrage(mut self, arbitrage: HashMap<String, ArbitrageChannels>) -> Self {
+        self.arbitrage = arbitrage;
+        self
+    }
+
+    pub fn with_routes(mut self, routes: HashMap<String, Sender<RouteQuery>>) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    pub fn with_executions(mut self, executions: HashMap<String, Sender<ExecutionQuery>>) -> Self {
+        self.executions = executions;
+        self
+    }
+
+    pub fn with_paper(mut self, paper: HashMap<String, PaperChannels>) -> Self {
+        self.paper = paper;
+        self
+    }
+
+    fn receiver(&self, symbol: &str) -> Option<Receiver<Summary>> {
+        symbol_entry(&self.receivers, symbol).cloned()
+    }
+
+    fn paper_queries(&self, symbol: &str) -> Option<&Sender<PaperQuery>> {
+        symbol_entry(&self.paper, symbol).map(|channels| &channels.queries)
+    }
+}
+
+/// Empty symbol selects the only aggregated symbol.
+fn symbol_entry<'a, V>(entries: &'a HashMap<String, V>, symbol: &str) -> Option<&'a V> {
+    if symbol.is_empty() && entries.len() == 1 {
+        return entries.values().next();
+    }
+    entries.get(&symbol.to_uppercase())
+}
+
+/// Summaries differ by times only.
+fn same_levels(a: &Summary, b: &Summary) -> bool {
+    a.spread == b.spread
+        && a.bids == b.bids
+        && a.asks == b.asks
+        && a.consolidated_bids == b.consolidated_bids
+        && a.consolidated_asks == b.consolidated_asks
+        && delta::same_venue_states(&a.venues, &b.venues)
+}
+
+fn unknown_symbol(symbol: &str) -> Status {
+    Status::not_found(format!("symbol {} is not aggregated", symbol))
+}
+
+fn stopped_symbol(symbol: &str) -> Status {
+    Status::unavailable(format!("aggregation of symbol {} is stopped", symbol))
+}
+
+/// Sends the query to the aggregation of the symbol found by [`symbol_entry`] and waits for
+/// the response.
+async fn symbol_query<Q, R>(
+    queries: Option<&Sender<Q>>,
+    symbol: &str,
+    query: impl FnOnce(oneshot::Sender<R>) -> Q,
+) -> Result<R, Status> {
+    let queries = queries.ok_or_else(|| unknown_symbol(symbol))?;
+    let (response, result) = oneshot::channel();
+    queries
+        .send(query(response))
+        .await
+        .map_err(|_| stopped_symbol(symbol))?;
+    result.await.map_err(|_| stopped_symbol(symbol))
+}
+
+impl BookSummaryRequest {
+    fn includes(&self, level: &Level) -> bool {
+        (self.exchanges.is_empty() || self.exchanges.contains(&level.exchange))
+            && level.amount >= self.min_qty
+    }
+
+    fn side(&self, levels: &[Level]) -> Vec<Level> {
+        levels
+            .iter()
+            .filter(|level| self.includes(level))
+            .take(self.depth())
+            .cloned()
+            .collect()
+    }
+
+    fn consolidated_side(&self, levels: &[ConsolidatedLevel]) -> Vec<ConsolidatedLevel> {
+        levels
+            .iter()
+            .filter_map(|level| {
+                let venues: Vec<Level> = level
+                    .venues
+                    .iter()
+                    .filter(|venue| {
+                        self.exchanges.is_empty() || self.exchanges.contains(&venue.exchange)
+                    })
+                    .cloned()
+                    .collect();
+                let amount = venues.iter().map(|venue| venue.amount).sum();
+                if venues.is_empty() || amount < self.min_qty {
+                    return None;
+                }
+                Some(ConsolidatedLevel {
+                    price: level.price,
+                    amount,
+                    venues,
+                })
+            })
+            .take(self.depth())
+            .collect()
+    }
+
+    fn includes_exchanges(&self, buy_exchange: &String, sell_exchange: &String) -> bool {
+        self.exchanges.is_empty()
+            || (self.exchanges.contains(buy_exchange) && self.exchanges.contains(sell_exchange))
+    }
+
+    fn depth(&self) -> usize {
+        match self.depth {
+            0 => usize::MAX,
+            val => val as usize,
+        }
+    }
+
+    /// Part of the aggregated `summary` the subscriber asked for.
+    /// Depth is capped by the server book, which has at most the server depth levels.
+    pub fn filter(&self, summary: &Summary) -> Summary {
+        let bids = self.side(&summary.bids);
+        let asks = self.side(&summary.asks);
+        let spread = match (bids.first(), asks.first()) {
+            (Some(bid), Some(ask)) => ask.price - bid.price,
+            _ => 0.0,
+        };
+        Summary {
+            spread,
+            bids,
+            asks,
+            consolidated_bids: self.consolidated_side(&summary.consolidated_bids),
+            consolidated_asks: self.consolidated_side(&summary.consolidated_asks),
+            venues: summary
+                .venues
+                .iter()
+                .filter(|venue| {
+                    self.exchanges.is_empty() || self.exchanges.contains(&venue.exchange)
+                })
+                .cloned()
+                .collect(),
+            ..summary.clone()
+        }
     }
 }
 
 #[tonic::async_trait]
 impl OrderbookAggregator for OrderbookAggregatorPublisher {
     type BookSummaryStream = ReceiverStream<Result<Summary, Status>>;
+    type BookDeltasStream = ReceiverStream<Result<BookDelta, Status>>;
+    type ArbitrageOpportunitiesStream = ReceiverStream<Result<ArbitrageOpportunity, Status>>;
+    type PaperFillsStream = ReceiverStream<Result<PaperFill, Status>>;
 
     async fn book_summary(
         &self,
-        _request: Request<Empty>,
+        request: Request<BookSummaryRequest>,
     ) -> Result<Response<Self::BookSummaryStream>, Status> {
-        info!("new book summary subscriber");
+        let request = request.into_inner();
+        let mut summary_receiver = self
+            .receiver(&request.symbol)
+            .ok_or_else(|| unknown_symbol(&request.symbol))?;
+        info!("new book summary subscriber. request={:?}", &request);
         let (tx, rx) = channel(4);
-        let mut summary_receiver = self.receiver.clone();
 
         tokio::spawn(async move {
+            let mut last_summary = None;
             while summary_receiver.changed().await.is_ok() {
-                let summary = (*summary_receiver.borrow()).clone();
-                if summary.bids.is_empty() || summary.asks.is_empty() {
+                let summary = request.filter(&summary_receiver.borrow());
+                // an empty side after the first book is a venue state change
+                let ready = !summary.bids.is_empty() && !summary.asks.is_empty();
+                if !ready && last_summary.is_none() {
+                    continue;
+                }
+                // changes of filtered out levels
+                if matches!(&last_summary, Some(last) if same_levels(last, &summary)) {
                     continue;
                 }
+                last_summary = Some(summary.clone());
                 info!("publish new book summary: {:?}", &summary);
                 if let Err(err) = tx.send(Ok(summary)).await {
                     error!("failed to send book summary. err={:?}", err);
+                    return;
+                };
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn book_deltas(
+        &self,
+        request: Request<BookSummaryRequest>,
+    ) -> Result<Response<Self::BookDeltasStream>, Status> {
+        let request = request.into_inner();
+        let mut summary_receiver = self
+            .receiver(&request.symbol)
+            .ok_or_else(|| unknown_symbol(&request.symbol))?;
+        info!("new book delta subscriber. request={:?}", &request);
+        let (tx, rx) = channel(4);
+
+        tokio::spawn(async move {
+            let mut last_summary: Option<Summary> = None;
+            let mut sequence = 0;
+            loop {
+                let mut summary = request.filter(&summary_receiver.borrow_and_update());
+                // consolidated levels are not a part of deltas
+                summary.consolidated_bids.clear();
+                summary.consolidated_asks.clear();
+
+                let ready = !summary.bids.is_empty() && !summary.asks.is_empty();
+                let book_delta = match &last_summary {
+                    Some(last) => delta::book_delta(sequence + 1, last, &summary),
+                    None if ready => Some(BookDelta {
+                        sequence: sequence + 1,
+                        spread: summary.spread,
+                        snapshot: Some(summary.clone()),
+                        ..Default::default()
+                    }),
+                    None => None,
                 };
+                if let Some(book_delta) = book_delta {
+                    sequence = book_delta.sequence;
+                    last_summary = Some(summary);
+                    if let Err(err) = tx.send(Ok(book_delta)).await {
+                        error!("failed to send book delta. err={:?}", err);
+                        return;
+                    };
+                }
+                if summary_receiver.changed().await.is_err() {
+                    return;
+                }
             }
         });
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
+
+    async fn arbitrage_opportunities(
+        &self,
+        request: Request<BookSummaryRequest>,
+    ) -> Result<Response<Self::ArbitrageOpportunitiesStream>, Status> {
+        let request = request.into_inner();
+        let mut events = symbol_entry(&self.arbitrage, &request.symbol)
+            .ok_or_else(|| unknown_symbol(&request.symbol))?
+            .events
+            .subscribe();
+        info!("new arbitrage subscriber. request={:?}", &request);
+        let (tx, rx) = channel(16);
+
+        tokio::spawn(async move {
+            loop {
+                let opportunity = match events.recv().await {
+                    Ok(val) => val,
+                    Err(RecvError::Lagged(skipped)) => {
+                        error!("arbitrage subscriber lagged. skipped={}", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                if !request
+                    .includes_exchanges(&opportunity.buy_exchange, &opportunity.sell_exchange)
+                    || opportunity.qty < request.min_qty
+                {
+                    continue;
+                }
+                if let Err(err) = tx.send(Ok(opportunity)).await {
+                    error!("failed to send arbitrage opportunity. err={:?}", err);
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_arbitrage_report(
+        &self,
+        request: Request<BookSummaryRequest>,
+    ) -> Result<Response<ArbitrageReport>, Status> {
+        let request = request.into_inner();
+        let channels = symbol_entry(&self.arbitrage, &request.symbol)
+            .ok_or_else(|| unknown_symbol(&request.symbol))?;
+        let mut report = channels.report.borrow().clone();
+        report
+            .pairs
+            .retain(|pair| request.includes_exchanges(&pair.buy_exchange, &pair.sell_exchange));
+        Ok(Response::new(report))
+    }
+
+    async fn get_execution_estimate(
+        &self,
+        request: Request<ExecutionRequest>,
+    ) -> Result<Response<ExecutionEstimate>, Status> {
+        let request = request.into_inner();
+        let amount = request
+            .execution_amount()
+            .ok_or_else(|| Status::invalid_argument("qty or notional must be positive"))?;
+        let symbol = request.symbol.clone();
+        let queries = symbol_entry(&self.executions, &symbol);
+        let estimate = symbol_query(queries, &symbol, |response| ExecutionQuery {
+            request,
+            amount,
+            response,
+        })
+        .await?;
+        Ok(Response::new(estimate))
+    }
+
+    async fn route_order(
+        &self,
+        request: Request<RouteOrderRequest>,
+    ) -> Result<Response<OrderRoute>, Status> {
+        let request = request.into_inner();
+        if !request.is_valid() {
+            return Err(Status::invalid_argument(
+                "qty must be positive, limit price and venue rules must not be negative",
+            ));
+        }
+        let symbol = request.symbol.clone();
+        let queries = symbol_entry(&self.routes, &symbol);
+        let route = symbol_query(queries, &symbol, |response| RouteQuery {
+            request,
+            response,
+        })
+        .await?;
+        Ok(Response::new(route))
+    }
+
+    async fn submit_paper_order(
+        &self,
+        request: Request<PaperOrderRequest>,
+    ) -> Result<Response<PaperOrderAck>, Status> {
+        let request = request.into_inner();
+        if !request.is_valid() {
+            return Err(Status::invalid_argument(
+                "qty must be positive and limit price must not be negative",
+            ));
+        }
+        let symbol = request.symbol.clone();
+        let ack = symbol_query(self.paper_queries(&symbol), &symbol, |response| {
+            PaperQuery::Submit { request, response }
+        })
+        .await?
+        .map_err(Status::invalid_argument)?;
+        Ok(Response::new(ack))
+    }
+
+    async fn cancel_paper_order(
+        &self,
+        request: Request<CancelPaperOrderRequest>,
+    ) -> Result<Response<PaperOrder>, Status> {
+        let request = request.into_inner();
+        let order_id = request.order_id;
+        let order = symbol_query(
+            self.paper_queries(&request.symbol),
+            &request.symbol,
+            |response| PaperQuery::Cancel { order_id, response },
+        )
+        .await?
+        .ok_or_else(|| Status::not_found(format!("order {} is not resting", order_id)))?;
+        Ok(Response::new(order))
+    }
+
+    async fn get_paper_account(
+        &self,
+        request: Request<PaperAccountRequest>,
+    ) -> Result<Response<PaperAccount>, Status> {
+        let request = request.into_inner();
+        let account = symbol_query(
+            self.paper_queries(&request.symbol),
+            &request.symbol,
+            |response| PaperQuery::Account { response },
+        )
+        .await?;
+        Ok(Response::new(account))
+    }
+
+    async fn paper_fills(
+        &self,
+        request: Request<PaperAccountRequest>,
+    ) -> Result<Response<Self::PaperFillsStream>, Status> {
+        let request = request.into_inner();
+        let mut fills = symbol_entry(&self.paper, &request.symbol)
+            .ok_or_else(|| unknown_symbol(&request.symbol))?
+            .fills
+            .subscribe();
+        info!("new paper fills subscriber. request={:?}", &request);
+        let (tx, rx) = channel(16);
+
+        tokio::spawn(async move {
+            loop {
+                let fill = match fills.recv().await {
+                    Ok(val) => val,
+                    Err(RecvError::Lagged(skipped)) => {
+                        error!("paper fills subscriber lagged. skipped={}", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                if let Err(err) = tx.send(Ok(fill)).await {
+                    error!("failed to send paper fill. err={:?}", err);
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_book_snapshot(
+        &self,
+        request: Request<BookSummaryRequest>,
+    ) -> Result<Response<Summary>, Status> {
+        let request = request.into_inner();
+        let summary_receiver = self
+            .receiver(&request.symbol)
+            .ok_or_else(|| unknown_symbol(&request.symbol))?;
+        let summary = request.filter(&summary_receiver.borrow());
+        if summary.bids.is_empty() || summary.asks.is_empty() {
+            return Err(Status::unavailable(format!(
+                "book of symbol {} is not ready",
+                &request.symbol
+            )));
+        }
+        Ok(Response::new(summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::book::L2Book;
+    use super::execution::{self, ExecutionQuery};
+    use super::execution_request::{Amount, Side};
+    use super::orderbook_aggregator_server::OrderbookAggregator;
+    use super::paper::{PaperChannels, PaperQuery, PaperTrading};
+    use super::routing::{self, RouteQuery};
+    use super::venue_status::State;
+    use super::{
+        BookSummaryRequest, CancelPaperOrderRequest, ConsolidatedLevel, ExecutionRequest, Level,
+        OrderbookAggregatorPublisher, PaperAccountRequest, PaperOrderRequest, RouteOrderRequest,
+        Summary, VenueRules, VenueStatus,
+    };
+    use crate::aggregation::aggregator::OrderBookAggregator;
+    use crate::aggregation::paper::PaperEngine;
+    use crate::aggregation::quote_merge::IterativeMergeQuotes;
+    use crate::common::model::{ExchangeQuote, OrderBookUpdate};
+    use futures_util::StreamExt;
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+    use tonic::{Code, Request};
+
+    fn level(exchange: &str, price: f64, amount: f64) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price,
+            amount,
+            effective_price: price,
+            currency: "USD".to_string(),
+        }
+    }
+
+    #[test]
+    fn receiver_by_symbol() {
+        let (_btc_sender, btc_receiver) = tokio::sync::watch::channel(Summary::default());
+        let publisher = OrderbookAggregatorPublisher::new(HashMap::from([(
+            "BTC/USDT".to_string(),
+            btc_receiver,
+        )]));
+        assert!(publisher.receiver("btc/usdt").is_some());
+        assert!(publisher.receiver("").is_some());
+        assert!(publisher.receiver("ETH/USDT").is_none());
+
+        let (_eth_sender, eth_receiver) = tokio::sync::watch::channel(Summary::default());
+        let mut receivers = publisher.receivers;
+        receivers.insert("ETH/USDT".to_string(), eth_receiver);
+        let publisher = OrderbookAggregatorPublisher::new(receivers);
+        assert!(publisher.receiver("ETH/USDT").is_some());
+        assert!(publisher.receiver("").is_none());
+    }
+
+    #[test]
+    fn filter_summary() {
+        let summary = Summary {
+            spread: 1.0,
+            bids: vec![
+                level("binance", 100.0, 0.1),
+                level("bitstamp", 99.0, 2.0),
+                level("binance", 98.0, 3.0),
+                level("bitstamp", 97.0, 4.0),
+            ],
+            asks: vec![level("binance", 101.0, 1.0), level("bitstamp", 102.0, 1.0)],
+            consolidated_bids: vec![ConsolidatedLevel {
+                price: 100.0,
+                amount: 3.0,
+                venues: vec![level("binance", 100.0, 1.0), level("bitstamp", 100.0, 2.0)],
+            }],
+            consolidated_asks: vec![],
+            receive_time: 1672515782136422,
+            sequence: 7,
+            ..Default::default()
+        };
+
+        let request = BookSummaryRequest::default();
+        assert_eq!(request.filter(&summary), summary);
+
+        let request = BookSummaryRequest {
+            symbol: String::new(),
+            depth: 1,
+            exchanges: vec!["bitstamp".to_string()],
+            min_qty: 0.5,
+        };
+        let filtered = request.filter(&summary);
+        assert_eq!(filtered.bids, vec![level("bitstamp", 99.0, 2.0)]);
+        assert_eq!(filtered.asks, vec![level("bitstamp", 102.0, 1.0)]);
+        assert_eq!(filtered.spread, 3.0);
+        assert_eq!(filtered.sequence, 7);
+        assert_eq!(filtered.consolidated_bids[0].amount, 2.0);
+        assert_eq!(filtered.consolidated_bids[0].venues.len(), 1);
+
+        let request = BookSummaryRequest {
+            min_qty: 2.5,
+            ..Default::default()
+        };
+        let filtered = request.filter(&summary);
+        assert_eq!(filtered.bids.len(), 2);
+        assert_eq!(filtered.consolidated_bids.len(), 1);
+        assert!(filtered.asks.is_empty());
+        assert_eq!(filtered.spread, 0.0);
+    }
+
+    #[tokio::test]
+    async fn book_summary_venue_state_change() {
+        let (sender, receiver) = tokio::sync::watch::channel(Summary::default());
+        let publisher =
+            OrderbookAggregatorPublisher::new(HashMap::from([("BTC/USDT".to_string(), receiver)]));
+        let mut stream = publisher
+            .book_summary(Request::new(BookSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let venue = |state: State| VenueStatus {
+            exchange: "binance".to_string(),
+            state: state as i32,
+            ..Default::default()
+        };
+        // no book yet
+        sender
+            .send(Summary {
+                venues: vec![venue(State::Live)],
+                ..Default::default()
+            })
+            .unwrap();
+        tokio::task::yield_now().await;
+        sender
+            .send(Summary {
+                spread: 1.0,
+                bids: vec![level("binance", 100.0, 1.0)],
+                asks: vec![level("binance", 101.0, 1.0)],
+                venues: vec![venue(State::Live)],
+                ..Default::default()
+            })
+            .unwrap();
+        let summary = stream.next().await.unwrap().unwrap();
+        assert_eq!(summary.bids.len(), 1);
+
+        sender
+            .send(Summary {
+                venues: vec![venue(State::Stale)],
+                ..Default::default()
+            })
+            .unwrap();
+        let summary = stream.next().await.unwrap().unwrap();
+        assert!(summary.bids.is_empty() && summary.asks.is_empty());
+        assert_eq!(summary.venues[0].state(), State::Stale);
+    }
+
+    #[tokio::test]
+    async fn execution_estimate() {
+        let (_sender, receiver) = tokio::sync::watch::channel(Summary::default());
+        let (execution_sender, mut execution_receiver) =
+            tokio::sync::mpsc::channel::<ExecutionQuery>(1);
+        let publisher =
+            OrderbookAggregatorPublisher::new(HashMap::from([("BTC/USDT".to_string(), receiver)]))
+                .with_executions(HashMap::from([("BTC/USDT".to_string(), execution_sender)]));
+
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let mut aggregator = OrderBookAggregator::new(
+            IterativeMergeQuotes::new(3, 2),
+            2,
+            3,
+            exchanges_id_mapping.clone(),
+        );
+        let quote = |price, qty| ExchangeQuote { price, qty };
+        aggregator.process(OrderBookUpdate {
+            exchange_id: Some(0),
+            bid_changes: vec![quote(dec!(100), dec!(1))],
+            ask_changes: vec![quote(dec!(101), dec!(1)), quote(dec!(103), dec!(1))],
+            is_snapshot: true,
+            ..Default::default()
+        });
+        aggregator.process(OrderBookUpdate {
+            exchange_id: Some(1),
+            bid_changes: vec![quote(dec!(99), dec!(1))],
+            ask_changes: vec![quote(dec!(102), dec!(0.5))],
+            is_snapshot: true,
+            ..Default::default()
+        });
+        tokio::spawn(async move {
+            while let Some(query) = execution_receiver.recv().await {
+                let exchange_ids = query.request.exchange_ids(&exchanges_id_mapping);
+                let execution = aggregator.execution(
+                    query.request.execution_side(),
+                    query.amount,
+                    exchange_ids.as_deref(),
+                );
+                let _ = query.response.send(execution::execution_estimate(
+                    &execution,
+                    &exchanges_id_mapping,
+                ));
+            }
+        });
+
+        let estimate = publisher
+            .get_execution_estimate(Request::new(ExecutionRequest {
+                amount: Some(Amount::Qty(2.0)),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(estimate.qty, 2.0);
+        assert_eq!(estimate.vwap, 101.75);
+        assert_eq!(estimate.worst_price, 103.0);
+        assert_eq!(estimate.mid_price, 100.5);
+        assert_eq!(estimate.fills.len(), 2);
+        assert_eq!(estimate.fills[0].exchange, "binance");
+        assert_eq!(estimate.fills[0].qty, 1.5);
+        assert_eq!(estimate.fills[1].exchange, "bitstamp");
+        assert!(!estimate.depth_exhausted);
+
+        let estimate = publisher
+            .get_execution_estimate(Request::new(ExecutionRequest {
+                side: Side::Sell.into(),
+                amount: Some(Amount::Notional(150.0)),
+                exchanges: vec!["binance".to_string()],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(estimate.qty, 1.0);
+        // sold at 100 against the 100.5 mid
+        assert!((estimate.slippage_bps - 49.75).abs() < 0.01);
+        assert!(estimate.depth_exhausted);
+
+        let status = publisher
+            .get_execution_estimate(Request::new(ExecutionRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn route_order() {
+        let (_sender, receiver) = tokio::sync::watch::channel(Summary::default());
+        let (route_sender, mut route_receiver) = tokio::sync::mpsc::channel::<RouteQuery>(1);
+        let publisher =
+            OrderbookAggregatorPublisher::new(HashMap::from([("BTC/USDT".to_string(), receiver)]))
+                .with_routes(HashMap::from([("BTC/USDT".to_string(), route_sender)]));
+
+        let exchanges_id_mapping =
+            HashMap::from([(0, "binance".to_string()), (1, "bitstamp".to_string())]);
+        let mut aggregator = OrderBookAggregator::new(
+            IterativeMergeQuotes::new(3, 2),
+            2,
+            3,
+            exchanges_id_mapping.clone(),
+        );
+        for (exchange_id, price) in [(0, dec!(100)), (1, dec!(101))] {
+            aggregator.process(OrderBookUpdate {
+                exchange_id: Some(exchange_id),
+                ask_changes: vec![ExchangeQuote {
+                    price,
+                    qty: dec!(1),
+                }],
+                is_snapshot: true,
+                ..Default::default()
+            });
+        }
+        tokio::spawn(async move {
+            while let Some(query) = route_receiver.recv().await {
+                let parent = query.request.parent_order(&exchanges_id_mapping);
+                let plan = aggregator.route(&parent);
+                let _ = query
+                    .response
+                    .send(routing::order_route(&plan, &exchanges_id_mapping));
+            }
+        });
+
+        let route = publisher
+            .route_order(Request::new(RouteOrderRequest {
+                qty: 1.5,
+                venues: vec![VenueRules {
+                    exchange: "bitstamp".to_string(),
+                    lot_size: 0.2,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(route.child_orders.len(), 2);
+        assert_eq!(route.child_orders[0].exchange, "binance");
+        assert_eq!(route.child_orders[1].qty, 0.4);
+        assert_eq!(route.qty, 1.4);
+        assert_eq!(route.unfilled, 0.1);
+
+        let status = publisher
+            .route_order(Request::new(RouteOrderRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn paper_trading() {
+        let (_sender, receiver) = tokio::sync::watch::channel(Summary::default());
+        let (query_sender, mut query_receiver) = tokio::sync::mpsc::channel::<PaperQuery>(1);
+        let (fills, _) = tokio::sync::broadcast::channel(16);
+        let publisher =
+            OrderbookAggregatorPublisher::new(HashMap::from([("BTC/USDT".to_string(), receiver)]))
+                .with_paper(HashMap::from([(
+                    "BTC/USDT".to_string(),
+                    PaperChannels {
+                        queries: query_sender,
+                        fills: fills.clone(),
+                    },
+                )]));
+
+        let mut book = L2Book::new();
+        book.update_bid(dec!(99), dec!(2));
+        book.update_ask(dec!(100), dec!(1));
+        let books = vec![book];
+        let mut paper_trading = PaperTrading::new(
+            PaperEngine::new(),
+            HashMap::from([(0, "binance".to_string())]),
+            fills,
+        );
+        tokio::spawn(async move {
+            while let Some(query) = query_receiver.recv().await {
+                paper_trading.handle(query, &books, |_| false);
+            }
+        });
+
+        let mut fills = publisher
+            .paper_fills(Request::new(PaperAccountRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        let ack = publisher
+            .submit_paper_order(Request::new(PaperOrderRequest {
+                exchange: "binance".to_string(),
+                qty: 0.5,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(ack.fills.len(), 1);
+        assert_eq!(fills.next().await.unwrap().unwrap(), ack.fills[0]);
+
+        let ack = publisher
+            .submit_paper_order(Request::new(PaperOrderRequest {
+                exchange: "binance".to_string(),
+                side: Side::Sell.into(),
+                qty: 1.0,
+                limit_price: 100.0,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(ack.resting.as_ref().unwrap().queue_ahead, 1.0);
+
+        let account = publisher
+            .get_paper_account(Request::new(PaperAccountRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(account.positions[0].qty, 0.5);
+        assert_eq!(account.positions[0].unrealized_pnl, -0.25);
+        assert_eq!(account.orders.len(), 1);
+
+        let order = publisher
+            .cancel_paper_order(Request::new(CancelPaperOrderRequest {
+                order_id: ack.order_id,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(order.remaining, 1.0);
+
+        let status = publisher
+            .submit_paper_order(Request::new(PaperOrderRequest {
+                exchange: "kraken".to_string(),
+                qty: 1.0,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn paper_trading_stale_exchange() {
+        let (fills, mut fill_receiver) = tokio::sync::broadcast::channel(16);
+        let mut book = L2Book::new();
+        book.update_bid(dec!(99), dec!(2));
+        book.update_ask(dec!(100), dec!(1));
+        let mut books = vec![book];
+        let mut paper_trading = PaperTrading::new(
+            PaperEngine::new(),
+            HashMap::from([(0, "binance".to_string())]),
+            fills,
+        );
+        let submit = |paper_trading: &mut PaperTrading, books: &[L2Book], is_stale: bool| {
+            let (response, ack) = tokio::sync::oneshot::channel();
+            let request = PaperOrderRequest {
+                exchange: "binance".to_string(),
+                side: Side::Sell.into(),
+                qty: 1.0,
+                limit_price: 100.0,
+                ..Default::default()
+            };
+            paper_trading.handle(PaperQuery::Submit { request, response }, books, |_| {
+                is_stale
+            });
+            ack
+        };
+
+        let ack = submit(&mut paper_trading, &books, true).await.unwrap();
+        assert_eq!(ack.unwrap_err(), "exchange binance is stale");
+        let ack = submit(&mut paper_trading, &books, false).await.unwrap();
+        assert!(ack.unwrap().resting.is_some());
+
+        // crossing bid in the kept book of a stale exchange
+        books[0].update_bid(dec!(101), dec!(1));
+        paper_trading.on_book_update(0, &books, |_| true);
+        assert!(fill_receiver.try_recv().is_err());
+        paper_trading.on_book_update(0, &books, |_| false);
+        assert_eq!(fill_receiver.try_recv().unwrap().qty, 1.0);
+    }
+
+    #[tokio::test]
+    async fn book_snapshot() {
+        let (sender, receiver) = tokio::sync::watch::channel(Summary::default());
+        let publisher =
+            OrderbookAggregatorPublisher::new(HashMap::from([("BTC/USDT".to_string(), receiver)]));
+
+        let status = publisher
+            .get_book_snapshot(Request::new(BookSummaryRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        let status = publisher
+            .get_book_snapshot(Request::new(BookSummaryRequest {
+                symbol: "ETH/USDT".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        sender
+            .send(Summary {
+                spread: 1.0,
+                bids: vec![level("binance", 100.0, 1.0)],
+                asks: vec![level("binance", 101.0, 1.0)],
+                ..Default::default()
+            })
+            .unwrap();
+        let summary = publisher
+            .get_book_snapshot(Request::new(BookSummaryRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.spread, 1.0);
+    }
 }
@@ -0,0 +1,237 @@
+use crate::aggregation::paper::{self, Fill, Liquidity, PaperEngine, RestingOrder};
+use crate::common::model::Side;
+use crate::orderbook::book::L2Book;
+use crate::orderbook::execution_request::Side as RequestSide;
+use crate::orderbook::paper_fill::Liquidity as FillLiquidity;
+use crate::orderbook::{
+    PaperAccount, PaperFill, PaperOrder, PaperOrderAck, PaperOrderRequest, PaperPosition,
+};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use tokio::sync::{broadcast, oneshot};
+use tracing::info;
+
+/// Paper trading request of the gRPC server answered by the aggregation of the symbol,
+/// which owns exchange books.
+#[derive(Debug)]
+pub enum PaperQuery {
+    Submit {
+        request: PaperOrderRequest,
+        response: oneshot::Sender<Result<PaperOrderAck, String>>,
+    },
+    Cancel {
+        order_id: u64,
+        response: oneshot::Sender<Option<PaperOrder>>,
+    },
+    Account {
+        response: oneshot::Sender<PaperAccount>,
+    },
+}
+
+/// Paper trading queries and fills of one symbol.
+#[derive(Debug, Clone)]
+pub struct PaperChannels {
+    pub queries: tokio::sync::mpsc::Sender<PaperQuery>,
+    pub fills: broadcast::Sender<PaperFill>,
+}
+
+fn to_f64(val: Decimal) -> f64 {
+    val.to_f64().unwrap_or_default()
+}
+
+fn request_side(side: Side) -> RequestSide {
+    match side {
+        Side::Buy => RequestSide::Buy,
+        Side::Sell => RequestSide::Sell,
+    }
+}
+
+impl PaperOrderRequest {
+    /// Qty must be positive and limit price must not be negative.
+    pub fn is_valid(&self) -> bool {
+        self.qty > 0.0 && self.limit_price >= 0.0
+    }
+}
+
+/// Paper engine of a symbol with exchange names of the gRPC API.
+pub struct PaperTrading {
+    engine: PaperEngine,
+    exchanges_id_mapping: HashMap<usize, String>,
+    fills: broadcast::Sender<PaperFill>,
+}
+
+impl PaperTrading {
+    pub fn new(
+        engine: PaperEngine,
+        exchanges_id_mapping: HashMap<usize, String>,
+        fills: broadcast::Sender<PaperFill>,
+    ) -> Self {
+        Self {
+            engine,
+            exchanges_id_mapping,
+            fills,
+        }
+    }
+
+    /// Orders to exchanges for which `is_stale` is true are rejected, their kept books are out of
+    /// date.
+    pub fn handle(
+        &mut self,
+        query: PaperQuery,
+        books: &[L2Book],
+        is_stale: impl Fn(usize) -> bool,
+    ) {
+        // send errors mean the client is gone
+        match query {
+            PaperQuery::Submit { request, response } => {
+                let _ = response.send(self.submit(&request, books, is_stale));
+            }
+            PaperQuery::Cancel { order_id, response } => {
+                let order = self.engine.cancel(order_id);
+                let _ = response.send(order.map(|order| self.paper_order(&order)));
+            }
+            PaperQuery::Account { response } => {
+                let _ = response.send(self.account(books));
+            }
+        }
+    }
+
+    /// Fills resting orders of `exchange` after its book changed, a stale exchange fills nothing.
+    pub fn on_book_update(
+        &mut self,
+        exchange: usize,
+        books: &[L2Book],
+        is_stale: impl Fn(usize) -> bool,
+    ) {
+        if is_stale(exchange) {
+            return;
+        }
+        let book = match books.get(exchange) {
+            Some(val) => val,
+            None => return,
+        };
+        for fill in self.engine.on_book_update(exchange, book) {
+            self.publish(&fill);
+        }
+    }
+
+    fn submit(
+        &mut self,
+        request: &PaperOrderRequest,
+        books: &[L2Book],
+        is_stale: impl Fn(usize) -> bool,
+    ) -> Result<PaperOrderAck, String> {
+        let exchange = self
+            .exchanges_id_mapping
+            .iter()
+            .find(|(_, name)| **name == request.exchange)
+            .map(|(exchange_id, _)| *exchange_id)
+            .ok_or_else(|| format!("exchange {} is not aggregated", request.exchange))?;
+        if is_stale(exchange) {
+            return Err(format!("exchange {} is stale", request.exchange));
+        }
+        let side = match request.side() {
+            RequestSide::Buy => Side::Buy,
+            RequestSide::Sell => Side::Sell,
+        };
+        let decimal =
+            |val: f64| Decimal::from_f64(val).ok_or_else(|| format!("invalid number {}", val));
+        let qty = decimal(request.qty)?;
+        let order = if request.limit_price > 0.0 {
+            paper::PaperOrder::limit(exchange, side, decimal(request.limit_price)?, qty)
+        } else {
+            paper::PaperOrder::market(exchange, side, qty)
+        };
+
+        let ack = self.engine.submit(order, books)?;
+        info!("paper order. order={:?} ack={:?}", &order, &ack);
+        for fill in &ack.fills {
+            self.publish(fill);
+        }
+        Ok(PaperOrderAck {
+            order_id: ack.order_id,
+            fills: ack.fills.iter().map(|fill| self.paper_fill(fill)).collect(),
+            resting: ack.resting.as_ref().map(|order| self.paper_order(order)),
+            cancelled_qty: to_f64(ack.cancelled),
+        })
+    }
+
+    fn publish(&self, fill: &Fill) {
+        // error means there are no subscribers
+        let _ = self.fills.send(self.paper_fill(fill));
+    }
+
+    fn exchange_name(&self, exchange: usize) -> String {
+        self.exchanges_id_mapping
+            .get(&exchange)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn paper_fill(&self, fill: &Fill) -> PaperFill {
+        let liquidity = match fill.liquidity {
+            Liquidity::Maker => FillLiquidity::Maker,
+            Liquidity::Taker => FillLiquidity::Taker,
+        };
+        PaperFill {
+            order_id: fill.order_id,
+            exchange: self.exchange_name(fill.exchange),
+            side: request_side(fill.side).into(),
+            price: to_f64(fill.price),
+            qty: to_f64(fill.qty),
+            fee: to_f64(fill.fee),
+            liquidity: liquidity.into(),
+            time: fill.time,
+        }
+    }
+
+    fn paper_order(&self, order: &RestingOrder) -> PaperOrder {
+        PaperOrder {
+            order_id: order.order_id,
+            exchange: self.exchange_name(order.exchange),
+            side: request_side(order.side).into(),
+            price: to_f64(order.price),
+            qty: to_f64(order.qty),
+            remaining: to_f64(order.remaining),
+            queue_ahead: to_f64(order.queue_ahead),
+        }
+    }
+
+    /// Positions are marked to the mid price of exchange books.
+    fn account(&self, books: &[L2Book]) -> PaperAccount {
+        let mut account = PaperAccount {
+            orders: self
+                .engine
+                .orders()
+                .map(|order| self.paper_order(order))
+                .collect(),
+            ..Default::default()
+        };
+        let mut realized_pnl = Decimal::ZERO;
+        let mut unrealized_pnl = Decimal::ZERO;
+        let mut fees = Decimal::ZERO;
+        for (exchange, position) in self.engine.positions() {
+            let mark_price = books.get(*exchange).and_then(L2Book::mid_price);
+            let position_unrealized_pnl = mark_price
+                .map(|mark_price| position.unrealized_pnl(mark_price))
+                .unwrap_or_default();
+            realized_pnl += position.realized_pnl;
+            unrealized_pnl += position_unrealized_pnl;
+            fees += position.fees;
+            account.positions.push(PaperPosition {
+                exchange: self.exchange_name(*exchange),
+                qty: to_f64(position.qty),
+                avg_price: to_f64(position.avg_price),
+                realized_pnl: to_f64(position.realized_pnl),
+                unrealized_pnl: to_f64(position_unrealized_pnl),
+                mark_price: mark_price.map(to_f64).unwrap_or_default(),
+                fees: to_f64(position.fees),
+            });
+        }
+        account.realized_pnl = to_f64(realized_pnl);
+        account.unrealized_pnl = to_f64(unrealized_pnl);
+        account.fees = to_f64(fees);
+        account
+    }
+}
@@ -0,0 +1,94 @@
+use crate::aggregation::fees::FeeSchedule;
+use crate::aggregation::routing::{self, ParentOrder, RoutePlan};
+use crate::common::model::Side;
+use crate::orderbook::execution_request::Side as RequestSide;
+use crate::orderbook::{ChildOrder, OrderRoute, RouteOrderRequest};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use tokio::sync::oneshot;
+
+/// Route request of the gRPC server answered by the aggregation of the symbol,
+/// which owns exchange books.
+#[derive(Debug)]
+pub struct RouteQuery {
+    pub request: RouteOrderRequest,
+    pub response: oneshot::Sender<OrderRoute>,
+}
+
+fn decimal(val: f64) -> Decimal {
+    Decimal::from_f64(val).unwrap_or_default()
+}
+
+impl RouteOrderRequest {
+    /// Qty must be positive, limit price and venue sizes must not be negative.
+    pub fn is_valid(&self) -> bool {
+        self.qty > 0.0
+            && self.limit_price >= 0.0
+            && self.venues.iter().all(|venue| {
+                venue.taker_fee >= 0.0 && venue.min_size >= 0.0 && venue.lot_size >= 0.0
+            })
+    }
+
+    /// Unknown exchanges are ignored.
+    pub fn parent_order(&self, exchanges_id_mapping: &HashMap<usize, String>) -> ParentOrder {
+        let exchange_ids: HashMap<&str, usize> = exchanges_id_mapping
+            .iter()
+            .map(|(exchange_id, exchange)| (exchange.as_str(), *exchange_id))
+            .collect();
+        let side = match self.side() {
+            RequestSide::Buy => Side::Buy,
+            RequestSide::Sell => Side::Sell,
+        };
+
+        let mut parent = ParentOrder::new(side, decimal(self.qty));
+        if self.limit_price > 0.0 {
+            parent = parent.with_limit_price(decimal(self.limit_price));
+        }
+        for venue in &self.venues {
+            if let Some(exchange_id) = exchange_ids.get(venue.exchange.as_str()) {
+                let rules = routing::VenueRules::new(
+                    FeeSchedule::new(Decimal::ZERO, decimal(venue.taker_fee)),
+                    decimal(venue.min_size),
+                    decimal(venue.lot_size),
+                );
+                parent = parent.with_venue(*exchange_id, rules);
+            }
+        }
+        if !self.exchanges.is_empty() {
+            let exchanges = self
+                .exchanges
+                .iter()
+                .filter_map(|exchange| exchange_ids.get(exchange.as_str()).copied())
+                .collect();
+            parent = parent.with_exchanges(exchanges);
+        }
+        parent
+    }
+}
+
+pub fn order_route(plan: &RoutePlan, exchanges_id_mapping: &HashMap<usize, String>) -> OrderRoute {
+    let to_f64 = |val: Decimal| val.to_f64().unwrap_or_default();
+    OrderRoute {
+        child_orders: plan
+            .child_orders
+            .iter()
+            .map(|order| ChildOrder {
+                exchange: exchanges_id_mapping
+                    .get(&order.exchange)
+                    .cloned()
+                    .unwrap_or_default(),
+                qty: to_f64(order.qty),
+                limit_price: to_f64(order.limit_price),
+                notional: to_f64(order.notional),
+                fees: to_f64(order.fees),
+                vwap: to_f64(order.vwap()),
+            })
+            .collect(),
+        qty: to_f64(plan.qty),
+        notional: to_f64(plan.notional),
+        fees: to_f64(plan.fees),
+        vwap: plan.vwap().map(to_f64).unwrap_or_default(),
+        unfilled: to_f64(plan.unfilled),
+    }
+}